@@ -15,9 +15,9 @@
 
 use std::rc::Rc;
 
-use bellframe::SameStageVec;
+use bellframe::{Row, SameStageVec};
 use emath::Pos2;
-use jigsaw_utils::indexed_vec::{PartVec, RowVec};
+use jigsaw_utils::indexed_vec::{PartIdx, PartVec, RowVec};
 
 use crate::spec::{self, part_heads::PartHeads};
 
@@ -26,7 +26,7 @@ pub(crate) struct ExpandedFrag {
     pub position: Pos2,
     /// Each of these contains all the [`Row`]s of the source [`Fragment`] (including the leftover)
     /// for each part in the expanded composition
-    pub rows_per_part: PartVec<SameStageVec>,
+    pub rows_per_part: Rc<PartVec<SameStageVec>>,
     /// Stores data for each [`Row`] which is independent of which part is being seen
     pub row_data: RowVec<RowData>,
     /// `false` if the source [`Fragment`] is muted
@@ -50,12 +50,22 @@ impl ExpandedFrag {
             .collect();
         Self {
             position,
-            rows_per_part,
+            rows_per_part: Rc::new(rows_per_part),
             row_data,
             is_proved,
         }
     }
 
+    /// The first [`Row`] of this [`ExpandedFrag`] in the first part of the composition
+    pub(crate) fn first_row(&self) -> &Row {
+        self.rows_per_part[PartIdx::new(0)].first().unwrap()
+    }
+
+    /// The leftover [`Row`] of this [`ExpandedFrag`] in the first part of the composition
+    pub(crate) fn leftover_row(&self) -> &Row {
+        self.rows_per_part[PartIdx::new(0)].last().unwrap()
+    }
+
     /// The number of proved [`Row`]s in this [`ExpandedFrag`] in one part of the composition.
     pub(crate) fn len(&self) -> usize {
         if self.is_proved {
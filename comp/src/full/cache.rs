@@ -0,0 +1,91 @@
+//! Caching of intermediate results, so that [`FullState::update`] only has to recompute the parts
+//! of the composition which have changed.
+
+// This lint gives false positives for raw pointers (which are hashed by the memory address they
+// point to).  See https://github.com/rust-lang/rust-clippy/issues/6745
+#![allow(clippy::mutable_key_type)]
+
+use std::{collections::HashMap, rc::Rc};
+
+use jigsaw_utils::indexed_vec::FragVec;
+
+use crate::{
+    expanded_frag::ExpandedFrag,
+    music::Music,
+    spec::{self, part_heads::PartHeads, CompSpec},
+};
+
+use super::from_expanded_frags::FragMusic;
+
+// Imports only used for doc comments
+#[allow(unused_imports)]
+use super::FullState;
+
+/// The intermediate results of building a [`FullState`] from a [`CompSpec`].  Every
+/// [`spec::Fragment`] is stored in an `Rc`, so any fragment which is unchanged between two
+/// [`CompSpec`]s will have the same address (which we check with [`Rc::ptr_eq`]).
+#[derive(Debug, Clone)]
+pub(super) struct Cache {
+    part_heads: Rc<PartHeads>,
+    music: Rc<Vec<Music>>,
+    pub(super) frags: FragVec<CachedFrag>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct CachedFrag {
+    /// The [`spec::Fragment`] which generated this data.  As well as identifying the fragment,
+    /// this keeps the source alive so that its address can't be reused by a different fragment.
+    pub(super) source: Rc<spec::Fragment>,
+    pub(super) expanded: Rc<ExpandedFrag>,
+    pub(super) music: Rc<FragMusic>,
+}
+
+impl Cache {
+    /// Expands every fragment in `spec`, reusing the results from `old_cache` for any fragments
+    /// which haven't changed.
+    pub(super) fn new(spec: &CompSpec, old_cache: Option<&Cache>) -> Self {
+        let part_heads = spec.part_heads();
+        let music = spec.music();
+
+        // Expanded fragments can only be reused if the part heads haven't changed, and their
+        // music can only be reused if the music definitions haven't changed either
+        let (reusable_frags, can_reuse_music) = match old_cache {
+            Some(cache) if Rc::ptr_eq(&cache.part_heads, part_heads) => {
+                let frags_by_address = cache
+                    .frags
+                    .iter()
+                    .map(|f| (f.source.as_ref() as *const spec::Fragment, f))
+                    .collect::<HashMap<_, _>>();
+                (frags_by_address, Rc::ptr_eq(&cache.music, music))
+            }
+            _ => (HashMap::new(), false),
+        };
+
+        let frags = spec
+            .fragments()
+            .iter()
+            .map(|frag| {
+                let cached = reusable_frags.get(&(frag.as_ref() as *const spec::Fragment));
+                let expanded = match cached {
+                    Some(c) => c.expanded.clone(),
+                    None => Rc::new(frag.expand(part_heads)),
+                };
+                let frag_music = match cached {
+                    Some(c) if can_reuse_music => c.music.clone(),
+                    _ => Rc::new(FragMusic::new(&expanded, music, spec.stage())),
+                };
+                CachedFrag {
+                    source: frag.clone(),
+                    expanded,
+                    music: frag_music,
+                }
+            })
+            .collect();
+
+        Self {
+            part_heads: part_heads.clone(),
+            music: music.clone(),
+            frags,
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Storage for which leads of a composition are folded.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+};
+
+use jigsaw_utils::indexed_vec::FragIdx;
+
+use crate::spec::{self, CompSpec, EditError, FragId};
+
+// Imports only used for doc comments
+#[allow(unused_imports)]
+use super::FullState;
+
+/// The leads of each [`Fragment`](spec::Fragment) which are folded into a single display row.
+/// Folding only changes how the composition is displayed, so `Folds` are kept outside the
+/// [`CompSpec`] (and therefore out of the undo history) and passed to [`FullState::update`].
+///
+/// Folds are stored by the ID of their fragment, which survives undo and redo.  Splitting,
+/// joining or duplicating fragments creates fragments with new IDs, so the folds have to be
+/// copied onto the new fragments.  The folds of fragments which no longer exist are kept, in case
+/// the fragment is brought back by an undo.  Any fold which doesn't start a plain lead of its
+/// fragment (e.g. because the lead was changed by an undo) is ignored.
+#[derive(Debug, Clone, Default)]
+pub struct Folds {
+    /// For each fragment, the index of the first row of every folded lead
+    lead_heads: HashMap<FragId, BTreeSet<usize>>,
+}
+
+impl Folds {
+    /// Folds or unfolds the plain lead containing a given row of a [`Fragment`](spec::Fragment)
+    pub fn toggle(
+        &mut self,
+        spec: &CompSpec,
+        frag_idx: FragIdx,
+        row_idx: isize,
+    ) -> Result<(), EditError> {
+        let lead = spec.plain_lead_containing(frag_idx, row_idx)?;
+        let frag_id = spec.fragments()[frag_idx].id();
+        let lead_heads = self.lead_heads.entry(frag_id).or_default();
+        if !lead_heads.remove(&lead.start) {
+            lead_heads.insert(lead.start);
+        }
+        Ok(())
+    }
+
+    /// Copies the folds onto the fragments created when [`CompSpec::split_fragment`] splits
+    /// `frag_idx` of `old_spec` at `split_idx` (producing `new_spec`).  Folds below the split move
+    /// to the new fragment.
+    pub fn split_fragment(
+        &mut self,
+        old_spec: &CompSpec,
+        new_spec: &CompSpec,
+        frag_idx: FragIdx,
+        split_idx: isize,
+    ) {
+        let split_idx = split_idx.max(0) as usize;
+        let mut top_folds = self.get(old_spec, frag_idx);
+        let bottom_folds = top_folds.split_off(&split_idx);
+        // The new fragment is added to the end of the list
+        let new_frag_idx = new_spec.fragments().last_idx();
+        self.set(new_spec, frag_idx, top_folds);
+        self.set(
+            new_spec,
+            new_frag_idx,
+            bottom_folds.into_iter().map(|i| i - split_idx).collect(),
+        );
+    }
+
+    /// Copies the folds onto the fragment created when [`CompSpec::join_fragments`] joins `bottom`
+    /// onto `top` in `old_spec` (producing `new_spec`)
+    pub fn join_fragments(
+        &mut self,
+        old_spec: &CompSpec,
+        new_spec: &CompSpec,
+        top: FragIdx,
+        bottom: FragIdx,
+    ) {
+        let top_len = match old_spec.fragments().get(top) {
+            Some(f) => f.len(),
+            None => return,
+        };
+        // The folds of `bottom` now start `top_len` rows further down
+        let mut folds = self.get(old_spec, top);
+        folds.extend(self.get(old_spec, bottom).into_iter().map(|i| i + top_len));
+        let joined_idx = if bottom < top { top - 1 } else { top };
+        self.set(new_spec, joined_idx, folds);
+    }
+
+    /// Copies the folds onto the fragment created when [`CompSpec::duplicate_fragment`] copies
+    /// `frag_idx` to create `new_frag_idx`.  The copy is folded in the same way as the original.
+    pub fn duplicate_fragment(
+        &mut self,
+        spec: &CompSpec,
+        frag_idx: FragIdx,
+        new_frag_idx: FragIdx,
+    ) {
+        let folds = self.get(spec, frag_idx);
+        self.set(spec, new_frag_idx, folds);
+    }
+
+    /// The ranges of row indices covered by each folded lead of a [`Fragment`](spec::Fragment),
+    /// in order
+    pub(crate) fn folded_leads(&self, frag: &spec::Fragment) -> Vec<Range<usize>> {
+        match self.lead_heads.get(&frag.id()) {
+            Some(lead_heads) => frag.folded_leads(lead_heads),
+            None => Vec::new(),
+        }
+    }
+
+    fn get(&self, spec: &CompSpec, frag_idx: FragIdx) -> BTreeSet<usize> {
+        (spec.fragments().get(frag_idx))
+            .and_then(|f| self.lead_heads.get(&f.id()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn set(&mut self, spec: &CompSpec, frag_idx: FragIdx, folds: BTreeSet<usize>) {
+        if let Some(f) = spec.fragments().get(frag_idx) {
+            self.lead_heads.insert(f.id(), folds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use emath::Pos2;
+    use jigsaw_utils::indexed_vec::{FragIdx, RowIdx};
+
+    use crate::{full::FullState, spec::CompSpec, History};
+
+    use super::Folds;
+
+    /// Folds are kept when edits are undone and redone
+    #[test]
+    fn not_in_undo_history() {
+        let frag_idx = FragIdx::new(0);
+        let mut history = History::new(CompSpec::example());
+        let mut folds = Folds::default();
+        let mut full_state = FullState::new(history.comp_spec());
+        let num_rows = full_state.fragments[frag_idx].num_display_rows();
+
+        // Fold the first lead (of Deva, which has 32 rows)
+        folds.toggle(history.comp_spec(), frag_idx, 5).unwrap();
+        full_state.update(history.comp_spec(), &folds);
+        assert_eq!(
+            full_state.fragments[frag_idx].num_display_rows(),
+            num_rows - 31
+        );
+        // Make an edit and undo it.  The lead should still be folded.
+        let new_pos = Pos2::new(0.0, 0.0);
+        (history.apply_edit(|spec| spec.move_fragment(frag_idx, new_pos))).unwrap();
+        assert!(history.undo());
+        full_state.update(history.comp_spec(), &folds);
+        assert_eq!(
+            full_state.fragments[frag_idx].num_display_rows(),
+            num_rows - 31
+        );
+        // Unfolding and redoing shouldn't bring the fold back
+        folds.toggle(history.comp_spec(), frag_idx, 0).unwrap();
+        assert!(history.redo());
+        full_state.update(history.comp_spec(), &folds);
+        assert_eq!(full_state.fragments[frag_idx].num_display_rows(), num_rows);
+    }
+
+    /// Undoing a deletion brings back the deleted fragment's folds, and doesn't move the folds of
+    /// any other fragment
+    #[test]
+    fn delete_and_undo() {
+        let mut history = History::new(CompSpec::example());
+        let mut folds = Folds::default();
+        // Copy the fragment, then fold the first lead of the original and the second of the copy
+        let copy_idx = (history
+            .apply_edit(|spec| spec.duplicate_fragment(FragIdx::new(0), Pos2::new(0.0, 0.0))))
+        .unwrap();
+        folds
+            .toggle(history.comp_spec(), FragIdx::new(0), 0)
+            .unwrap();
+        folds.toggle(history.comp_spec(), copy_idx, 32).unwrap();
+        let folded_leads = |history: &History, folds: &Folds| {
+            let mut full_state = FullState::new(history.comp_spec());
+            full_state.update(history.comp_spec(), folds);
+            (full_state.fragments.iter())
+                .map(|f| {
+                    (
+                        f.folded_lead_len(RowIdx::new(0)),
+                        f.is_hidden(RowIdx::new(40)),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            folded_leads(&history, &folds),
+            [(Some(32), false), (None, true)]
+        );
+
+        // Deleting the original leaves the copy folded as before ...
+        (history.apply_edit(|spec| spec.delete_fragment(FragIdx::new(0)))).unwrap();
+        assert_eq!(folded_leads(&history, &folds), [(None, true)]);
+        // ... and undoing the deletion restores the original's folds
+        assert!(history.undo());
+        assert_eq!(
+            folded_leads(&history, &folds),
+            [(Some(32), false), (None, true)]
+        );
+    }
+
+    /// Folds move with their leads when fragments are split and joined, and are restored when
+    /// those edits are undone
+    #[test]
+    fn split_and_join() {
+        let mut history = History::new(CompSpec::example());
+        let mut folds = Folds::default();
+        // Fold the second lead (of Yorkshire, which starts at row 32)
+        folds
+            .toggle(history.comp_spec(), FragIdx::new(0), 40)
+            .unwrap();
+        let unsplit_spec = history.comp_spec().clone();
+
+        // Split between the two leads
+        (history.apply_edit(|spec| spec.split_fragment(FragIdx::new(0), 32, Pos2::new(0.0, 0.0))))
+            .unwrap();
+        folds.split_fragment(&unsplit_spec, history.comp_spec(), FragIdx::new(0), 32);
+        let mut full_state = FullState::new(history.comp_spec());
+        full_state.update(history.comp_spec(), &folds);
+        let bottom = &full_state.fragments[FragIdx::new(1)];
+        assert_eq!(bottom.folded_lead_len(RowIdx::new(0)), Some(32));
+        assert_eq!(full_state.fragments[FragIdx::new(0)].num_display_rows(), 33);
+
+        // Join them back together
+        let split_spec = history.comp_spec().clone();
+        (history.apply_edit(|spec| spec.join_fragments(FragIdx::new(0), FragIdx::new(1)))).unwrap();
+        folds.join_fragments(
+            &split_spec,
+            history.comp_spec(),
+            FragIdx::new(0),
+            FragIdx::new(1),
+        );
+        full_state.update(history.comp_spec(), &folds);
+        let joined = &full_state.fragments[FragIdx::new(0)];
+        assert_eq!(joined.folded_lead_len(RowIdx::new(32)), Some(32));
+        assert!(joined.is_hidden(RowIdx::new(40)));
+
+        // Undoing both edits gives back the folds of the split and unsplit fragments
+        assert!(history.undo());
+        full_state.update(history.comp_spec(), &folds);
+        let bottom = &full_state.fragments[FragIdx::new(1)];
+        assert_eq!(bottom.folded_lead_len(RowIdx::new(0)), Some(32));
+        assert!(history.undo());
+        full_state.update(history.comp_spec(), &folds);
+        let unsplit = &full_state.fragments[FragIdx::new(0)];
+        assert_eq!(unsplit.folded_lead_len(RowIdx::new(32)), Some(32));
+    }
+}
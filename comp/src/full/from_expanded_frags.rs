@@ -4,11 +4,17 @@
 // point to).  See https://github.com/rust-lang/rust-clippy/issues/6745
 #![allow(clippy::mutable_key_type)]
 
-use std::{collections::HashMap, rc::Rc};
+use std::{collections::HashMap, ops::Range, rc::Rc};
 
-use bellframe::Stage;
+use bellframe::{Bell, Stage};
 use itertools::Itertools;
-use jigsaw_utils::indexed_vec::{FragSlice, FragVec, MethodIdx, MethodSlice, MethodVec, RowVec};
+use jigsaw_utils::{
+    indexed_vec::{
+        CallIdx, CallSlice, CallVec, FragSlice, FragVec, MethodIdx, MethodSlice, MethodVec,
+        PartVec, RowVec,
+    },
+    types::Stroke,
+};
 
 use crate::{
     expanded_frag::ExpandedFrag,
@@ -16,37 +22,79 @@ use crate::{
     spec::{self, part_heads::PartHeads},
 };
 
-use super::{FullState, Stats};
+use super::{cache::Cache, Folds, FullState, Stats};
+
+pub(super) use music_gen::FragMusic;
 
 pub(super) fn from_expanded_frags(
-    expanded_frags: FragVec<ExpandedFrag>,
+    cache: Cache,
+    folds: &Folds,
     spec_methods: &MethodSlice<Rc<spec::Method>>,
+    spec_calls: &CallSlice<Rc<spec::Call>>,
     part_heads: Rc<PartHeads>,
     music: &[music::Music],
     stage: Stage,
 ) -> FullState {
+    let expanded_frags: FragVec<_> = cache.frags.iter().map(|f| f.expanded.clone()).collect();
+    let frag_musics: FragVec<_> = cache.frags.iter().map(|f| f.music.clone()).collect();
     let (method_map, methods) = expand_methods(spec_methods, &expanded_frags, part_heads.len());
-    let stats = generate_stats(&expanded_frags);
-    let (music, frag_musics) = music_gen::compute_music(music, &expanded_frags, stage);
+    let (call_map, calls) = expand_calls(spec_calls, &expanded_frags, part_heads.len());
+    let (false_row_groups, frag_falseness, num_false_rows, num_false_groups) =
+        falseness::compute_falseness(&expanded_frags);
+    let (frag_links, frag_link_groups) = links::gen_frag_links(&expanded_frags, &part_heads);
+    let is_round_block = links::is_round_block(&expanded_frags, &frag_links, &part_heads);
+    let stats = generate_stats(
+        &expanded_frags,
+        num_false_rows,
+        num_false_groups,
+        is_round_block,
+    );
+    let first_strokes = links::first_row_strokes(&expanded_frags, &frag_links, part_heads.len());
+    let music =
+        music_gen::compute_music(music, &frag_musics, &first_strokes, part_heads.len(), stage);
+    // Folds aren't part of the `CompSpec`, so aren't cached with the expanded fragments
     let fragments = expanded_frags
-        .into_iter()
-        .zip(frag_musics)
-        .map(|(exp_frag, music)| expand_frag(exp_frag, music, &method_map, &methods))
+        .iter()
+        .zip_eq(&frag_musics)
+        .zip_eq(frag_falseness)
+        .zip_eq(frag_link_groups)
+        .zip_eq(&cache.frags)
+        .zip_eq(first_strokes)
+        .map(
+            |(((((exp_frag, music), falseness), link_groups), cached), first_strokes)| {
+                expand_frag(
+                    exp_frag,
+                    music.highlights(&first_strokes, stage),
+                    first_strokes,
+                    falseness,
+                    link_groups,
+                    folds.folded_leads(&cached.source),
+                    &method_map,
+                    &methods,
+                    &call_map,
+                    &calls,
+                )
+            },
+        )
         .collect();
 
     FullState {
         part_heads,
         fragments,
         methods,
+        calls,
         music,
+        false_row_groups,
+        frag_links,
         stats,
         stage,
+        cache,
     }
 }
 
 fn expand_methods(
     methods: &MethodSlice<Rc<spec::Method>>,
-    frags: &FragSlice<ExpandedFrag>,
+    frags: &FragSlice<Rc<ExpandedFrag>>,
     num_parts: usize,
 ) -> (
     HashMap<*const spec::Method, MethodIdx>,
@@ -94,10 +142,470 @@ fn expand_methods(
     (method_map, methods)
 }
 
-fn generate_stats(frags: &FragSlice<ExpandedFrag>) -> Stats {
+fn expand_calls(
+    calls: &CallSlice<Rc<spec::Call>>,
+    frags: &FragSlice<Rc<ExpandedFrag>>,
+    num_parts: usize,
+) -> (HashMap<*const spec::Call, CallIdx>, CallVec<Rc<full::Call>>) {
+    // Maps source calls (hashed by their memory addresses) to the index of the corresponding
+    // `full::Call`, in the same way as `expand_methods`
+    let call_map = calls
+        .iter_enumerated()
+        .map(|(idx, c)| (c.as_ref() as *const spec::Call, idx))
+        .collect::<HashMap<_, _>>();
+    let mut calls = calls
+        .iter()
+        .map(|c| full::Call {
+            source: c.clone(),
+            // Will be accumulated later
+            num_uses: 0,
+            num_proved_uses: 0,
+        })
+        .collect::<CallVec<_>>();
+
+    // Count the uses of each call.  Each call is only counted on its first row, and (like the
+    // method row counts) each use is counted once per part.
+    for f in frags {
+        for row_data in &f.row_data {
+            if let Some((spec_call, 0)) = &row_data.call_source {
+                let full_call = &mut calls[call_map[&(spec_call.as_ref() as *const spec::Call)]];
+                full_call.num_uses += num_parts;
+                if row_data.is_proved {
+                    full_call.num_proved_uses += num_parts;
+                }
+            }
+        }
+    }
+
+    let calls = calls.into_iter().map(Rc::new).collect();
+    (call_map, calls)
+}
+
+fn generate_stats(
+    frags: &FragSlice<Rc<ExpandedFrag>>,
+    num_false_rows: usize,
+    num_false_groups: usize,
+    is_round_block: bool,
+) -> Stats {
     // The total length of a part is the sum of the lengths of fragments
     let part_len = frags.iter().map(|f| f.len()).sum();
-    Stats { part_len }
+    Stats {
+        part_len,
+        num_false_rows,
+        num_false_groups,
+        is_round_block,
+    }
+}
+
+///////////
+// LINKS //
+///////////
+
+mod links {
+    use std::{collections::HashMap, rc::Rc};
+
+    use bellframe::{Row, RowBuf, SameStageVec};
+    use index_vec::index_vec;
+    use itertools::Itertools;
+    use jigsaw_utils::{
+        indexed_vec::{FragIdx, FragSlice, FragVec, PartIdx, PartVec},
+        types::Stroke,
+    };
+
+    use crate::{expanded_frag::ExpandedFrag, full, spec::part_heads::PartHeads};
+
+    /// The link groups of the top and bottom of a single [`Fragment`]
+    #[derive(Debug, Clone, Copy, Default)]
+    pub(super) struct FragLinkGroups {
+        pub(super) top: Option<usize>,
+        pub(super) bottom: Option<usize>,
+    }
+
+    /// Determine which [`Fragment`]s can be linked onto the end of which other [`Fragment`]s.
+    ///
+    /// Links onto and from muted [`Fragment`]s are included (and get link markers), since muted
+    /// [`Fragment`]s can still be joined to others.  So are links from a [`Fragment`] onto
+    /// itself, which show that the [`Fragment`] comes round on its own.
+    pub(super) fn gen_frag_links(
+        expanded_frags: &FragSlice<Rc<ExpandedFrag>>,
+        part_heads: &PartHeads,
+    ) -> (Vec<full::FragLink>, FragVec<FragLinkGroups>) {
+        // A map to determine which group ID should be assigned to each linked row.  This way,
+        // interconnected groups of links are given the same colour.  Rows are keyed by
+        // `canonical_row`, so links through rows which are only equivalent under the part heads
+        // are also given the same colour.
+        let mut link_groups = HashMap::<&Row, usize>::new();
+        let mut frag_links = Vec::new();
+        let mut frag_link_groups = index_vec![FragLinkGroups::default(); expanded_frags.len()];
+
+        // Test every pair of frags f -> g ...
+        for (f_idx, f) in expanded_frags.iter_enumerated() {
+            for (g_idx, g) in expanded_frags.iter_enumerated() {
+                // ... if `g` starts with the leftover row of `f`, then f -> g
+                let leftover_row_of_f = f.leftover_row();
+                if part_heads
+                    .are_equivalent(leftover_row_of_f, g.first_row())
+                    .unwrap()
+                {
+                    // Equivalent rows have the same canonical row, so every link from `f` (and
+                    // every link onto `g`) is put in the same group
+                    let row = canonical_row(f, SameStageVec::last);
+                    debug_assert_eq!(row, canonical_row(g, SameStageVec::first));
+                    let next_group = link_groups.len();
+                    let group = *link_groups.entry(row).or_insert(next_group);
+                    frag_links.push(full::FragLink {
+                        from: f_idx,
+                        to: g_idx,
+                        group,
+                    });
+                    frag_link_groups[f_idx].bottom = Some(group);
+                    frag_link_groups[g_idx].top = Some(group);
+                }
+            }
+        }
+        (frag_links, frag_link_groups)
+    }
+
+    /// The lowest value which some [`Row`] of a [`Fragment`] (given by `get_row`) takes in any
+    /// part.  If two [`Row`]s are equivalent under the part heads, then they take the same values
+    /// across all the parts (just in a different order), so this is the same for every [`Row`]
+    /// which is equivalent to the given one.
+    fn canonical_row<'f>(
+        frag: &'f ExpandedFrag,
+        get_row: impl Fn(&'f SameStageVec) -> Option<&'f Row>,
+    ) -> &'f Row {
+        (frag.rows_per_part.iter())
+            .map(|rows| get_row(rows).unwrap())
+            .min()
+            .unwrap() // Every composition has at least one part
+    }
+
+    /// Determine the stroke of the first [`Row`] of every [`Fragment`], in every part.  Strokes are
+    /// passed along the links from proved [`Fragment`]s into whichever part the linked [`Row`]
+    /// lands in, and a [`Fragment`] which isn't linked onto the end of any other starts at
+    /// handstroke in the first part.  Any parts which can't be reached along links are assumed to
+    /// follow on from the previous part of the same block of linked [`Fragment`]s, so blocks of
+    /// odd length alternate strokes between parts.
+    pub(super) fn first_row_strokes(
+        expanded_frags: &FragSlice<Rc<ExpandedFrag>>,
+        frag_links: &[full::FragLink],
+        num_parts: usize,
+    ) -> FragVec<PartVec<Stroke>> {
+        // Muted fragments aren't rung, so don't pass their strokes on to other fragments
+        let links = (frag_links.iter())
+            .filter(|l| expanded_frags[l.from].is_proved)
+            .collect::<Vec<_>>();
+        let mut first_strokes: FragVec<PartVec<Option<Stroke>>> =
+            index_vec![index_vec![None; num_parts]; expanded_frags.len()];
+        // For each fragment, the number of proved rows in the block of linked fragments which
+        // first reached it
+        let mut block_lens: FragVec<Option<usize>> = index_vec![None; expanded_frags.len()];
+
+        // Start at the fragments which nothing else links onto.  Any fragments left over after
+        // that must be in cycles of links, so they are started at the first fragment in the cycle.
+        let is_linked_onto = |idx: FragIdx| links.iter().any(|l| l.to == idx && l.from != idx);
+        let starts = (expanded_frags.indices())
+            .filter(|idx| !is_linked_onto(*idx))
+            .chain(expanded_frags.indices());
+        for start in starts {
+            if block_lens[start].is_some() {
+                continue;
+            }
+            first_strokes[start][PartIdx::new(0)] = Some(Stroke::Hand);
+            let mut block = vec![start];
+            // Propagate the stroke along the links, depth first.  A fragment can be reached in
+            // several parts (e.g. if the block comes round to a part head), but fragments which
+            // were reached by an earlier block are left alone.
+            let mut frags_to_visit = vec![(start, PartIdx::new(0))];
+            while let Some((from, part)) = frags_to_visit.pop() {
+                // The leftover row (i.e. the last row) of `from` is the first row of the
+                // fragments which it links to, in whichever part contains that row
+                let from_frag = &expanded_frags[from];
+                let leftover_row = from_frag.rows_per_part[part].last().unwrap();
+                let num_rows = from_frag.row_data.len();
+                let leftover_stroke = first_strokes[from][part].unwrap().offset(num_rows - 1);
+                for link in links.iter().filter(|l| l.from == from) {
+                    if block_lens[link.to].is_some() {
+                        continue; // Reached by an earlier block
+                    }
+                    let to_part = (expanded_frags[link.to].rows_per_part)
+                        .position(|rows| rows.first() == Some(leftover_row))
+                        .unwrap(); // Linked rows are equivalent, so appear in some part
+                    if first_strokes[link.to][to_part].is_none() {
+                        first_strokes[link.to][to_part] = Some(leftover_stroke);
+                        if !block.contains(&link.to) {
+                            block.push(link.to);
+                        }
+                        frags_to_visit.push((link.to, to_part));
+                    }
+                }
+            }
+            let block_len = block.iter().map(|idx| expanded_frags[*idx].len()).sum();
+            for idx in block {
+                block_lens[idx] = Some(block_len);
+            }
+        }
+
+        first_strokes
+            .into_iter()
+            .zip_eq(block_lens)
+            .map(|(strokes, block_len)| {
+                let block_len = block_len.unwrap(); // Every fragment is in some block
+
+                // Fill in the parts which weren't reached, counting on from the first part which
+                // was
+                let (reached_part, reached_stroke) = (strokes.iter_enumerated())
+                    .find_map(|(part, stroke)| Some((part, (*stroke)?)))
+                    .unwrap();
+                (strokes.iter_enumerated())
+                    .map(|(part, stroke)| {
+                        stroke.unwrap_or_else(|| {
+                            let parts_after =
+                                (part.index() + num_parts - reached_part.index()) % num_parts;
+                            reached_stroke.offset(parts_after * block_len)
+                        })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Returns `true` if the proved [`Fragment`]s can be linked, each exactly once, into a single
+    /// block which starts and finishes at rounds
+    pub(super) fn is_round_block(
+        expanded_frags: &FragSlice<Rc<ExpandedFrag>>,
+        frag_links: &[full::FragLink],
+        part_heads: &PartHeads,
+    ) -> bool {
+        let num_proved_frags = expanded_frags.iter().filter(|f| f.is_proved).count();
+        if num_proved_frags == 0 {
+            return false; // An empty composition doesn't count as a round block
+        }
+
+        let rounds = RowBuf::rounds(part_heads.stage());
+        let search = BlockSearch {
+            expanded_frags,
+            frag_links,
+            num_proved_frags,
+            is_rounds: &|r: &Row| part_heads.are_equivalent(r, &rounds).unwrap(),
+        };
+        let mut is_visited = index_vec![false; expanded_frags.len()];
+        // Try starting the block at every proved fragment which starts at rounds
+        expanded_frags
+            .iter_enumerated()
+            .filter(|(_, f)| f.is_proved && (search.is_rounds)(f.first_row()))
+            .any(|(start_idx, _)| search.visit(start_idx, 1, &mut is_visited))
+    }
+
+    /// Depth-first search for a chain of linked proved [`Fragment`]s which uses every proved
+    /// [`Fragment`] and finishes at rounds.  Compositions only contain a handful of
+    /// [`Fragment`]s, so an exhaustive search is fast enough.
+    struct BlockSearch<'a> {
+        expanded_frags: &'a FragSlice<Rc<ExpandedFrag>>,
+        frag_links: &'a [full::FragLink],
+        num_proved_frags: usize,
+        is_rounds: &'a dyn Fn(&Row) -> bool,
+    }
+
+    impl BlockSearch<'_> {
+        fn visit(
+            &self,
+            frag_idx: FragIdx,
+            num_visited: usize,
+            is_visited: &mut FragVec<bool>,
+        ) -> bool {
+            if num_visited == self.num_proved_frags {
+                // Every fragment has been used, so the block is round iff it finishes at rounds
+                return (self.is_rounds)(self.expanded_frags[frag_idx].leftover_row());
+            }
+            is_visited[frag_idx] = true;
+            let is_round = self.frag_links.iter().any(|link| {
+                link.from == frag_idx
+                    && self.expanded_frags[link.to].is_proved
+                    && !is_visited[link.to]
+                    && self.visit(link.to, num_visited + 1, is_visited)
+            });
+            is_visited[frag_idx] = false;
+            is_round
+        }
+    }
+}
+
+///////////////
+// FALSENESS //
+///////////////
+
+mod falseness {
+    use std::rc::Rc;
+
+    use bellframe::Row;
+    use index_vec::index_vec;
+    use itertools::Itertools;
+    use jigsaw_utils::{
+        indexed_vec::{FragSlice, FragVec, RowIdx, RowVec},
+        types::RowLocation,
+    };
+
+    use crate::{expanded_frag::ExpandedFrag, full};
+
+    /// Computes the falseness of the composition, returning the groups of false rows, the
+    /// falseness of each fragment, the number of false rows and the number of false range groups
+    pub(super) fn compute_falseness(
+        expanded_frags: &FragSlice<Rc<ExpandedFrag>>,
+    ) -> (
+        Vec<full::FalseRowGroup>,
+        FragVec<FragFalseness>,
+        usize,
+        usize,
+    ) {
+        let (false_row_groups, num_false_rows) = gen_false_row_groups(expanded_frags);
+
+        // Mark every false row, and create the coalesced ranges of false rows
+        let mut frag_falseness: FragVec<FragFalseness> = expanded_frags
+            .iter()
+            .map(|f| FragFalseness {
+                is_false: index_vec![false; f.row_data.len()],
+                false_row_ranges: Vec::new(),
+            })
+            .collect();
+        for group in &false_row_groups {
+            for loc in &group.locations {
+                frag_falseness[loc.frag_index].is_false[loc.row_index] = true;
+            }
+        }
+        let num_false_groups = coalesce_false_row_groups(&false_row_groups, &mut frag_falseness);
+
+        (
+            false_row_groups,
+            frag_falseness,
+            num_false_rows,
+            num_false_groups,
+        )
+    }
+
+    /// The falseness annotations for a single [`Fragment`]
+    #[derive(Debug, Clone)]
+    pub(super) struct FragFalseness {
+        /// For each row, `true` if that row is false in any part
+        pub(super) is_false: RowVec<bool>,
+        pub(super) false_row_ranges: Vec<full::FalseRowRange>,
+    }
+
+    /// The key used to sort [`RowLocation`]s so that consecutive rows from the same part of the
+    /// same fragment are always next to each other
+    fn sort_key(loc: &RowLocation) -> impl Ord {
+        (loc.frag_index, loc.part_index, loc.row_index)
+    }
+
+    /// Groups all the proved rows in the composition (in every part) by the [`Row`] they contain,
+    /// returning the groups which contain more than one [`RowLocation`] along with the total
+    /// number of false rows.
+    fn gen_false_row_groups(
+        expanded_frags: &FragSlice<Rc<ExpandedFrag>>,
+    ) -> (Vec<full::FalseRowGroup>, usize) {
+        // Flatten every proved row in the composition into one list
+        let mut flattened_rows = Vec::<(&Row, RowLocation)>::new();
+        for (frag_index, frag) in expanded_frags.iter_enumerated() {
+            for (part_index, rows) in frag.rows_per_part.iter_enumerated() {
+                for ((row_index, row_data), row) in
+                    frag.row_data.iter_enumerated().zip_eq(rows.iter())
+                {
+                    if row_data.is_proved {
+                        let loc = RowLocation {
+                            frag_index,
+                            row_index,
+                            part_index,
+                        };
+                        flattened_rows.push((row, loc));
+                    }
+                }
+            }
+        }
+        // Sort the rows, so that identical rows appear next to each other.  Every `RowLocation` is
+        // unique, so each group will only be generated once.
+        flattened_rows.sort_by_key(|(row, _)| *row);
+
+        let mut num_false_rows = 0;
+        let mut false_row_groups = Vec::new();
+        for (_row, group) in &flattened_rows.into_iter().group_by(|(row, _)| *row) {
+            let mut locations = group.map(|(_, loc)| loc).collect_vec();
+            // If we saw more than one identical row, then these rows are false against each
+            // other
+            if locations.len() > 1 {
+                num_false_rows += locations.len();
+                locations.sort_by_key(sort_key);
+                false_row_groups.push(full::FalseRowGroup { locations });
+            }
+        }
+        (false_row_groups, num_false_rows)
+    }
+
+    /// Combine adjacent false row groups into [`FalseRowRange`](full::FalseRowRange)s, so that we
+    /// use fewer colours.  This returns the number of groups of ranges.
+    fn coalesce_false_row_groups(
+        false_row_groups: &[full::FalseRowGroup],
+        frag_falseness: &mut FragVec<FragFalseness>,
+    ) -> usize {
+        // Sort the groups by their first location.  Because every group is also sorted, any
+        // groups which should be coalesced will end up next to each other.
+        let mut sorted_groups = false_row_groups.iter().map(|g| &g.locations).collect_vec();
+        sorted_groups.sort_by_key(|locs| sort_key(&locs[0]));
+
+        let mut num_groups = 0;
+        let mut iter = sorted_groups.into_iter();
+        if let Some(first_group) = iter.next() {
+            let mut first_group_in_range = first_group;
+            let mut last_group = first_group;
+            for group in iter {
+                // Two groups are adjacent if they have the same number of locations, and each
+                // pair of corresponding locations are adjacent rows in the same part of the same
+                // fragment
+                let is_adjacent_to_last = group.len() == last_group.len()
+                    && group.iter().zip_eq(last_group).all(|(loc1, loc2)| {
+                        loc1.frag_index == loc2.frag_index
+                            && loc1.part_index == loc2.part_index
+                            && (loc1.row_index.index() as isize - loc2.row_index.index() as isize)
+                                .abs()
+                                == 1
+                    });
+                // If this group isn't adjacent to the last one, then we've finished a range
+                if !is_adjacent_to_last {
+                    add_ranges(frag_falseness, first_group_in_range, last_group, num_groups);
+                    first_group_in_range = group;
+                    num_groups += 1;
+                }
+                last_group = group;
+            }
+            // Make sure that we add the ranges containing the last group
+            add_ranges(frag_falseness, first_group_in_range, last_group, num_groups);
+            num_groups += 1;
+        }
+        num_groups
+    }
+
+    /// Adds the [`FalseRowRange`](full::FalseRowRange)s between two coalesced groups of false
+    /// rows to the fragments which contain them
+    fn add_ranges(
+        frag_falseness: &mut FragVec<FragFalseness>,
+        start: &[RowLocation],
+        end: &[RowLocation],
+        group: usize,
+    ) {
+        for (start_loc, end_loc) in start.iter().zip_eq(end) {
+            // Ranges can run in opposite directions (e.g. if a block is false against itself in
+            // reverse), so we make sure that `top <= bottom`
+            let top = RowIdx::min(start_loc.row_index, end_loc.row_index);
+            let bottom = RowIdx::max(start_loc.row_index, end_loc.row_index);
+            frag_falseness[start_loc.frag_index]
+                .false_row_ranges
+                .push(full::FalseRowRange {
+                    part_index: start_loc.part_index,
+                    top,
+                    bottom,
+                    group,
+                });
+        }
+    }
 }
 
 ////////////////////
@@ -105,180 +613,397 @@ fn generate_stats(frags: &FragSlice<ExpandedFrag>) -> Stats {
 ////////////////////
 
 mod music_gen {
-    use std::rc::Rc;
+    use std::{collections::HashMap, rc::Rc};
 
-    use bellframe::Stage;
+    use bellframe::{
+        music::{Regex, RegexElem},
+        Stage,
+    };
     use index_vec::index_vec;
     use itertools::Itertools;
     use jigsaw_utils::{
-        indexed_vec::{FragSlice, FragVec, PartVec, RowIdx},
-        types::RowLocation,
+        indexed_vec::{FragSlice, FragVec, PartIdx, PartSlice, PartVec, RowIdx},
+        types::{RowLocation, Stroke},
     };
 
     use crate::{expanded_frag::ExpandedFrag, full, music};
 
+    /// Combine the music matched by each fragment into the music groups for the whole composition
     pub(super) fn compute_music(
         music: &[music::Music],
-        expanded_frags: &FragSlice<ExpandedFrag>,
+        frag_musics: &FragSlice<Rc<FragMusic>>,
+        first_strokes: &FragSlice<PartVec<Stroke>>,
+        num_parts: usize,
         stage: Stage,
-    ) -> (full::Music, FragVec<FragMusic>) {
-        // Create a set of `FragMusic`s per part, who's counters will be incremented whilst computing
-        // the music
-        let mut frag_musics: FragVec<FragMusic> = expanded_frags
-            .iter()
-            .map(|frag| FragMusic::all_counters_zero(frag, stage))
-            .collect();
-        let (groups, total_count, max_count) =
-            expand_music_groups(music, expanded_frags, &mut frag_musics, stage);
-
-        let music = full::Music {
-            groups,
-            total_count,
-            max_count,
+    ) -> full::Music {
+        let expander = MusicExpander {
+            frag_musics,
+            first_strokes,
+            num_parts,
+            stage,
         };
-        (music, frag_musics)
+        let mut leaf_idx = 0;
+        let groups = expander.expand_groups(music, 1.0, &mut leaf_idx);
+        let counts = expander.sum_counts(&groups);
+        full::Music {
+            // Sum the top-level groups (ignoring the fact that we might double count identical
+            // regexes in different groups)
+            total_count: groups.iter().map(|g| g.inner.count()).sum(),
+            max_count: groups.iter().map(|g| g.max_count).sum(),
+            total_score: groups.iter().map(|g| g.score).sum(),
+            groups,
+            counts_per_part: counts.per_part,
+            counts_per_frag: counts.per_frag,
+            counts_per_stroke: counts.per_stroke,
+        }
     }
 
-    /// Recursively expand a sequence of music groups, totalling the number of occurrences
-    fn expand_music_groups(
-        music: &[music::Music],
-        expanded_frags: &FragSlice<ExpandedFrag>,
-        frag_musics: &mut FragSlice<FragMusic>,
-        stage: Stage,
-    ) -> (Vec<Rc<full::MusicGroup>>, usize, usize) {
-        // Expand groups individually
-        let music_groups = music
-            .iter()
-            .map(|m| expand_music_group(m, expanded_frags, frag_musics, stage))
-            .map(Rc::new)
-            .collect_vec();
-        // Sum their instances (ignoring the fact that we might double count identical regexes in
-        // different groups)
-        let total_count = music_groups.iter().map(|g| g.inner.count()).sum();
-        let max_count = music_groups.iter().map(|g| g.max_count).sum();
-        (music_groups, total_count, max_count)
-    }
-
-    /// Recursively expand a single [`music::Music`] group
-    fn expand_music_group(
-        group: &music::Music,
-        expanded_frags: &FragSlice<ExpandedFrag>,
-        frag_musics: &mut FragSlice<FragMusic>,
+    /// The number of music matches in each part, each fragment and at each stroke
+    struct Counts {
+        per_part: PartVec<usize>,
+        per_frag: FragVec<usize>,
+        per_stroke: [usize; 2],
+    }
+
+    /// The data shared by every music group whilst expanding the music definitions
+    struct MusicExpander<'a> {
+        frag_musics: &'a FragSlice<Rc<FragMusic>>,
+        first_strokes: &'a FragSlice<PartVec<Stroke>>,
+        num_parts: usize,
         stage: Stage,
-    ) -> full::MusicGroup {
-        match group {
-            music::Music::Regex(name, regex) => {
-                // Compute where this `Regex` is matched in the composition
-                let mut rows_matched = Vec::<RowLocation>::new();
-                // For each fragment ...
-                for ((frag_index, expanded_frag), frag_music) in
-                    expanded_frags.iter_enumerated().zip_eq(frag_musics)
-                {
-                    // ... for each part ...
-                    for ((part_index, rows), part_music_counters) in expanded_frag
-                        .rows_per_part
+    }
+
+    impl MusicExpander<'_> {
+        /// Recursively expand a sequence of music groups.  `default_weight` is the weight of any
+        /// group which doesn't specify its own, and `leaf_idx` tracks the index of the next leaf
+        /// (i.e. [`Regex`]) group, in depth-first order.
+        fn expand_groups(
+            &self,
+            music: &[music::Music],
+            default_weight: f32,
+            leaf_idx: &mut usize,
+        ) -> Vec<Rc<full::MusicGroup>> {
+            music
+                .iter()
+                .map(|m| self.expand_group(m, default_weight, leaf_idx))
+                .map(Rc::new)
+                .collect_vec()
+        }
+
+        /// Recursively expand a single [`music::Music`] group
+        fn expand_group(
+            &self,
+            group: &music::Music,
+            default_weight: f32,
+            leaf_idx: &mut usize,
+        ) -> full::MusicGroup {
+            match group {
+                music::Music::Regex {
+                    name,
+                    regex,
+                    weight,
+                    stroke,
+                } => {
+                    // Collect the proved rows matched by this `Regex` in every fragment, keeping
+                    // only those at the right stroke
+                    let rows_matched = self
+                        .frag_musics
                         .iter_enumerated()
-                        .zip_eq(&mut frag_music.music_highlights_per_part)
-                    {
-                        // ... for each row ...
-                        //
-                        // PERF: This whole calculation can probably be done in one vectorised pass
-                        for (row_index, ((row, music_counters), row_data)) in rows
-                            .iter()
-                            .zip_eq(part_music_counters.chunks_mut(stage.num_bells()))
-                            .zip_eq(&expanded_frag.row_data)
-                            .enumerate()
-                        {
-                            // Sanity check that all the elements are the same length.  The code
-                            // will likely panic anyway, but this assertion is easier to debug
-                            assert_eq!(music_counters.len(), stage.num_bells());
-                            // ... if the row matches this music pattern ...
-                            if let Some(matched_places) = regex.match_pattern(row) {
-                                // ... mark the row's places as highlight-able
-                                for matched_place in matched_places {
-                                    let counter = &mut music_counters[matched_place];
-                                    match counter.checked_add(1) {
-                                        // No problem if the counter didn't overflow
-                                        Some(v) => *counter = v,
-                                        None => {
-                                            eprintln!("WARNING: A place is matched by more than 255 music scores, clamping value to 255");
-                                            // Don't write to the counter, because its value is
-                                            // already 255
-                                        }
-                                    }
-                                }
-                                // ... and if the row is proved, include this row's location in the
-                                // music group
-                                if row_data.is_proved {
-                                    rows_matched.push(RowLocation {
-                                        frag_index,
-                                        row_index: RowIdx::new(row_index),
-                                        part_index,
-                                    });
-                                }
-                            }
+                        .flat_map(|(frag_index, frag_music)| {
+                            frag_music.matches_per_regex[*leaf_idx]
+                                .iter()
+                                .filter(|m| m.is_proved)
+                                .map(move |m| RowLocation {
+                                    frag_index,
+                                    row_index: m.row_index,
+                                    part_index: m.part_index,
+                                })
+                        })
+                        .filter(|loc| stroke.is_none_or(|s| self.stroke_of(loc) == s))
+                        .collect_vec();
+                    *leaf_idx += 1;
+
+                    // Use the music group's name, falling back on the regex's representation
+                    let name = name
+                        .as_ref()
+                        .map_or_else(|| regex.to_string(), String::clone);
+                    let max_count = num_matching_rows(regex, self.stage);
+                    let weight = weight.unwrap_or(default_weight);
+                    // Break the matches down by part, fragment and stroke
+                    let counts = self.counts(&rows_matched);
+                    full::MusicGroup {
+                        name,
+                        max_count,
+                        weight,
+                        score: rows_matched.len() as f32 * weight,
+                        counts_per_part: counts.per_part,
+                        counts_per_frag: counts.per_frag,
+                        counts_per_stroke: counts.per_stroke,
+                        inner: full::MusicGroupInner::Leaf { rows_matched },
+                    }
+                }
+                music::Music::Group {
+                    name,
+                    sub_groups: source_sub_groups,
+                    weight,
+                    unique_rows,
+                } => {
+                    // For a music group, expand the sub-groups in turn (passing this group's
+                    // weight down to them) and then total their matches
+                    let weight = weight.unwrap_or(default_weight);
+                    let sub_groups = self.expand_groups(source_sub_groups, weight, leaf_idx);
+                    let max_count = sub_groups.iter().map(|g| g.max_count).sum();
+                    let (count, score, counts) = if *unique_rows {
+                        // Only count each matched row once, scoring it by its best match
+                        let mut weighted_rows = HashMap::new();
+                        for g in &sub_groups {
+                            add_weighted_rows(g, &mut weighted_rows);
                         }
+                        (
+                            weighted_rows.len(),
+                            weighted_rows.values().sum(),
+                            self.counts(weighted_rows.keys()),
+                        )
+                    } else {
+                        (
+                            sub_groups.iter().map(|g| g.inner.count()).sum(),
+                            sub_groups.iter().map(|g| g.score).sum(),
+                            self.sum_counts(&sub_groups),
+                        )
+                    };
+                    full::MusicGroup {
+                        name: name.to_owned(),
+                        max_count,
+                        weight,
+                        score,
+                        counts_per_part: counts.per_part,
+                        counts_per_frag: counts.per_frag,
+                        counts_per_stroke: counts.per_stroke,
+                        inner: full::MusicGroupInner::Group { count, sub_groups },
                     }
                 }
+            }
+        }
 
-                // Use the music group's name, falling back on the regex's representation
-                let name = name
-                    .as_ref()
-                    .map_or_else(|| regex.to_string(), String::clone);
-                let max_count = regex
-                    .num_matching_rows(stage)
-                    .expect("Overflow whilst computing num rows");
-                full::MusicGroup {
-                    name,
-                    max_count,
-                    inner: full::MusicGroupInner::Leaf { rows_matched },
+        /// The stroke at which the [`Row`] at a given location is rung
+        fn stroke_of(&self, loc: &RowLocation) -> Stroke {
+            self.first_strokes[loc.frag_index][loc.part_index].offset(loc.row_index.index())
+        }
+
+        /// Count how many of `locations` fall in each part, each fragment and at each stroke
+        fn counts<'l>(&self, locations: impl IntoIterator<Item = &'l RowLocation>) -> Counts {
+            let mut counts = self.zero_counts();
+            for loc in locations {
+                counts.per_part[loc.part_index] += 1;
+                counts.per_frag[loc.frag_index] += 1;
+                counts.per_stroke[self.stroke_of(loc) as usize] += 1;
+            }
+            counts
+        }
+
+        /// Sum the [`Counts`] of a sequence of music groups
+        fn sum_counts(&self, groups: &[Rc<full::MusicGroup>]) -> Counts {
+            let mut counts = self.zero_counts();
+            for g in groups {
+                for (total, count) in counts.per_part.iter_mut().zip_eq(&g.counts_per_part) {
+                    *total += count;
+                }
+                for (total, count) in counts.per_frag.iter_mut().zip_eq(&g.counts_per_frag) {
+                    *total += count;
+                }
+                for (total, count) in counts.per_stroke.iter_mut().zip_eq(&g.counts_per_stroke) {
+                    *total += count;
+                }
+            }
+            counts
+        }
+
+        fn zero_counts(&self) -> Counts {
+            Counts {
+                per_part: index_vec![0; self.num_parts],
+                per_frag: index_vec![0; self.frag_musics.len()],
+                per_stroke: [0; 2],
+            }
+        }
+    }
+
+    /// Adds every [`RowLocation`] matched by a group (or its descendants) to `out`, along with
+    /// the largest weight of any leaf group which matches it
+    fn add_weighted_rows(group: &full::MusicGroup, out: &mut HashMap<RowLocation, f32>) {
+        match &group.inner {
+            full::MusicGroupInner::Leaf { rows_matched } => {
+                for loc in rows_matched {
+                    let weight = out.entry(*loc).or_insert(group.weight);
+                    *weight = weight.max(group.weight);
                 }
             }
-            music::Music::Group(name, source_sub_groups) => {
-                // For a music group, expand the sub-groups in turn and total the match counts
-                let (sub_groups, count, max_count) =
-                    expand_music_groups(source_sub_groups, expanded_frags, frag_musics, stage);
-                full::MusicGroup {
-                    name: name.to_owned(),
-                    max_count,
-                    inner: full::MusicGroupInner::Group { count, sub_groups },
+            full::MusicGroupInner::Group { sub_groups, .. } => {
+                for g in sub_groups {
+                    add_weighted_rows(g, out);
                 }
             }
         }
     }
 
-    /// The music annotations for a single [`Fragment`]
+    /// The number of [`Row`]s of the given [`Stage`] which could match `regex`.  This is `0` if
+    /// `regex` has more bells than the [`Stage`] (which [`Regex::num_matching_rows`] doesn't
+    /// handle), and saturates if the count overflows.
+    fn num_matching_rows(regex: &Regex, stage: Stage) -> usize {
+        let num_non_globs = regex
+            .elems()
+            .iter()
+            .filter(|e| !matches!(e, RegexElem::Glob))
+            .count();
+        if num_non_globs > stage.num_bells() {
+            return 0;
+        }
+        regex.num_matching_rows(stage).unwrap_or(usize::MAX)
+    }
+
+    /// Adds every [`Regex`] (along with its stroke filter) in a tree of music groups to `out`, in
+    /// depth-first order
+    fn leaf_regexes<'m>(music: &'m [music::Music], out: &mut Vec<(&'m Regex, Option<Stroke>)>) {
+        for m in music {
+            match m {
+                music::Music::Regex { regex, stroke, .. } => out.push((regex, *stroke)),
+                music::Music::Group { sub_groups, .. } => leaf_regexes(sub_groups, out),
+            }
+        }
+    }
+
+    /// A single [`Row`] of a [`Fragment`] which is matched by a [`Regex`]
+    #[derive(Debug, Clone)]
+    struct RegexMatch {
+        row_index: RowIdx,
+        part_index: PartIdx,
+        is_proved: bool,
+        /// The places of the bells which match the [`Regex`]
+        places: Vec<usize>,
+    }
+
+    /// The music annotations for a single [`Fragment`].  This only depends on the [`Fragment`]
+    /// and the music definitions, so can be reused if neither of them change.
     #[derive(Debug, Clone)]
-    pub(super) struct FragMusic {
+    pub(crate) struct FragMusic {
         /// For each part, how many leaf music groups match each place in the [`Fragment`].  We use
         /// `u8`s here, because I find it highly unlikely that we will be able to include a single
         /// place in more than 255 different music classes.  If we do manage that, the code will
         /// gracefully print a warning and saturate the value at 255.
-        pub(super) music_highlights_per_part: PartVec<Vec<u8>>,
+        ///
+        /// This doesn't include music which only matches at one stroke, because the strokes
+        /// depend on how the [`Fragment`]s are linked together.
+        music_highlights_per_part: Rc<PartVec<Vec<u8>>>,
+        /// For each leaf music group (in depth-first order), every row which it matches
+        matches_per_regex: Vec<Vec<RegexMatch>>,
+        /// For each leaf music group (in depth-first order), the stroke that it's restricted to
+        stroke_per_regex: Vec<Option<Stroke>>,
     }
 
     impl FragMusic {
-        fn all_counters_zero(frag: &ExpandedFrag, stage: Stage) -> Self {
-            let num_parts = frag.rows_per_part.len();
+        pub(crate) fn new(frag: &ExpandedFrag, music: &[music::Music], stage: Stage) -> Self {
+            let mut leaves = Vec::new();
+            leaf_regexes(music, &mut leaves);
+
+            // For each part, for each place, we initialise the counters to 0
+            let mut music_highlights_per_part: PartVec<Vec<u8>> = index_vec![
+                vec![0u8; frag.row_data.len() * stage.num_bells()];
+                frag.rows_per_part.len()
+            ];
+            let mut matches_per_regex = Vec::with_capacity(leaves.len());
+            let mut stroke_per_regex = Vec::with_capacity(leaves.len());
+            for (regex, stroke) in leaves {
+                // Compute where this `Regex` is matched in the fragment, in every part
+                //
+                // PERF: This whole calculation can probably be done in one vectorised pass
+                let mut matches = Vec::new();
+                for (part_index, rows) in frag.rows_per_part.iter_enumerated() {
+                    for (row_index, (row, row_data)) in
+                        rows.iter().zip_eq(&frag.row_data).enumerate()
+                    {
+                        if let Some(places) = regex.match_pattern(row) {
+                            matches.push(RegexMatch {
+                                row_index: RowIdx::new(row_index),
+                                part_index,
+                                is_proved: row_data.is_proved,
+                                places,
+                            });
+                        }
+                    }
+                }
+                // Music which doesn't depend on stroke can be highlighted straight away
+                if stroke.is_none() {
+                    add_highlights(&mut music_highlights_per_part, &matches, stage);
+                }
+                matches_per_regex.push(matches);
+                stroke_per_regex.push(stroke);
+            }
+
             Self {
-                music_highlights_per_part: {
-                    // For each part ...
-                    index_vec![
-                        // ... for each place, we initialise the counters to 0
-                        vec![0u8; frag.row_data.len() * stage.num_bells()];
-                        num_parts
-                    ]
-                },
+                music_highlights_per_part: Rc::new(music_highlights_per_part),
+                matches_per_regex,
+                stroke_per_regex,
+            }
+        }
+
+        /// The music highlights of this [`Fragment`], given the stroke of its first [`Row`] in
+        /// each part
+        pub(super) fn highlights(
+            &self,
+            first_strokes: &PartSlice<Stroke>,
+            stage: Stage,
+        ) -> Rc<PartVec<Vec<u8>>> {
+            if self.stroke_per_regex.iter().all(Option::is_none) {
+                // No music depends on stroke, so the cached highlights are already complete
+                return self.music_highlights_per_part.clone();
+            }
+            let mut highlights = PartVec::clone(&self.music_highlights_per_part);
+            for (stroke, matches) in self.stroke_per_regex.iter().zip_eq(&self.matches_per_regex) {
+                if let Some(stroke) = stroke {
+                    let matches_at_stroke = matches.iter().filter(|m| {
+                        first_strokes[m.part_index].offset(m.row_index.index()) == *stroke
+                    });
+                    add_highlights(&mut highlights, matches_at_stroke, stage);
+                }
+            }
+            Rc::new(highlights)
+        }
+    }
+
+    /// Mark the places matched by some [`RegexMatch`]es as highlight-able
+    fn add_highlights<'m>(
+        highlights: &mut PartVec<Vec<u8>>,
+        matches: impl IntoIterator<Item = &'m RegexMatch>,
+        stage: Stage,
+    ) {
+        for m in matches {
+            let row_start = m.row_index.index() * stage.num_bells();
+            for place in &m.places {
+                let counter = &mut highlights[m.part_index][row_start + place];
+                match counter.checked_add(1) {
+                    // No problem if the counter didn't overflow
+                    Some(v) => *counter = v,
+                    None => {
+                        eprintln!("WARNING: A place is matched by more than 255 music scores, clamping value to 255");
+                        // Don't write to the counter, because its value is already 255
+                    }
+                }
             }
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn expand_frag(
-    exp_frag: ExpandedFrag,
-    music: music_gen::FragMusic,
+    exp_frag: &ExpandedFrag,
+    music_highlights_per_part: Rc<PartVec<Vec<u8>>>,
+    first_stroke_per_part: PartVec<Stroke>,
+    falseness: falseness::FragFalseness,
+    link_groups: links::FragLinkGroups,
+    folded_leads: Vec<Range<usize>>,
     method_map: &HashMap<*const spec::Method, MethodIdx>,
     methods: &MethodSlice<Rc<full::Method>>,
+    call_map: &HashMap<*const spec::Call, CallIdx>,
+    calls: &CallSlice<Rc<full::Call>>,
 ) -> full::Fragment {
     // Helper function to convert between `spec::Method` and the corresponding `full::Method`
     let spec_to_full_method = |spec: &Rc<spec::Method>| {
@@ -290,10 +1015,20 @@ fn expand_frag(
     let mut full_row_data: RowVec<full::RowData> = exp_frag
         .row_data
         .iter()
-        .map(|row_data| full::RowData {
+        .zip_eq(falseness.is_false)
+        .map(|(row_data, is_false)| full::RowData {
             is_proved: row_data.is_proved,
+            is_false,
             ruleoff_above: false,    // Set later in this function
             method_annotation: None, // Set later in this function
+            // Calls are labelled on their first row
+            call_annotation: match &row_data.call_source {
+                Some((call, 0)) => {
+                    let spec_call_ptr = call.as_ref() as *const spec::Call;
+                    Some(calls[call_map[&spec_call_ptr]].clone())
+                }
+                _ => None,
+            },
         })
         .collect();
 
@@ -336,6 +1071,29 @@ fn expand_frag(
         }
     }
 
+    // Compute the calling positions of every call, in every part.  The calling position is
+    // determined by the place of the observation bell (the tenor) in the first row **after** the
+    // call.
+    let calling_positions_per_part = exp_frag
+        .rows_per_part
+        .iter()
+        .map(|rows| {
+            let observation_bell = Bell::tenor(rows.stage());
+            exp_frag
+                .row_data
+                .iter_enumerated()
+                .map(|(row_idx, row_data)| match &row_data.call_source {
+                    Some((call, 0)) => {
+                        let row_after_call = &rows[row_idx.index() + call.len()];
+                        let place = row_after_call.place_of(observation_bell).unwrap();
+                        call.calling_position(place).map(str::to_owned)
+                    }
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect();
+
     // The first row of every fragment is always given a method name.
     //
     // The unwraps here are safe, because fragments must contain at least one row (i.e. the
@@ -348,10 +1106,372 @@ fn expand_frag(
         // Only set a method name if the first row has a source method (i.e. is non-leftover)
         .map(|(method, _sub_lead_idx)| spec_to_full_method(method));
 
+    // Group the rows into the rows which will be displayed on the screen.  Each folded lead
+    // becomes a single display row, and every other row is displayed on its own.
+    let mut display_rows = Vec::with_capacity(full_row_data.len());
+    let mut folded_leads = folded_leads.into_iter().peekable();
+    let mut row_idx = 0;
+    while row_idx < full_row_data.len() {
+        let display_row = match folded_leads.peek() {
+            Some(lead) if lead.start == row_idx => folded_leads.next().unwrap(),
+            _ => row_idx..row_idx + 1,
+        };
+        row_idx = display_row.end;
+        display_rows.push(display_row);
+    }
+    let mut display_idx_of_row = RowVec::with_capacity(full_row_data.len());
+    for (display_idx, display_row) in display_rows.iter().enumerate() {
+        display_idx_of_row.extend(display_row.clone().map(|_| display_idx));
+    }
+
     full::Fragment {
         position: exp_frag.position,
-        rows_per_part: exp_frag.rows_per_part,
-        music_highlights_per_part: music.music_highlights_per_part,
+        link_group_top: link_groups.top,
+        link_group_bottom: link_groups.bottom,
+        rows_per_part: exp_frag.rows_per_part.clone(),
+        music_highlights_per_part,
+        first_stroke_per_part,
+        calling_positions_per_part,
+        false_row_ranges: falseness.false_row_ranges,
         row_data: full_row_data,
+        display_rows,
+        display_idx_of_row,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bellframe::{music::Regex, RowBuf, Stage};
+    use emath::Pos2;
+    use jigsaw_utils::{
+        indexed_vec::{FragIdx, MethodIdx, PartIdx, RowIdx},
+        types::Stroke,
+    };
+
+    use crate::{
+        full::{self, FragLink, FullState},
+        spec::{part_heads::PartHeads, CompSpec, FragLength, RuleoffPreset},
+        Music, MusicPresets,
+    };
+
+    /// Creates a composition of Plain Bob Doubles (which has 10-row leads and 40-row plain
+    /// courses) containing one fragment, starting at rounds, for each of `frag_lengths`
+    fn plain_bob(part_heads: &str, frag_lengths: &[FragLength]) -> CompSpec {
+        let mut spec = CompSpec::empty(Stage::DOUBLES, MusicPresets::None);
+        spec.add_method(
+            "Plain Bob".to_owned(),
+            "P".to_owned(),
+            "5.1.5.1.5,125",
+            RuleoffPreset::LeadEnd,
+        )
+        .unwrap();
+        spec.set_part_heads(PartHeads::parse(part_heads, Stage::DOUBLES).unwrap());
+        for (i, &length) in frag_lengths.iter().enumerate() {
+            let position = Pos2::new(i as f32 * 100.0, 0.0);
+            let rounds = RowBuf::rounds(Stage::DOUBLES);
+            spec.add_fragment(position, rounds, MethodIdx::new(0), length)
+                .unwrap();
+        }
+        spec
+    }
+
+    fn link(from: usize, to: usize, group: usize) -> FragLink {
+        FragLink {
+            from: FragIdx::new(from),
+            to: FragIdx::new(to),
+            group,
+        }
+    }
+
+    ///////////////
+    // FALSENESS //
+    ///////////////
+
+    #[test]
+    fn plain_course_is_true() {
+        let full_state = FullState::new(&plain_bob("", &[FragLength::PlainCourse]));
+        assert_eq!(full_state.stats.part_len, 40);
+        assert_eq!(full_state.stats.num_false_rows, 0);
+        assert_eq!(full_state.stats.num_false_groups, 0);
+        assert!(full_state.false_row_groups.is_empty());
+    }
+
+    #[test]
+    fn repeated_plain_course() {
+        let mut spec = plain_bob("", &[FragLength::PlainCourse, FragLength::PlainCourse]);
+        let full_state = FullState::new(&spec);
+        // Every proved row is false against the same row of the other fragment (leftover rows
+        // aren't proved)
+        assert_eq!(full_state.stats.num_false_rows, 80);
+        assert_eq!(full_state.false_row_groups.len(), 40);
+        assert!(full_state
+            .false_row_groups
+            .iter()
+            .all(|g| g.locations.len() == 2
+                && g.locations[0].frag_index == FragIdx::new(0)
+                && g.locations[1].frag_index == FragIdx::new(1)));
+        // The false rows are coalesced into one range in each fragment
+        assert_eq!(full_state.stats.num_false_groups, 1);
+        for frag in &full_state.fragments {
+            let ranges = frag
+                .false_row_ranges_in_part(PartIdx::new(0))
+                .map(|r| (r.top.index(), r.bottom.index(), r.group))
+                .collect::<Vec<_>>();
+            assert_eq!(ranges, [(0, 39, 0)]);
+        }
+
+        // Muted fragments aren't proved
+        spec.solo_frag(FragIdx::new(0)).unwrap();
+        let full_state = FullState::new(&spec);
+        assert_eq!(full_state.stats.num_false_rows, 0);
+        assert_eq!(full_state.stats.part_len, 40);
+    }
+
+    #[test]
+    fn false_between_parts() {
+        // Two plain leads in a 4-part whose part heads are the plain lead heads, so every part
+        // repeats the rows of the part after it
+        let full_state = FullState::new(&plain_bob("13524", &[FragLength::Leads(2)]));
+        assert_eq!(full_state.part_heads.len(), 4);
+        assert_eq!(full_state.stats.num_false_rows, 80);
+        assert_eq!(full_state.false_row_groups.len(), 40);
+        assert!(full_state
+            .false_row_groups
+            .iter()
+            .all(|g| g.locations.len() == 2));
+    }
+
+    ///////////
+    // LINKS //
+    ///////////
+
+    #[test]
+    fn plain_course_links_to_itself() {
+        let full_state = FullState::new(&plain_bob("", &[FragLength::PlainCourse]));
+        assert_eq!(full_state.frag_links, [link(0, 0, 0)]);
+        assert_eq!(
+            full_state.fragments[FragIdx::new(0)].link_group_top,
+            Some(0)
+        );
+        assert_eq!(
+            full_state.fragments[FragIdx::new(0)].link_group_bottom,
+            Some(0)
+        );
+        assert!(full_state.stats.is_round_block);
+    }
+
+    #[test]
+    fn links_between_fragments() {
+        let mut spec = plain_bob("", &[FragLength::PlainCourse]);
+        // Split after the first lead, so that the fragments link in a cycle
+        spec.split_fragment(FragIdx::new(0), 10, Pos2::new(0.0, 500.0))
+            .unwrap();
+        let full_state = FullState::new(&spec);
+        assert_eq!(full_state.frag_links, [link(0, 1, 0), link(1, 0, 1)]);
+        assert!(full_state.stats.is_round_block);
+
+        // A single lead doesn't come round on its own ...
+        spec.solo_frag(FragIdx::new(0)).unwrap();
+        let full_state = FullState::new(&spec);
+        assert!(!full_state.stats.is_round_block);
+        // ... but does if the part heads are the plain lead heads
+        let spec = plain_bob("13524", &[FragLength::Leads(1)]);
+        let full_state = FullState::new(&spec);
+        assert_eq!(full_state.frag_links, [link(0, 0, 0)]);
+        assert!(full_state.stats.is_round_block);
+    }
+
+    #[test]
+    fn equivalent_rows_share_link_groups() {
+        // In this 4-part, one and two plain leads both come round, but finish at different rows
+        let mut spec = plain_bob("13524", &[FragLength::Leads(1), FragLength::Leads(2)]);
+        let full_state = FullState::new(&spec);
+        assert_eq!(
+            full_state.frag_links,
+            [link(0, 0, 0), link(0, 1, 0), link(1, 0, 0), link(1, 1, 0)]
+        );
+        for frag in &full_state.fragments {
+            assert_eq!(
+                (frag.link_group_top, frag.link_group_bottom),
+                (Some(0), Some(0))
+            );
+        }
+
+        // Muted fragments still get links
+        spec.solo_frag(FragIdx::new(0)).unwrap();
+        let full_state = FullState::new(&spec);
+        assert_eq!(full_state.frag_links.len(), 4);
+        let muted_frag = &full_state.fragments[FragIdx::new(1)];
+        assert_eq!(muted_frag.link_group_top, Some(0));
+    }
+
+    #[test]
+    fn round_block_uses_every_proved_fragment() {
+        // The plain course links onto the lead, but the lead doesn't finish at rounds
+        let spec = plain_bob("", &[FragLength::PlainCourse, FragLength::Leads(1)]);
+        let full_state = FullState::new(&spec);
+        assert!(!full_state.stats.is_round_block);
+        // An empty composition isn't a round block
+        let spec = CompSpec::empty(Stage::DOUBLES, MusicPresets::None);
+        assert!(!FullState::new(&spec).stats.is_round_block);
+    }
+
+    /////////////
+    // STROKES //
+    /////////////
+
+    #[test]
+    fn strokes_follow_links() {
+        let mut spec = plain_bob("", &[FragLength::PlainCourse]);
+        // Split after 5 rows, so the second fragment starts at backstroke
+        spec.split_fragment(FragIdx::new(0), 5, Pos2::new(0.0, 500.0))
+            .unwrap();
+        let full_state = FullState::new(&spec);
+        let first_stroke = |frag: usize| {
+            full_state.fragments[FragIdx::new(frag)].stroke(RowIdx::new(0), PartIdx::new(0))
+        };
+        assert_eq!(first_stroke(0), Stroke::Hand);
+        assert_eq!(first_stroke(1), Stroke::Back);
+        let frag_1 = &full_state.fragments[FragIdx::new(1)];
+        assert_eq!(frag_1.stroke(RowIdx::new(1), PartIdx::new(0)), Stroke::Hand);
+    }
+
+    #[test]
+    fn strokes_in_odd_length_parts() {
+        // 5-row parts alternate strokes
+        let spec = plain_bob("13524", &[FragLength::Rows(5)]);
+        let full_state = FullState::new(&spec);
+        let frag = &full_state.fragments[FragIdx::new(0)];
+        let strokes = (0..4)
+            .map(|p| frag.stroke(RowIdx::new(0), PartIdx::new(p)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            strokes,
+            [Stroke::Hand, Stroke::Back, Stroke::Hand, Stroke::Back]
+        );
+    }
+
+    #[test]
+    fn strokes_carried_between_parts() {
+        // Each part of this 4-part is one plain lead, which links onto itself in the next part.
+        // The other fragment has an odd length, but isn't part of that block.
+        let mut spec = plain_bob("13524", &[FragLength::Leads(1), FragLength::Rows(5)]);
+        let part_strokes = |spec: &CompSpec| {
+            let full_state = FullState::new(spec);
+            let frag = &full_state.fragments[FragIdx::new(0)];
+            (0..4)
+                .map(|p| frag.stroke(RowIdx::new(0), PartIdx::new(p)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(part_strokes(&spec), [Stroke::Hand; 4]);
+        // Muting the other fragment doesn't change the strokes
+        spec.solo_frag(FragIdx::new(0)).unwrap();
+        assert_eq!(part_strokes(&spec), [Stroke::Hand; 4]);
+    }
+
+    #[test]
+    fn music_filtered_by_stroke() {
+        let mut spec = plain_bob("", &[FragLength::PlainCourse]);
+        let count = |spec: &mut CompSpec, stroke: Option<Stroke>| {
+            let music = Music::regex(None, Regex::parse("12345"));
+            let music = match stroke {
+                Some(stroke) => music.with_stroke(stroke),
+                None => music,
+            };
+            spec.set_music(vec![music], None);
+            FullState::new(spec).music.total_count
+        };
+        // Rounds is only proved as the first row, which is at handstroke
+        assert_eq!(count(&mut spec, None), 1);
+        assert_eq!(count(&mut spec, Some(Stroke::Hand)), 1);
+        assert_eq!(count(&mut spec, Some(Stroke::Back)), 0);
+    }
+
+    ///////////
+    // MUSIC //
+    ///////////
+
+    /// Computes the music of a plain course of Plain Bob Doubles.  In the 40 rows of the course,
+    /// `*4`, `*5` and `1*` each match 8 rows, `*45` matches 3 rows and `*345` matches 2 rows.
+    fn plain_course_music(music: Vec<Music>) -> full::Music {
+        let mut spec = plain_bob("", &[FragLength::PlainCourse]);
+        spec.set_music(music, None);
+        FullState::new(&spec).music
+    }
+
+    fn regex(pattern: &str) -> Music {
+        Music::regex(None, Regex::parse(pattern))
+    }
+
+    #[test]
+    fn weighted_regex() {
+        let music = plain_course_music(vec![regex("*5").with_weight(2.5)]);
+        let group = &music.groups[0];
+        assert_eq!(group.inner.count(), 8);
+        assert_eq!(group.weight, 2.5);
+        assert_eq!(group.score, 20.0);
+        assert_eq!(music.total_score, 20.0);
+    }
+
+    #[test]
+    fn weighted_group() {
+        // `*5` has its own weight, but `*4` takes its weight from the group
+        let sub_groups = vec![regex("*5").with_weight(0.5), regex("*4")];
+        let music = plain_course_music(vec![Music::group("g", sub_groups).with_weight(3.0)]);
+        let group = &music.groups[0];
+        let sub_groups = match &group.inner {
+            full::MusicGroupInner::Group { sub_groups, .. } => sub_groups,
+            full::MusicGroupInner::Leaf { .. } => panic!("Expected a group"),
+        };
+        let weights_and_scores = (sub_groups.iter())
+            .map(|g| (g.weight, g.score))
+            .collect::<Vec<_>>();
+        assert_eq!(weights_and_scores, [(0.5, 4.0), (3.0, 24.0)]);
+        assert_eq!(group.inner.count(), 16);
+        assert_eq!(group.score, 28.0);
+    }
+
+    #[test]
+    fn unique_rows_group() {
+        // Every row matching `*345` also matches `*45`, and every row matching `*45` also
+        // matches `*5`.  Each row should only be counted once, scored by its largest weight.
+        let group = |unique_rows: bool| Music::Group {
+            name: "g".to_owned(),
+            sub_groups: vec![
+                regex("*5"),
+                regex("*45").with_weight(2.0),
+                regex("*345").with_weight(4.0),
+            ],
+            weight: None,
+            unique_rows,
+        };
+        let music = plain_course_music(vec![group(true)]);
+        let group_music = &music.groups[0];
+        assert_eq!(group_music.inner.count(), 8);
+        assert_eq!(music.total_count, 8);
+        // 5 rows only match `*5`, 1 row matches `*45` and 2 rows match `*345`
+        assert_eq!(group_music.score, 5.0 * 1.0 + 1.0 * 2.0 + 2.0 * 4.0);
+        assert_eq!(group_music.counts_per_part.iter().sum::<usize>(), 8);
+        assert_eq!(group_music.counts_per_stroke.iter().sum::<usize>(), 8);
+
+        // Without `unique_rows`, every match is counted and scored
+        let music = plain_course_music(vec![group(false)]);
+        assert_eq!(music.groups[0].inner.count(), 8 + 3 + 2);
+        assert_eq!(music.groups[0].score, 8.0 * 1.0 + 3.0 * 2.0 + 2.0 * 4.0);
+    }
+
+    #[test]
+    fn total_score() {
+        let music = plain_course_music(vec![
+            regex("*5").with_weight(2.5),
+            Music::group("g", vec![regex("*5").with_weight(0.5), regex("*4")]).with_weight(3.0),
+            regex("1*").with_weight(-1.0),
+            regex("*45"),
+        ]);
+        assert_eq!(music.total_count, 8 + (8 + 8) + 8 + 3);
+        assert_eq!(
+            music.total_score,
+            8.0 * 2.5 + (8.0 * 0.5 + 8.0 * 3.0) - 8.0 + 3.0
+        );
     }
 }
@@ -1,23 +1,32 @@
 //! The fully annotated state of a composition used for querying and rendering.
 
-use std::{ops::Deref, rc::Rc};
+use std::{
+    ops::{Deref, Range},
+    rc::Rc,
+};
 
 use bellframe::{SameStageVec, Stage};
 use emath::Pos2;
 use itertools::Itertools;
 use jigsaw_utils::{
-    indexed_vec::{FragVec, MethodVec, PartIdx, PartVec, RowIdx, RowVec},
-    types::{RowLocation, RowSource},
+    indexed_vec::{CallVec, FragIdx, FragVec, MethodVec, PartIdx, PartVec, RowIdx, RowVec},
+    types::{RowLocation, Stroke},
 };
 
-use crate::spec::{self, part_heads::PartHeads, CompSpec};
+use crate::spec::{self, part_heads::PartHeads, CompSpec, RuleoffPreset};
 
 // Imports only used for doc comments
 #[allow(unused_imports)]
 use bellframe::Row;
 
+mod cache;
+mod folds;
 mod from_expanded_frags;
 
+pub use folds::Folds;
+
+use cache::Cache;
+
 /// The fully specified state of a composition.  This is designed to be efficient to query and easy
 /// to render from, unlike [`CompSpec`] which is designed to be compact and easy to modify or store
 /// to disk.
@@ -31,30 +40,45 @@ pub struct FullState {
     pub part_heads: Rc<PartHeads>,
     pub fragments: FragVec<Fragment>,
     pub methods: MethodVec<Rc<Method>>,
+    pub calls: CallVec<Rc<Call>>,
     pub music: Music,
+    /// Every set of proved [`Row`]s which are identical to each other
+    pub false_row_groups: Vec<FalseRowGroup>,
+    /// Every pair of [`Fragment`]s which can be joined together
+    pub frag_links: Vec<FragLink>,
     /// Misc statistics about the composition (e.g. part length)
     pub stats: Stats,
     pub stage: Stage,
+    /// Intermediate results which can be reused by [`FullState::update`]
+    cache: Cache,
 }
 
 impl FullState {
-    /// Creates a new [`FullState`] representing the same composition as a given [`CompSpec`].
+    /// Creates a new [`FullState`] representing the same composition as a given [`CompSpec`], with
+    /// no leads folded.
     pub fn new(spec: &CompSpec) -> Self {
-        let expanded_frags = spec.expand_fragments();
+        Self::with_cache(spec, Cache::new(spec, None), &Folds::default())
+    }
+
+    /// Updates `self` to represent the same composition as a given [`CompSpec`], with a given set
+    /// of [`Folds`].  Expanded [`Fragment`]s and their music are reused if their source fragments
+    /// (and the part heads and music definitions) are unchanged; everything else is recomputed.
+    pub fn update(&mut self, spec: &CompSpec, folds: &Folds) {
+        let cache = Cache::new(spec, Some(&self.cache));
+        *self = Self::with_cache(spec, cache, folds);
+    }
+
+    fn with_cache(spec: &CompSpec, cache: Cache, folds: &Folds) -> Self {
         from_expanded_frags::from_expanded_frags(
-            expanded_frags,
+            cache,
+            folds,
             spec.methods(),
+            spec.calls(),
             spec.part_heads().clone(),
             spec.music(),
             spec.stage(),
         )
     }
-
-    /// Updates `self` to represent the same composition as a given [`CompSpec`]
-    pub fn update(&mut self, spec: &CompSpec) {
-        // For now, just overwrite `self` without reusing any allocations
-        *self = Self::new(spec);
-    }
 }
 
 ///////////////
@@ -65,15 +89,33 @@ impl FullState {
 pub struct Fragment {
     /// The position of the top-left corner of the first [`Row`] in this `Fragment`
     pub position: Pos2,
+    /// The link group of the first [`Row`] of this `Fragment`, if any `Fragment` (including this
+    /// one or a muted one) links onto the top of this one
+    pub link_group_top: Option<usize>,
+    /// The link group of the leftover [`Row`] of this `Fragment`, if it links onto the top of any
+    /// `Fragment` (including this one or a muted one)
+    pub link_group_bottom: Option<usize>,
     /// For each part, which [`Row`]s make up this `Fragment`
-    rows_per_part: PartVec<SameStageVec>,
+    rows_per_part: Rc<PartVec<SameStageVec>>,
     /// For each part, how many leaf music groups match each place in the [`Row`]s from that part.
     /// I find it extremely unlikely that we'll overflow `u8`s here (since we'd need at least 256
     /// music groups to apply to the same position in a row).  Even then, the code saturates
     /// instead of overflowing and prints a warning to stderr.
-    music_highlights_per_part: PartVec<Vec<u8>>,
+    music_highlights_per_part: Rc<PartVec<Vec<u8>>>,
+    /// For each part, the stroke of the first [`Row`] of this `Fragment`
+    first_stroke_per_part: PartVec<Stroke>,
+    /// For each part, the calling position of each [`Call`] in this `Fragment`.  Only the first
+    /// [`Row`] of each [`Call`] has a calling position.
+    calling_positions_per_part: PartVec<RowVec<Option<String>>>,
+    /// The ranges of false [`Row`]s in this `Fragment`, in any part
+    false_row_ranges: Vec<FalseRowRange>,
     /// Extra non-part-specific data about each row to help the rendering
     row_data: RowVec<RowData>,
+    /// The range of [`Row`]s displayed in each on-screen row.  Folded leads take up one display
+    /// row, and every other [`Row`] has a display row to itself.
+    display_rows: Vec<Range<usize>>,
+    /// The index into `display_rows` which contains each [`Row`]
+    display_idx_of_row: RowVec<usize>,
 }
 
 impl Fragment {
@@ -81,28 +123,113 @@ impl Fragment {
         self.row_data.len()
     }
 
-    pub fn rows_in_part(&self, part: PartIdx) -> impl Iterator<Item = (RowIdx, RowDataForOnePart)> {
+    /// The stroke at which the [`Row`] at a given index is rung, in a given part
+    pub fn stroke(&self, row_idx: RowIdx, part_idx: PartIdx) -> Stroke {
+        self.first_stroke_per_part[part_idx].offset(row_idx.index())
+    }
+
+    /// Gets the part-independent data about the [`Row`] at a given index
+    pub fn row_data(&self, idx: RowIdx) -> &RowData {
+        &self.row_data[idx]
+    }
+
+    /// Gets the [`Row`] at a given index, as it appears in a given part
+    pub fn row_in_part(&self, part: PartIdx, idx: RowIdx) -> &Row {
+        self.rows_per_part[part].get(idx.index()).unwrap()
+    }
+
+    pub fn rows_in_part(
+        &self,
+        part: PartIdx,
+    ) -> impl Iterator<Item = (RowIdx, RowDataForOnePart<'_>)> {
         let row_vec = &self.rows_per_part[part];
         let stage = row_vec.stage();
         row_vec
             .iter()
             .zip_eq(&self.row_data)
             .zip_eq(self.music_highlights_per_part[part].chunks(stage.num_bells()))
+            .zip_eq(&self.calling_positions_per_part[part])
             .enumerate()
-            .map(|(idx, ((row, data), music_counts))| {
+            .map(|(idx, (((row, data), music_counts), calling_position))| {
                 (
                     RowIdx::new(idx),
-                    RowDataForOnePart::new(row, music_counts, data),
+                    RowDataForOnePart::new(row, music_counts, calling_position.as_deref(), data),
                 )
             })
     }
 
-    /// Returns the (index, distance) of the nearest rule-off to a given `target` row position
-    /// (which may have a fractional component).
+    /// The number of rows that this `Fragment` takes up on the screen, after folding
+    pub fn num_display_rows(&self) -> usize {
+        self.display_rows.len()
+    }
+
+    /// The index of the on-screen row which displays the [`Row`] at `idx`.  Every [`Row`] in a
+    /// folded lead is displayed by the same row.
+    pub fn display_idx(&self, idx: RowIdx) -> usize {
+        self.display_idx_of_row[idx]
+    }
+
+    /// Returns `true` if the [`Row`] at `idx` is hidden inside a folded lead (i.e. it is in a
+    /// folded lead, but isn't that lead's lead head)
+    pub fn is_hidden(&self, idx: RowIdx) -> bool {
+        self.display_rows[self.display_idx(idx)].start != idx.index()
+    }
+
+    /// If the [`Row`] at `idx` is the first row of a folded lead, then this returns the number of
+    /// [`Row`]s in that lead
+    pub fn folded_lead_len(&self, idx: RowIdx) -> Option<usize> {
+        let display_row = &self.display_rows[self.display_idx(idx)];
+        (display_row.start == idx.index() && display_row.len() > 1).then(|| display_row.len())
+    }
+
+    /// Converts a (fractional) on-screen row position into the corresponding (fractional)
+    /// [`Row`] index.  Positions in the top half of a folded lead map to the top of the lead, and
+    /// those in the bottom half map to the bottom, so that rounding never lands inside a folded
+    /// lead.  Positions outside the `Fragment` are extrapolated.
+    pub fn display_pos_to_row_pos(&self, display_pos: f32) -> f32 {
+        let display_idx = display_pos.floor();
+        let fraction = display_pos - display_idx;
+        if display_idx < 0.0 {
+            display_pos // Folding can't change anything above the first row
+        } else if let Some(rows) = self.display_rows.get(display_idx as usize) {
+            if fraction < 0.5 {
+                rows.start as f32 + fraction
+            } else {
+                rows.end as f32 - (1.0 - fraction)
+            }
+        } else {
+            display_pos - self.num_display_rows() as f32 + self.num_rows() as f32
+        }
+    }
+
+    /// Converts a (fractional) [`Row`] index into the corresponding (fractional) on-screen row
+    /// position.  Positions inside folded leads are scaled to fit in their single display row,
+    /// and positions outside the `Fragment` are extrapolated.
+    pub fn row_pos_to_display_pos(&self, row_pos: f32) -> f32 {
+        let row_idx = row_pos.floor();
+        if row_idx < 0.0 {
+            row_pos // Folding can't change anything above the first row
+        } else if let Some(&display_idx) = self.display_idx_of_row.get(row_idx as usize) {
+            let rows = &self.display_rows[display_idx];
+            display_idx as f32 + (row_pos - rows.start as f32) / rows.len() as f32
+        } else {
+            row_pos - self.num_rows() as f32 + self.num_display_rows() as f32
+        }
+    }
+
+    /// The [`FalseRowRange`]s which should be displayed when viewing a given part
+    pub fn false_row_ranges_in_part(&self, part: PartIdx) -> impl Iterator<Item = &FalseRowRange> {
+        self.false_row_ranges
+            .iter()
+            .filter(move |range| range.part_index == part)
+    }
+
+    /// Returns the (index, distance) of the nearest visible rule-off to a given `target` row
+    /// position (which may have a fractional component).
     pub fn nearest_ruleoff_to(&self, target: f32) -> Option<(RowIdx, f32)> {
         let mut nearest_dist = None;
         for (row_idx, row_data) in self.row_data.iter_enumerated() {
-            if row_data.ruleoff_above {
+            if row_data.ruleoff_above && !self.is_hidden(row_idx) {
                 let dist = (row_idx.index() as f32 - target).abs();
                 match nearest_dist {
                     // If no rule-offs have been found, then this is automatically better
@@ -125,14 +252,22 @@ impl Fragment {
 pub struct RowDataForOnePart<'frag> {
     pub row: &'frag Row,
     pub music_counts: &'frag [u8],
+    /// The calling position of the [`Call`] starting at this [`Row`] (if there is one)
+    pub calling_position: Option<&'frag str>,
     data: &'frag RowData,
 }
 
 impl<'frag> RowDataForOnePart<'frag> {
-    pub fn new(row: &'frag Row, music_counts: &'frag [u8], data: &'frag RowData) -> Self {
+    pub fn new(
+        row: &'frag Row,
+        music_counts: &'frag [u8],
+        calling_position: Option<&'frag str>,
+        data: &'frag RowData,
+    ) -> Self {
         Self {
             row,
             music_counts,
+            calling_position,
             data,
         }
     }
@@ -158,10 +293,49 @@ pub struct RowData {
     pub ruleoff_above: bool,
     /// What method name should be placed here
     pub method_annotation: Option<Rc<Method>>,
-    /*
-    /// Do any of these [`Row`]s appear elsewhere in the composition?
+    /// Which [`Call`] (if any) should be labelled at this [`Row`].  This is only set on the first
+    /// [`Row`] of each call.
+    pub call_annotation: Option<Rc<Call>>,
+    /// Do any of these [`Row`]s (in any part) appear elsewhere in the composition?
     pub is_false: bool,
-    */
+}
+
+/// A contiguous range of false [`Row`]s in a single part of a [`Fragment`].  Ranges with the same
+/// `group` are false against each other, and should be highlighted in the same way.
+#[derive(Debug, Clone)]
+pub struct FalseRowRange {
+    pub part_index: PartIdx,
+    /// The index of the first [`Row`] in this range
+    pub top: RowIdx,
+    /// The index of the last [`Row`] in this range (i.e. this range is inclusive)
+    pub bottom: RowIdx,
+    /// Which group of false ranges this belongs to.  Groups are numbered from `0` to
+    /// `stats.num_false_groups - 1`.
+    pub group: usize,
+}
+
+impl FalseRowRange {
+    /// The number of [`Row`]s covered by this range
+    pub fn num_rows(&self) -> usize {
+        self.bottom.index() - self.top.index() + 1
+    }
+}
+
+/// A set of [`RowLocation`]s which all contain the same [`Row`].  These locations are sorted, and
+/// every group contains at least two locations.
+#[derive(Debug, Clone)]
+pub struct FalseRowGroup {
+    pub locations: Vec<RowLocation>,
+}
+
+/// A link between two [`Fragment`]s, meaning that the leftover [`Row`] of `from` is equivalent
+/// (under the [`PartHeads`]) to the first [`Row`] of `to`.  All the links which join equivalent
+/// [`Row`]s share a `group`, so that they can be drawn in the same colour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragLink {
+    pub from: FragIdx,
+    pub to: FragIdx,
+    pub group: usize,
 }
 
 /////////////
@@ -187,6 +361,53 @@ impl Method {
     pub fn shorthand(&self) -> String {
         self.source.shorthand().to_owned()
     }
+
+    #[inline]
+    pub fn place_notation(&self) -> String {
+        self.source.place_notation().to_owned()
+    }
+
+    /// The [`RuleoffPreset`] used by this [`Method`], or `None` if its rule-offs don't match any
+    /// preset
+    #[inline]
+    pub fn ruleoff_preset(&self) -> Option<RuleoffPreset> {
+        self.source.ruleoff_preset()
+    }
+}
+
+///////////
+// CALLS //
+///////////
+
+#[derive(Debug, Clone)]
+pub struct Call {
+    pub(crate) source: Rc<spec::Call>,
+    /// Total number of times that this [`Call`] is used, across all parts
+    pub num_uses: usize,
+    /// Number of times that this [`Call`] is used in proved [`Row`]s, across all parts
+    pub num_proved_uses: usize,
+}
+
+impl Call {
+    /// The [`char`] used to label this `Call` in the composition
+    #[inline]
+    pub fn symbol(&self) -> char {
+        self.source.symbol()
+    }
+
+    #[inline]
+    pub fn place_notation(&self) -> String {
+        self.source.place_notation().to_owned()
+    }
+
+    /// A short description of where this `Call` is placed in the lead (e.g. `"LE"` for lead end
+    /// calls)
+    pub fn location(&self) -> String {
+        match self.source.sub_lead_idx_after() {
+            0 => "LE".to_owned(),
+            idx => format!("before row {}", idx),
+        }
+    }
 }
 
 ///////////
@@ -199,6 +420,14 @@ pub struct Music {
     pub(super) groups: Vec<Rc<MusicGroup>>,
     pub(super) total_count: usize,
     pub(super) max_count: usize,
+    /// The sum of the scores of every top-level [`MusicGroup`]
+    pub(super) total_score: f32,
+    /// The total number of music matches in each part
+    pub(super) counts_per_part: PartVec<usize>,
+    /// The total number of music matches in each [`Fragment`] (across all parts)
+    pub(super) counts_per_frag: FragVec<usize>,
+    /// The total number of music matches at each [`Stroke`] (indexed by `Stroke as usize`)
+    pub(super) counts_per_stroke: [usize; 2],
 }
 
 impl Music {
@@ -206,14 +435,37 @@ impl Music {
         self.groups.as_slice()
     }
 
+    /// The sum of the counts of every top-level [`MusicGroup`].  Rows matched by more than one
+    /// group are counted once per group.
     pub fn total_count(&self) -> usize {
         self.total_count
     }
 
+    /// The sum of the scores of every top-level [`MusicGroup`].  This is the value used to
+    /// compare compositions.
+    pub fn total_score(&self) -> f32 {
+        self.total_score
+    }
+
     /// Get a reference to the music's max count.
     pub fn max_count(&self) -> &usize {
         &self.max_count
     }
+
+    /// The total number of music matches in each part
+    pub fn counts_per_part(&self) -> &PartVec<usize> {
+        &self.counts_per_part
+    }
+
+    /// The total number of music matches in each [`Fragment`] (summed over all the parts)
+    pub fn counts_per_frag(&self) -> &FragVec<usize> {
+        &self.counts_per_frag
+    }
+
+    /// The total number of music matches at each [`Stroke`] (indexed by `Stroke as usize`)
+    pub fn counts_per_stroke(&self) -> &[usize; 2] {
+        &self.counts_per_stroke
+    }
 }
 
 /// A group of musical rows, potentially subdivided into more groups.  This strongly follows the
@@ -222,21 +474,40 @@ impl Music {
 pub struct MusicGroup {
     pub name: String,
     pub max_count: usize,
+    /// The weight of this group.  For leaf groups this is the score given to each matched
+    /// [`Row`], and for other groups it's the default weight passed to the sub-groups.
+    pub weight: f32,
+    /// The total score of the rows matched by this group
+    pub score: f32,
+    /// The number of times that this group was matched in each part.  These sum to
+    /// `self.inner.count()`.
+    pub counts_per_part: PartVec<usize>,
+    /// The number of times that this group was matched in each [`Fragment`] (summed over all
+    /// the parts).  These also sum to `self.inner.count()`.
+    pub counts_per_frag: FragVec<usize>,
+    /// The number of times that this group was matched at each [`Stroke`] (indexed by
+    /// `Stroke as usize`).  These also sum to `self.inner.count()`.
+    pub counts_per_stroke: [usize; 2],
     // If empty, then this [`MusicGroup`] is a 'leaf' of the tree
     pub inner: MusicGroupInner,
 }
 
 impl MusicGroup {
-    /// Add the [`RowSource`] of every [`Row`] matched by `self` or any of its descendants.
-    /// [`RowSource`]s may be added multiple times.
-    pub fn add_row_sources(&self, out: &mut impl Extend<RowSource>) {
+    /// Add the [`RowLocation`] of every [`Row`] matched by `self` or any of its descendants,
+    /// provided that the location satisfies `filter`.  [`RowLocation`]s may be added multiple
+    /// times.
+    pub fn add_row_locations(
+        &self,
+        filter: &dyn Fn(&RowLocation) -> bool,
+        out: &mut impl Extend<RowLocation>,
+    ) {
         match &self.inner {
             MusicGroupInner::Leaf { rows_matched } => {
-                out.extend(rows_matched.iter().map(|loc| loc.as_source()))
+                out.extend(rows_matched.iter().filter(|loc| filter(loc)).copied())
             }
             MusicGroupInner::Group { sub_groups, .. } => {
                 for g in sub_groups {
-                    g.add_row_sources(out);
+                    g.add_row_locations(filter, out);
                 }
             }
         }
@@ -250,6 +521,8 @@ pub enum MusicGroupInner {
     },
     Group {
         sub_groups: Vec<Rc<MusicGroup>>,
+        /// The number of matches of this group.  If the group only counts unique rows, then this
+        /// can be less than the sum of the sub-groups' counts.
         count: usize,
     },
 }
@@ -268,16 +541,111 @@ impl MusicGroupInner {
 // MISC STATISTICS //
 /////////////////////
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Stats {
     /// The number of [`Row`]s in each part of the composition
     pub part_len: usize,
+    /// The total number of proved [`Row`]s (across all parts) which are false
+    pub num_false_rows: usize,
+    /// The number of distinct groups of [`FalseRowRange`]s
+    pub num_false_groups: usize,
+    /// `true` if the proved [`Fragment`]s can be linked into a single block which starts and ends
+    /// at rounds (i.e. a complete composition)
+    pub is_round_block: bool,
 }
 
-impl Default for Stats {
-    fn default() -> Self {
-        Self {
-            part_len: Default::default(),
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use bellframe::{music::Regex, RowBuf, Stage};
+    use emath::Pos2;
+    use jigsaw_utils::indexed_vec::FragIdx;
+
+    use crate::{
+        spec::{part_heads::PartHeads, CompSpec},
+        Music,
+    };
+
+    use super::{cache::Cache, Folds, FullState};
+
+    /// The example composition, plus a copy of its fragment (so that one fragment can be edited
+    /// whilst the other is left alone)
+    fn two_fragments() -> CompSpec {
+        let mut spec = CompSpec::example();
+        spec.duplicate_fragment(FragIdx::new(0), Pos2::new(500.0, 100.0))
+            .unwrap();
+        spec
+    }
+
+    /// Updates `full_state` to represent `spec`, checking that the result is the same as building
+    /// a new [`FullState`] from scratch.  Returns the cache from before the update.
+    fn update(full_state: &mut FullState, spec: &CompSpec) -> Cache {
+        let old_cache = full_state.cache.clone();
+        full_state.update(spec, &Folds::default());
+        assert_eq!(
+            format!("{:?}", full_state),
+            format!("{:?}", FullState::new(spec))
+        );
+        old_cache
+    }
+
+    /// Whether the expansion and the music of a fragment were reused from `old_cache`
+    fn reuse(
+        old_cache: &Cache,
+        old_idx: usize,
+        full_state: &FullState,
+        new_idx: usize,
+    ) -> (bool, bool) {
+        let old = &old_cache.frags[FragIdx::new(old_idx)];
+        let new = &full_state.cache.frags[FragIdx::new(new_idx)];
+        (
+            Rc::ptr_eq(&old.expanded, &new.expanded),
+            Rc::ptr_eq(&old.music, &new.music),
+        )
+    }
+
+    #[test]
+    fn unchanged_fragments_are_reused() {
+        let mut spec = two_fragments();
+        let mut full_state = FullState::new(&spec);
+
+        // Only the edited fragment is recomputed
+        let new_row = RowBuf::parse("12436578").unwrap();
+        (spec.transpose_fragment(FragIdx::new(1), 0, &new_row)).unwrap();
+        let old_cache = update(&mut full_state, &spec);
+        assert_eq!(reuse(&old_cache, 0, &full_state, 0), (true, true));
+        assert_eq!(reuse(&old_cache, 1, &full_state, 1), (false, false));
+
+        // Fragments are reused even if their index changes
+        spec.delete_fragment(FragIdx::new(0)).unwrap();
+        let old_cache = update(&mut full_state, &spec);
+        assert_eq!(reuse(&old_cache, 1, &full_state, 0), (true, true));
+    }
+
+    #[test]
+    fn part_head_change_clears_cache() {
+        let mut spec = two_fragments();
+        let mut full_state = FullState::new(&spec);
+
+        spec.set_part_heads(PartHeads::parse("", Stage::MAJOR).unwrap());
+        let old_cache = update(&mut full_state, &spec);
+        for idx in 0..2 {
+            assert_eq!(reuse(&old_cache, idx, &full_state, idx), (false, false));
+        }
+    }
+
+    #[test]
+    fn music_change_recomputes_music() {
+        let mut spec = two_fragments();
+        let mut full_state = FullState::new(&spec);
+
+        let music = Music::regex(Some("Rounds"), Regex::parse("12345678"));
+        spec.set_music(vec![music], None);
+        let old_cache = update(&mut full_state, &spec);
+        for idx in 0..2 {
+            assert_eq!(reuse(&old_cache, idx, &full_state, idx), (true, false));
         }
+        assert_eq!(full_state.music.total_count, 2);
     }
 }
@@ -3,8 +3,10 @@
 mod expanded_frag;
 pub mod full;
 mod history;
+pub mod method_lib;
 mod music;
+pub mod music_file;
 pub mod spec;
 
 pub use history::History;
-pub use music::Music;
+pub use music::{Music, MusicPresets};
@@ -0,0 +1,403 @@
+//! A searchable library of [`Method`]s, loaded from the Central Council's XML method collection
+
+use std::{
+    convert::TryFrom,
+    fmt::{Display, Formatter},
+    path::Path,
+};
+
+use bellframe::Stage;
+use quick_xml::{events::Event, Reader};
+
+// Imports only used for doc comments
+#[allow(unused_imports)]
+use crate::spec::Method;
+
+/// A library of [`Method`]s which can be searched and added to a composition.  This is usually
+/// loaded from a local copy of the CCCBR's method collection (`CCCBR_methods.xml`).
+#[derive(Debug, Clone, Default)]
+pub struct MethodLib {
+    methods: Vec<LibMethod>,
+}
+
+/// A single [`Method`] in a [`MethodLib`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LibMethod {
+    /// The full title of the method (e.g. `"Bristol Surprise Major"`)
+    pub title: String,
+    /// The name of the method (e.g. `"Bristol"`)
+    pub name: String,
+    pub stage: Stage,
+    /// The classification of the method (e.g. `"Surprise"`).  This is empty for principles.
+    pub class: String,
+    pub place_notation: String,
+    /// The lower-case version of `title`, cached to make searching faster
+    title_lower: String,
+}
+
+/// A search query over a [`MethodLib`].  A [`LibMethod`] matches the query if it satisfies every
+/// part of it.
+#[derive(Debug, Clone, Default)]
+pub struct Query<'q> {
+    /// Text which must appear in the title of the method.  This is case-insensitive.
+    pub text: &'q str,
+    pub stage: Option<Stage>,
+    /// The classification of the method.  This is case-insensitive.
+    pub class: Option<&'q str>,
+}
+
+impl MethodLib {
+    /// Loads a [`MethodLib`] from a file in the CCCBR's XML format
+    pub fn from_xml_file(path: impl AsRef<Path>) -> Result<Self, MethodLibError> {
+        let xml = std::fs::read_to_string(path).map_err(MethodLibError::Io)?;
+        Self::from_xml(&xml)
+    }
+
+    /// Parses a string in the CCCBR's XML format into a [`MethodLib`].  The parts of the file
+    /// that we care about are:
+    /// ```xml
+    /// <collection>
+    ///     <methodSet>
+    ///         <properties>
+    ///             <stage>8</stage>
+    ///             <classification>Surprise</classification>
+    ///         </properties>
+    ///         <method>
+    ///             <title>Bristol Surprise Major</title>
+    ///             <name>Bristol</name>
+    ///             <notation>-58-14.58-58.36.14-14.58-14-18,18</notation>
+    ///         </method>
+    ///     </methodSet>
+    /// </collection>
+    /// ```
+    /// Values inside a `method` element override those in the `properties` of its `methodSet`.
+    /// Methods which are missing any of these values (or have a stage of 0) are skipped.
+    pub fn from_xml(xml: &str) -> Result<Self, MethodLibError> {
+        let mut reader = Reader::from_str(xml);
+        reader.trim_text(true);
+
+        let mut methods = Vec::new();
+        // The names of the elements which enclose the current event
+        let mut element_stack = Vec::<Vec<u8>>::new();
+        // Properties shared by every method in the current `methodSet`
+        let mut set_props = Properties::default();
+        // Properties of the `method` currently being read (which override `set_props`)
+        let mut method_props = Properties::default();
+
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_event(&mut buf).map_err(MethodLibError::Xml)? {
+                Event::Start(e) => {
+                    match e.local_name() {
+                        b"methodSet" => set_props = Properties::default(),
+                        b"method" => method_props = Properties::default(),
+                        _ => {}
+                    }
+                    element_stack.push(e.local_name().to_owned());
+                }
+                Event::End(e) => {
+                    element_stack.pop();
+                    if e.local_name() == b"method" {
+                        methods.extend(LibMethod::from_props(&method_props, &set_props));
+                    }
+                }
+                Event::Text(e) => {
+                    let text = e
+                        .unescape_and_decode(&reader)
+                        .map_err(MethodLibError::Xml)?;
+                    // Only read values which are direct children of `method` or `properties`
+                    // elements (other elements, like `references`, can contain tags such as
+                    // `name` which mean something different).
+                    let (parent, element) = match element_stack.as_slice() {
+                        [.., parent, element] => (parent.as_slice(), element.as_slice()),
+                        _ => continue,
+                    };
+                    let props = match parent {
+                        b"method" => &mut method_props,
+                        b"properties" => &mut set_props,
+                        _ => continue,
+                    };
+                    match element {
+                        b"title" => props.title = Some(text),
+                        b"name" => props.name = Some(text),
+                        b"stage" => {
+                            // Stages of 0 (or which aren't numbers) are invalid, and mustn't
+                            // fall back on the stage of the `methodSet`
+                            props.stage = text
+                                .parse::<usize>()
+                                .ok()
+                                .and_then(|n| Stage::try_from(n).ok());
+                            props.is_invalid |= props.stage.is_none();
+                        }
+                        b"classification" => props.class = Some(text),
+                        b"notation" => props.place_notation = Some(text),
+                        _ => {}
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        Ok(Self { methods })
+    }
+
+    /// Returns up to `max_results` [`LibMethod`]s which match a [`Query`].  Methods whose titles
+    /// start with the query text are returned before those which only contain it, and shorter
+    /// titles are returned before longer ones.
+    pub fn search(&self, query: &Query, max_results: usize) -> Vec<&LibMethod> {
+        let text = query.text.trim().to_lowercase();
+        let mut results = self
+            .methods
+            .iter()
+            .filter(|m| query.stage.is_none_or(|stage| m.stage == stage))
+            .filter(|m| {
+                query
+                    .class
+                    .is_none_or(|class| m.class.eq_ignore_ascii_case(class.trim()))
+            })
+            .filter(|m| m.title_lower.contains(&text))
+            .collect::<Vec<_>>();
+        results.sort_by_key(|m| (!m.title_lower.starts_with(&text), m.title.len()));
+        results.truncate(max_results);
+        results
+    }
+
+    /// The number of [`LibMethod`]s in this library
+    pub fn len(&self) -> usize {
+        self.methods.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.methods.is_empty()
+    }
+}
+
+impl LibMethod {
+    /// Create a `LibMethod` from the properties of a `method` element, using the properties of
+    /// its `methodSet` for any missing values.  Returns `None` if any required values are
+    /// missing or invalid.
+    fn from_props(method_props: &Properties, set_props: &Properties) -> Option<Self> {
+        if method_props.is_invalid || set_props.is_invalid {
+            return None;
+        }
+        let title = method_props.title.clone()?;
+        Some(Self {
+            name: method_props.name.clone()?,
+            stage: method_props.stage.or(set_props.stage)?,
+            class: (method_props.class.as_ref())
+                .or(set_props.class.as_ref())
+                .cloned()
+                .unwrap_or_default(),
+            place_notation: method_props.place_notation.clone()?,
+            title_lower: title.to_lowercase(),
+            title,
+        })
+    }
+
+    /// A shorthand for this method, made from the first character of its name
+    pub fn default_shorthand(&self) -> String {
+        self.name.chars().take(1).collect()
+    }
+}
+
+/// The values read from either a `methodSet` or `method` element
+#[derive(Debug, Clone, Default)]
+struct Properties {
+    title: Option<String>,
+    name: Option<String>,
+    stage: Option<Stage>,
+    class: Option<String>,
+    place_notation: Option<String>,
+    /// `true` if any of the values couldn't be parsed, in which case the method is skipped
+    is_invalid: bool,
+}
+
+/// The possible ways that loading a [`MethodLib`] can fail
+#[derive(Debug)]
+pub enum MethodLibError {
+    Io(std::io::Error),
+    Xml(quick_xml::Error),
+}
+
+impl Display for MethodLibError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            MethodLibError::Io(e) => write!(f, "Error reading method library: {}", e),
+            MethodLibError::Xml(e) => write!(f, "Error parsing method library: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MethodLibError {}
+
+#[cfg(test)]
+mod tests {
+    use bellframe::Stage;
+
+    use crate::spec::CompSpec;
+
+    use super::{MethodLib, Query};
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<collection xmlns="http://www.cccbr.org.uk/methods/schemas/2007/05/methods">
+    <methodSet>
+        <properties>
+            <stage>8</stage>
+            <classification little="true">Surprise</classification>
+        </properties>
+        <method id="m1">
+            <title>Bristol Surprise Major</title>
+            <name>Bristol</name>
+            <notation>-58-14.58-58.36.14-14.58-14-18,18</notation>
+            <references><name>Not a method name</name></references>
+        </method>
+        <method id="m2">
+            <title>Cambridge Surprise Minor</title>
+            <name>Cambridge</name>
+            <stage>6</stage>
+            <notation>-36-14-12-36-14-56,12</notation>
+        </method>
+        <method id="m3">
+            <title>Missing Notation Surprise Major</title>
+            <name>Missing Notation</name>
+        </method>
+        <method id="m4">
+            <title>Zero Surprise</title>
+            <name>Zero</name>
+            <stage>0</stage>
+            <notation>x</notation>
+        </method>
+    </methodSet>
+    <methodSet>
+        <properties>
+            <stage>8</stage>
+        </properties>
+        <method id="m5">
+            <title>Plain Bob Major</title>
+            <name>Plain</name>
+            <notation>x18x18x18x18,12</notation>
+        </method>
+        <method id="m6">
+            <title>Little Bristol Surprise Major</title>
+            <name>Little Bristol</name>
+            <classification>Surprise</classification>
+            <notation>-58-14.58-58.36-14-58-36-18,18</notation>
+        </method>
+    </methodSet>
+</collection>"#;
+
+    fn titles(lib: &MethodLib, query: &Query) -> Vec<String> {
+        lib.search(query, 10)
+            .into_iter()
+            .map(|m| m.title.clone())
+            .collect()
+    }
+
+    #[test]
+    fn from_xml() {
+        let lib = MethodLib::from_xml(XML).unwrap();
+        // Methods with missing values or a stage of 0 are skipped
+        assert_eq!(lib.len(), 4);
+
+        let bristol = &lib.search(
+            &Query {
+                text: "bristol s",
+                ..Query::default()
+            },
+            10,
+        )[0];
+        assert_eq!(bristol.name, "Bristol");
+        assert_eq!(bristol.stage, Stage::MAJOR);
+        assert_eq!(bristol.class, "Surprise");
+        assert_eq!(bristol.place_notation, "-58-14.58-58.36.14-14.58-14-18,18");
+        assert_eq!(bristol.default_shorthand(), "B");
+
+        // Values in a method override those of its `methodSet`
+        let cambridge = &lib.search(
+            &Query {
+                text: "cambridge",
+                ..Query::default()
+            },
+            10,
+        )[0];
+        assert_eq!(cambridge.stage, Stage::MINOR);
+        // Principles have no class
+        let plain_bob = &lib.search(
+            &Query {
+                text: "plain",
+                ..Query::default()
+            },
+            10,
+        )[0];
+        assert_eq!(plain_bob.class, "");
+
+        assert!(MethodLib::from_xml("<collection><method></collection>").is_err());
+    }
+
+    #[test]
+    fn search() {
+        let lib = MethodLib::from_xml(XML).unwrap();
+        // Titles starting with the text come first, then shorter titles
+        assert_eq!(
+            titles(
+                &lib,
+                &Query {
+                    text: " BRISTOL ",
+                    ..Query::default()
+                }
+            ),
+            ["Bristol Surprise Major", "Little Bristol Surprise Major"]
+        );
+        assert_eq!(
+            titles(
+                &lib,
+                &Query {
+                    text: "surprise",
+                    stage: Some(Stage::MAJOR),
+                    class: Some("surprise"),
+                }
+            ),
+            ["Bristol Surprise Major", "Little Bristol Surprise Major"]
+        );
+        assert_eq!(
+            titles(
+                &lib,
+                &Query {
+                    text: "",
+                    stage: Some(Stage::MINOR),
+                    class: None
+                }
+            ),
+            ["Cambridge Surprise Minor"]
+        );
+        assert!(titles(
+            &lib,
+            &Query {
+                text: "stedman",
+                ..Query::default()
+            }
+        )
+        .is_empty());
+        assert_eq!(lib.search(&Query::default(), 2).len(), 2);
+    }
+
+    /// Library methods whose initial is already a shorthand get the first unused letter instead
+    #[test]
+    fn add_lib_method_shorthand() {
+        let lib = MethodLib::from_xml(XML).unwrap();
+        let query = Query {
+            text: "bristol",
+            ..Query::default()
+        };
+        let bristol = &lib.search(&query, 1)[0];
+        // The example composition already contains Bristol, as well as methods with the
+        // shorthands D, E, Y, K, S and W
+        let mut spec = CompSpec::example();
+        let idx = spec.add_lib_method(bristol).unwrap();
+        assert_eq!(*spec.methods()[idx].shorthand(), "A");
+        let idx = spec.add_lib_method(bristol).unwrap();
+        assert_eq!(*spec.methods()[idx].shorthand(), "C");
+    }
+}
@@ -1,7 +1,16 @@
 //! Representation of musical [`Row`]s
 
-use bellframe::{music::Regex, Stage};
+use std::{
+    fmt::{Display, Formatter},
+    iter::once,
+};
+
+use bellframe::{
+    music::{Regex, RegexElem},
+    Bell, Stage,
+};
 use itertools::Itertools;
+use jigsaw_utils::types::Stroke;
 
 // Imports only used for doc comments
 #[allow(unused_imports)]
@@ -12,28 +21,401 @@ use bellframe::Row;
 pub enum Music {
     /// An optionally named group of musical [`Row`]s, specified by a single [`Regex`] over
     /// [`Row`]s.  This cannot have any sub-groups.
-    Regex(Option<String>, Regex),
+    Regex {
+        name: Option<String>,
+        regex: Regex,
+        /// The score given to each matching [`Row`].  If `None`, this is inherited from the
+        /// enclosing group (or is `1.0` if there isn't one).
+        weight: Option<f32>,
+        /// If set, only [`Row`]s rung at this stroke are counted (e.g. `5678`s are usually only
+        /// counted at backstroke)
+        stroke: Option<Stroke>,
+    },
     /// A named group of sub-groups of musical [`Row`]s
-    Group(String, Vec<Music>),
+    Group {
+        name: String,
+        sub_groups: Vec<Music>,
+        /// The default weight of every sub-group which doesn't specify its own weight.  If
+        /// `None`, this is inherited from the enclosing group (or is `1.0` if there isn't one).
+        weight: Option<f32>,
+        /// If `true`, a [`Row`] matched by several sub-groups is only counted (and scored) once,
+        /// using the largest weight of any sub-group which matches it.
+        unique_rows: bool,
+    },
 }
 
 impl Music {
-    /// Creates a [`Music`] group for
+    /// Creates an unweighted [`Music::Regex`]
+    pub fn regex(name: Option<&str>, regex: Regex) -> Self {
+        Music::Regex {
+            name: name.map(str::to_owned),
+            regex,
+            weight: None,
+            stroke: None,
+        }
+    }
+
+    /// Creates an unweighted [`Music::Group`], which counts every match of its sub-groups
+    pub fn group(name: &str, sub_groups: Vec<Music>) -> Self {
+        Music::Group {
+            name: name.to_owned(),
+            sub_groups,
+            weight: None,
+            unique_rows: false,
+        }
+    }
+
+    /// Creates a [`Music`] group for runs of `len` bells off the front and back of [`Row`]s
     pub fn runs_front_and_back(stage: Stage, len: usize) -> Music {
         let name = format!("{}-bell runs", len);
         let sub_classes = vec![
             Self::group_from_regexes("front", Regex::runs_front(stage, len)),
             Self::group_from_regexes("back", Regex::runs_back(stage, len)),
         ];
-        Music::Group(name, sub_classes)
+        Music::group(&name, sub_classes)
     }
 
     /// Create a [`Music::Group`] containing one unnamed group per [`Regex`] yielded by `regexes`.
     pub fn group_from_regexes(name: &str, regexes: impl IntoIterator<Item = Regex>) -> Self {
         let sub_groups = regexes
             .into_iter()
-            .map(|r| Music::Regex(None, r))
+            .map(|r| Music::regex(None, r))
             .collect_vec();
-        Self::Group(name.to_owned(), sub_groups)
+        Self::group(name, sub_groups)
+    }
+
+    /// Returns a copy of `self` with a given weight
+    pub fn with_weight(mut self, new_weight: f32) -> Self {
+        match &mut self {
+            Music::Regex { weight, .. } | Music::Group { weight, .. } => *weight = Some(new_weight),
+        }
+        self
+    }
+
+    /// Returns a copy of `self` where every [`Regex`] only matches [`Row`]s at a given stroke
+    pub fn with_stroke(self, new_stroke: Stroke) -> Self {
+        match self {
+            Music::Regex {
+                name,
+                regex,
+                weight,
+                stroke: _,
+            } => Music::Regex {
+                name,
+                regex,
+                weight,
+                stroke: Some(new_stroke),
+            },
+            Music::Group {
+                name,
+                sub_groups,
+                weight,
+                unique_rows,
+            } => Music::Group {
+                name,
+                sub_groups: sub_groups
+                    .into_iter()
+                    .map(|m| m.with_stroke(new_stroke))
+                    .collect(),
+                weight,
+                unique_rows,
+            },
+        }
+    }
+}
+
+/////////////
+// PRESETS //
+/////////////
+
+impl Music {
+    /// Creates a [`Music`] group for 56s and 65s (i.e. `5678` or `6578` followed by the rest of
+    /// the bells in order), or `None` if the [`Stage`] has fewer than 8 bells.
+    pub fn fifty_sixes_and_sixty_fives(stage: Stage) -> Option<Music> {
+        let tenors = |first: usize, second: usize| {
+            let bells = IntoIterator::into_iter([first, second]).chain(6..stage.num_bells());
+            Regex::from_elems(once(RegexElem::Glob).chain(bells.map(bell_elem)))
+        };
+        (stage >= Stage::MAJOR).then(|| {
+            Music::group(
+                "56s/65s",
+                vec![
+                    Music::regex(Some("65s"), tenors(5, 4)),
+                    Music::regex(Some("56s"), tenors(4, 5)),
+                ],
+            )
+        })
+    }
+
+    /// Creates a [`Music`] group for combination rollups: rows ending in two of the `4`, `5` and
+    /// `6`, followed by the rest of the bells in order (e.g. `4578` or `6478` on Major).  The
+    /// combinations `56` and `65` aren't included, since those are counted as 56s and 65s.
+    /// Returns `None` if the [`Stage`] has fewer than 8 bells.
+    pub fn crus(stage: Stage) -> Option<Music> {
+        let regexes = [3, 4, 5]
+            .iter()
+            .copied()
+            .permutations(2)
+            .filter(|pair| pair.contains(&3))
+            .map(|pair| {
+                let bells = pair.into_iter().chain(6..stage.num_bells());
+                Regex::from_elems(once(RegexElem::Glob).chain(bells.map(bell_elem)))
+            });
+        (stage >= Stage::MAJOR).then(|| Self::group_from_regexes("CRUs", regexes))
+    }
+
+    /// Creates a [`Music`] group for near misses: rows which are one swap of adjacent bells away
+    /// from rounds
+    pub fn near_misses(stage: Stage) -> Music {
+        let regexes = (0..stage.num_bells().saturating_sub(1)).map(|swap_idx| {
+            let mut bells = (0..stage.num_bells()).collect_vec();
+            bells.swap(swap_idx, swap_idx + 1);
+            exact_row(bells)
+        });
+        Self::group_from_regexes("Near misses", regexes)
+    }
+
+    /// Creates a [`Music`] group for the well-known named rows: queens, kings, tittums,
+    /// whittingtons (on 7 or more bells) and backrounds.  On small [`Stage`]s, some of these rows
+    /// are the same (e.g. queens and tittums on Minimus), so each row is only counted once.
+    pub fn named_rows(stage: Stage) -> Music {
+        let n = stage.num_bells();
+        // Odd and even bells, in ascending order of bell number (treble is index 0, so is odd)
+        let odds = (0..n).step_by(2);
+        let evens = (1..n).step_by(2);
+
+        let queens = odds.clone().chain(evens.clone());
+        let kings = odds.rev().chain(evens);
+        // Interleave the bells from the front and back halves of the row
+        let tittums = (0..n).map(|i| (i % 2) * n.div_ceil(2) + i / 2);
+        let backrounds = (0..n).rev();
+
+        let mut named_rows = vec![
+            Music::regex(Some("Queens"), exact_row(queens)),
+            Music::regex(Some("Kings"), exact_row(kings)),
+            Music::regex(Some("Tittums"), exact_row(tittums)),
+        ];
+        if n >= 7 {
+            // Whittingtons leaves the front `n - 6` bells in rounds, then puts the odd bells of
+            // the back six in descending order before the even bells (e.g. `12753468` on Major)
+            let back_six = n - 6..n;
+            let whittingtons = (0..n - 6)
+                .chain(back_six.clone().rev().filter(|b| b % 2 == 0))
+                .chain(back_six.filter(|b| b % 2 == 1));
+            named_rows.push(Music::regex(Some("Whittingtons"), exact_row(whittingtons)));
+        }
+        named_rows.push(Music::regex(Some("Backrounds"), exact_row(backrounds)));
+
+        Music::Group {
+            name: "Named rows".to_owned(),
+            sub_groups: named_rows,
+            weight: None,
+            unique_rows: true,
+        }
+    }
+
+    /// Creates a [`Music`] group for rows ending with the two largest bells reversed (e.g. `*87`
+    /// on Major).  These are usually considered unmusical, so this group has a weight of `-1`.
+    /// Returns `None` if the [`Stage`] has fewer than 4 bells.
+    pub fn tenors_reversed(stage: Stage) -> Option<Music> {
+        (stage >= Stage::MINIMUS).then(|| {
+            let n = stage.num_bells();
+            let tenors = [n - 1, n - 2].map(bell_elem);
+            let regex = Regex::from_elems(once(RegexElem::Glob).chain(tenors));
+            Music::regex(Some("Tenors reversed"), regex).with_weight(-1.0)
+        })
+    }
+
+    /// Creates a [`Music`] group for runs of `len` little bells (i.e. excluding the two largest
+    /// bells) anywhere in a [`Row`].  Returns `None` if there aren't enough little bells to make
+    /// runs of `len` bells.
+    pub fn little_bell_runs(stage: Stage, len: usize) -> Option<Music> {
+        let num_little_bells = stage.num_bells().saturating_sub(2);
+        if len < 2 || len > num_little_bells {
+            return None;
+        }
+        let regexes = (0..=num_little_bells - len).flat_map(|start| {
+            let run = start..start + len;
+            let surround = |run: Vec<usize>| {
+                let bells = run.into_iter().map(bell_elem);
+                Regex::from_elems(
+                    once(RegexElem::Glob)
+                        .chain(bells)
+                        .chain(once(RegexElem::Glob)),
+                )
+            };
+            vec![
+                surround(run.clone().collect()),
+                surround(run.rev().collect()),
+            ]
+        });
+        let name = format!("{}-bell little bell runs", len);
+        Some(Self::group_from_regexes(&name, regexes))
+    }
+}
+
+/// A [`Regex`] which matches exactly one [`Row`], given by the indices of its [`Bell`]s
+fn exact_row(bell_indices: impl IntoIterator<Item = usize>) -> Regex {
+    Regex::from_elems(bell_indices.into_iter().map(bell_elem))
+}
+
+fn bell_elem(index: usize) -> RegexElem {
+    RegexElem::Bell(Bell::from_index(index))
+}
+
+/// A set of built-in music presets, which can be used as the music of a new composition
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MusicPresets {
+    /// No music at all
+    None,
+    /// Runs off the front and back of every length from 4 bells (or 3 on Minimus) up to one
+    /// fewer than the [`Stage`]
+    Runs,
+    /// [`Runs`](Self::Runs), along with 56s/65s and named rows
+    #[default]
+    Standard,
+    /// [`Standard`](Self::Standard), along with CRUs, near misses, 4-bell little bell runs and a
+    /// penalty for rows with the tenors reversed
+    Full,
+}
+
+impl MusicPresets {
+    /// Every set of presets, in increasing order of size
+    pub const ALL: [MusicPresets; 4] = [
+        MusicPresets::None,
+        MusicPresets::Runs,
+        MusicPresets::Standard,
+        MusicPresets::Full,
+    ];
+
+    /// Generates the [`Music`] in this set of presets for a given [`Stage`].  Presets which don't
+    /// make sense on that [`Stage`] are left out.
+    pub fn music(self, stage: Stage) -> Vec<Music> {
+        let mut music = Vec::new();
+        if self == MusicPresets::None {
+            return music;
+        }
+
+        if self >= MusicPresets::Standard {
+            music.extend(Music::fifty_sixes_and_sixty_fives(stage));
+        }
+        let n = stage.num_bells();
+        for len in 4.min(n.saturating_sub(1)).max(2)..n {
+            music.push(Music::runs_front_and_back(stage, len));
+        }
+        if self >= MusicPresets::Standard {
+            music.push(Music::named_rows(stage));
+        }
+        if self == MusicPresets::Full {
+            music.extend(Music::crus(stage));
+            music.push(Music::near_misses(stage));
+            music.extend(Music::little_bell_runs(stage, 4));
+            music.extend(Music::tenors_reversed(stage));
+        }
+        music
+    }
+}
+
+impl Display for MusicPresets {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            MusicPresets::None => write!(f, "None"),
+            MusicPresets::Runs => write!(f, "Runs"),
+            MusicPresets::Standard => write!(f, "Standard"),
+            MusicPresets::Full => write!(f, "Full"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bellframe::Stage;
+
+    use super::{Music, MusicPresets};
+
+    /// The names and patterns of the regexes directly inside a group
+    fn regexes(group: &Music) -> Vec<(String, String)> {
+        match group {
+            Music::Group { sub_groups, .. } => sub_groups
+                .iter()
+                .map(|m| match m {
+                    Music::Regex { name, regex, .. } => {
+                        (name.clone().unwrap_or_default(), regex.to_string())
+                    }
+                    Music::Group { .. } => panic!("Expected a regex"),
+                })
+                .collect(),
+            Music::Regex { .. } => panic!("Expected a group"),
+        }
+    }
+
+    #[test]
+    fn named_rows() {
+        #[rustfmt::skip]
+        let cases: &[(Stage, &[(&str, &str)])] = &[
+            (Stage::MINIMUS, &[
+                ("Queens", "1324"), ("Kings", "3124"), ("Tittums", "1324"), ("Backrounds", "4321"),
+            ]),
+            (Stage::MINOR, &[
+                ("Queens", "135246"), ("Kings", "531246"), ("Tittums", "142536"),
+                ("Backrounds", "654321"),
+            ]),
+            (Stage::TRIPLES, &[
+                ("Queens", "1357246"), ("Kings", "7531246"), ("Tittums", "1526374"),
+                ("Whittingtons", "1753246"), ("Backrounds", "7654321"),
+            ]),
+            (Stage::MAJOR, &[
+                ("Queens", "13572468"), ("Kings", "75312468"), ("Tittums", "15263748"),
+                ("Whittingtons", "12753468"), ("Backrounds", "87654321"),
+            ]),
+            (Stage::CATERS, &[
+                ("Queens", "135792468"), ("Kings", "975312468"), ("Tittums", "162738495"),
+                ("Whittingtons", "123975468"), ("Backrounds", "987654321"),
+            ]),
+        ];
+        for (stage, exp_rows) in cases {
+            let exp_rows = exp_rows
+                .iter()
+                .map(|(name, row)| (name.to_string(), row.to_string()))
+                .collect::<Vec<_>>();
+            assert_eq!(regexes(&Music::named_rows(*stage)), exp_rows, "{}", stage);
+        }
+    }
+
+    #[test]
+    fn tenors_reversed() {
+        let pattern = |stage| match Music::tenors_reversed(stage) {
+            Some(Music::Regex { regex, weight, .. }) => {
+                assert_eq!(weight, Some(-1.0));
+                Some(regex.to_string())
+            }
+            Some(Music::Group { .. }) => panic!("Expected a regex"),
+            None => None,
+        };
+        assert_eq!(pattern(Stage::ONE), None);
+        assert_eq!(pattern(Stage::SINGLES), None);
+        assert_eq!(pattern(Stage::MINIMUS), Some("*43".to_owned()));
+        assert_eq!(pattern(Stage::MAJOR), Some("*87".to_owned()));
+        assert_eq!(pattern(Stage::MAXIMUS), Some("*TE".to_owned()));
+    }
+
+    #[test]
+    fn presets_on_every_stage() {
+        for n in 1..=16 {
+            let stage = Stage::new(n);
+            let sizes = MusicPresets::ALL
+                .iter()
+                .map(|presets| presets.music(stage).len())
+                .collect::<Vec<_>>();
+            assert_eq!(sizes[0], 0);
+            // Each set of presets contains the ones before it
+            assert!(
+                sizes.windows(2).all(|w| w[0] <= w[1]),
+                "{}: {:?}",
+                stage,
+                sizes
+            );
+        }
+        assert_eq!(MusicPresets::Full.music(Stage::MAJOR).len(), 10);
     }
 }
@@ -0,0 +1,806 @@
+//! A plain-text format for defining [`Music`], so that scoring schemes can be shared between
+//! compositions (and between people).
+//!
+//! Each non-blank line defines one item, and the items of a group are the lines indented beneath
+//! it.  Anything after a `#` is a comment.  For example:
+//! ```text
+//! # Backstroke 56s and 65s are worth double
+//! group "56s/65s" weight=2 stroke=back
+//!     regex *6578 name=65s
+//!     regex *5678 name=56s
+//! preset runs_front_and_back(4)
+//! preset runs_front_and_back(5) weight=2
+//! group "Named rows" unique
+//!     regex 13572468 name=Queens
+//!     regex 87654321 name=Backrounds
+//! ```
+//!
+//! There are three kinds of item:
+//! - `group <name>`: a named group, containing the items indented beneath it.  Takes the options
+//!   `weight`, `stroke` (which applies to every pattern in the group) and the flag `unique` (so
+//!   that rows matched by several sub-groups are only counted once).
+//! - `regex <pattern>`: a single pattern of bells, where `x` matches any one bell and `*` matches
+//!   any number of bells.  Takes the options `name`, `weight` and `stroke`.
+//! - `preset <preset>`: a group generated for the composition's [`Stage`] (see
+//!   [`Music`]'s constructors for details).  This is one of `runs_front_and_back(<len>)`,
+//!   `runs_front(<len>)`, `runs_back(<len>)`, `little_bell_runs(<len>)`, `56s_65s`, `crus`,
+//!   `near_misses`, `named_rows` or `tenors_reversed`.  Takes the options `name`, `weight` and
+//!   `stroke`.
+//!
+//! Names containing spaces can be wrapped in double quotes, and strokes are either `hand` or
+//! `back`.
+
+use std::{
+    fmt::{Display, Formatter},
+    path::Path,
+};
+
+use bellframe::{
+    music::{Regex, RegexElem},
+    Bell, Stage,
+};
+use jigsaw_utils::types::Stroke;
+
+use crate::Music;
+
+/// Reads and parses a music definition file, generating any presets for the given [`Stage`]
+pub fn load(path: &Path, stage: Stage) -> Result<Vec<Music>, LoadError> {
+    let source = std::fs::read_to_string(path).map_err(LoadError::Io)?;
+    parse(&source, stage).map_err(LoadError::Parse)
+}
+
+/// Parses the contents of a music definition file, generating any presets for the given
+/// [`Stage`]
+pub fn parse(source: &str, stage: Stage) -> Result<Vec<Music>, ParseError> {
+    // The groups which are still open, along with the indentation of their contents.  The bottom
+    // of the stack is the top level of the file, which is never indented and has no header.
+    let mut open_blocks = vec![Block {
+        indent: 0,
+        header: None,
+        music: Vec::new(),
+    }];
+    // A group which has just been opened, but whose indentation isn't known until the next line
+    let mut new_group: Option<(usize, GroupHeader)> = None;
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let err = |kind| ParseError {
+            line: line_idx + 1,
+            kind,
+        };
+
+        let tokens = tokenize(line).map_err(err)?;
+        if tokens.is_empty() {
+            continue; // Skip blank lines and comments
+        }
+        let indent = line.len() - line.trim_start().len();
+
+        // Decide which group this line belongs to
+        if let Some((header_indent, header)) = new_group.take() {
+            if indent > header_indent {
+                open_blocks.push(Block {
+                    indent,
+                    header: Some(header),
+                    music: Vec::new(),
+                });
+            } else {
+                // The group has no contents
+                open_blocks
+                    .last_mut()
+                    .unwrap()
+                    .music
+                    .push(header.into_music(vec![]));
+            }
+        }
+        while indent < open_blocks.last().unwrap().indent {
+            close_block(&mut open_blocks);
+        }
+        if indent != open_blocks.last().unwrap().indent {
+            return Err(err(ParseErrorKind::UnexpectedIndent));
+        }
+
+        match parse_item(tokens, stage).map_err(err)? {
+            Item::Group(header) => new_group = Some((indent, header)),
+            Item::Music(music) => open_blocks.last_mut().unwrap().music.push(music),
+        }
+    }
+
+    // Close any groups which are still open at the end of the file
+    if let Some((_, header)) = new_group {
+        open_blocks
+            .last_mut()
+            .unwrap()
+            .music
+            .push(header.into_music(vec![]));
+    }
+    while open_blocks.len() > 1 {
+        close_block(&mut open_blocks);
+    }
+    Ok(open_blocks.pop().unwrap().music)
+}
+
+/// A (possibly unfinished) sequence of items at the same indentation
+struct Block {
+    indent: usize,
+    /// The group which contains this block, or `None` for the top level of the file
+    header: Option<GroupHeader>,
+    music: Vec<Music>,
+}
+
+/// Pops the top [`Block`] off the stack, adding its group to the [`Block`] underneath it
+fn close_block(open_blocks: &mut Vec<Block>) {
+    let block = open_blocks.pop().unwrap();
+    let header = block
+        .header
+        .expect("Can't close the top level of a music file");
+    let group = header.into_music(block.music);
+    open_blocks.last_mut().unwrap().music.push(group);
+}
+
+/// The contents of a `group` line, which is turned into a [`Music::Group`] once its contents are
+/// known
+struct GroupHeader {
+    name: String,
+    weight: Option<f32>,
+    stroke: Option<Stroke>,
+    unique_rows: bool,
+}
+
+impl GroupHeader {
+    fn into_music(self, sub_groups: Vec<Music>) -> Music {
+        let group = Music::Group {
+            name: self.name,
+            sub_groups,
+            weight: self.weight,
+            unique_rows: self.unique_rows,
+        };
+        match self.stroke {
+            Some(stroke) => group.with_stroke(stroke),
+            None => group,
+        }
+    }
+}
+
+///////////
+// ITEMS //
+///////////
+
+/// The result of parsing a single line
+enum Item {
+    /// A `group`, whose contents are given by the following lines
+    Group(GroupHeader),
+    /// A `regex` or `preset`, which is complete in one line
+    Music(Music),
+}
+
+/// The `key=value` options and flags which can follow an item
+#[derive(Default)]
+struct Options {
+    name: Option<String>,
+    weight: Option<f32>,
+    stroke: Option<Stroke>,
+    unique: bool,
+}
+
+fn parse_item(tokens: Vec<Token>, stage: Stage) -> Result<Item, ParseErrorKind> {
+    let mut tokens = tokens.into_iter();
+    let keyword = tokens.next().expect("Items always have at least one token");
+    let item_kind = match keyword.text.as_str() {
+        "group" => "group",
+        "regex" => "regex",
+        "preset" => "preset",
+        _ => return Err(ParseErrorKind::UnknownItem(keyword.text)),
+    };
+
+    // The first token after the keyword is its argument, the rest are options
+    let argument = match tokens.next() {
+        Some(token) if token.equals_idx.is_none() => token.text,
+        _ => return Err(ParseErrorKind::MissingArgument(item_kind)),
+    };
+    let options = parse_options(tokens, item_kind)?;
+    let with_options = |music: Music| {
+        let music = match options.weight {
+            Some(weight) => music.with_weight(weight),
+            None => music,
+        };
+        match options.stroke {
+            Some(stroke) => music.with_stroke(stroke),
+            None => music,
+        }
+    };
+
+    Ok(match item_kind {
+        "group" => Item::Group(GroupHeader {
+            name: argument,
+            weight: options.weight,
+            stroke: options.stroke,
+            unique_rows: options.unique,
+        }),
+        "regex" => {
+            let regex = parse_regex(&argument, stage)?;
+            Item::Music(with_options(Music::regex(options.name.as_deref(), regex)))
+        }
+        _ => {
+            let mut music = parse_preset(&argument, stage)?;
+            // Some presets are groups and some are single regexes, but both can be renamed
+            if let Some(new_name) = &options.name {
+                match &mut music {
+                    Music::Regex { name, .. } => *name = Some(new_name.clone()),
+                    Music::Group { name, .. } => *name = new_name.clone(),
+                }
+            }
+            Item::Music(with_options(music))
+        }
+    })
+}
+
+fn parse_options(
+    tokens: impl IntoIterator<Item = Token>,
+    item_kind: &'static str,
+) -> Result<Options, ParseErrorKind> {
+    let mut options = Options::default();
+    for token in tokens {
+        let (key, value) = match token.equals_idx {
+            Some(idx) => (&token.text[..idx], Some(&token.text[idx + 1..])),
+            None => (token.text.as_str(), None),
+        };
+        let unknown_option = || ParseErrorKind::UnknownOption {
+            item_kind,
+            option: key.to_owned(),
+        };
+        let duplicate_option = || ParseErrorKind::DuplicateOption(key.to_owned());
+
+        match (key, value) {
+            ("name", Some(value)) if item_kind != "group" => {
+                if options.name.replace(value.to_owned()).is_some() {
+                    return Err(duplicate_option());
+                }
+            }
+            ("weight", Some(value)) => {
+                let weight = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|w| w.is_finite())
+                    .ok_or_else(|| ParseErrorKind::InvalidWeight(value.to_owned()))?;
+                if options.weight.replace(weight).is_some() {
+                    return Err(duplicate_option());
+                }
+            }
+            ("stroke", Some(value)) => {
+                let stroke = match value.to_lowercase().as_str() {
+                    "hand" | "handstroke" => Stroke::Hand,
+                    "back" | "backstroke" => Stroke::Back,
+                    _ => return Err(ParseErrorKind::InvalidStroke(value.to_owned())),
+                };
+                if options.stroke.replace(stroke).is_some() {
+                    return Err(duplicate_option());
+                }
+            }
+            ("unique", None) if item_kind == "group" => {
+                if options.unique {
+                    return Err(duplicate_option());
+                }
+                options.unique = true;
+            }
+            _ => return Err(unknown_option()),
+        }
+    }
+    Ok(options)
+}
+
+/// Parses a [`Regex`], checking that every character refers to a unique [`Bell`] in the given
+/// [`Stage`] (unlike [`Regex::parse`], which silently ignores invalid characters)
+pub(crate) fn parse_regex(s: &str, stage: Stage) -> Result<Regex, ParseErrorKind> {
+    let mut elems = Vec::new();
+    for c in s.chars() {
+        let elem = match c {
+            'x' | 'X' | '.' => RegexElem::Any,
+            '*' => RegexElem::Glob,
+            _ => match Bell::from_name(c) {
+                Some(bell) if bell.index() < stage.num_bells() => {
+                    if elems.contains(&RegexElem::Bell(bell)) {
+                        return Err(ParseErrorKind::RepeatedBell {
+                            regex: s.to_owned(),
+                            bell,
+                        });
+                    }
+                    RegexElem::Bell(bell)
+                }
+                _ => {
+                    return Err(ParseErrorKind::InvalidBell {
+                        regex: s.to_owned(),
+                        c,
+                        stage,
+                    })
+                }
+            },
+        };
+        elems.push(elem);
+    }
+    // Every element other than `*` takes up exactly one place, so they have to fit in the stage
+    let num_places = elems.iter().filter(|e| **e != RegexElem::Glob).count();
+    if num_places > stage.num_bells() {
+        return Err(ParseErrorKind::RegexTooLong {
+            regex: s.to_owned(),
+            stage,
+        });
+    }
+    Ok(Regex::from_elems(elems))
+}
+
+/// Parses and generates a preset of the form `<name>` or `<name>(<len>)`
+fn parse_preset(s: &str, stage: Stage) -> Result<Music, ParseErrorKind> {
+    let invalid_preset = || ParseErrorKind::InvalidPreset(s.to_owned());
+    let (name, len) = match s.split_once('(') {
+        Some((name, args)) => {
+            let len = args
+                .strip_suffix(')')
+                .and_then(|len| len.trim().parse::<usize>().ok())
+                .ok_or_else(invalid_preset)?;
+            (name.trim(), Some(len))
+        }
+        None => (s.trim(), None),
+    };
+
+    // Runs must be at least two bells long, and must fit within the stage
+    let run_len = len.filter(|len| (2..=stage.num_bells()).contains(len));
+    let music = match (name, len) {
+        ("runs_front_and_back", Some(_)) => {
+            run_len.map(|len| Music::runs_front_and_back(stage, len))
+        }
+        ("runs_front", Some(_)) => run_len.map(|len| {
+            let name = format!("{}-bell runs off the front", len);
+            Music::group_from_regexes(&name, Regex::runs_front(stage, len))
+        }),
+        ("runs_back", Some(_)) => run_len.map(|len| {
+            let name = format!("{}-bell runs off the back", len);
+            Music::group_from_regexes(&name, Regex::runs_back(stage, len))
+        }),
+        ("little_bell_runs", Some(len)) => Music::little_bell_runs(stage, len),
+        ("56s_65s", None) => Music::fifty_sixes_and_sixty_fives(stage),
+        ("crus", None) => Music::crus(stage),
+        ("near_misses", None) => Some(Music::near_misses(stage)),
+        ("named_rows", None) => Some(Music::named_rows(stage)),
+        ("tenors_reversed", None) => Music::tenors_reversed(stage),
+        // Known presets with the wrong number of arguments
+        (
+            "runs_front_and_back"
+            | "runs_front"
+            | "runs_back"
+            | "little_bell_runs"
+            | "56s_65s"
+            | "crus"
+            | "near_misses"
+            | "named_rows"
+            | "tenors_reversed",
+            _,
+        ) => return Err(invalid_preset()),
+        _ => return Err(ParseErrorKind::UnknownPreset(name.to_owned())),
+    };
+    music.ok_or_else(|| ParseErrorKind::PresetDoesntFit {
+        preset: s.to_owned(),
+        stage,
+    })
+}
+
+////////////
+// TOKENS //
+////////////
+
+/// A single whitespace-separated token of a line, with any quotes removed
+struct Token {
+    text: String,
+    /// The byte index within `text` of the first `=` which wasn't in quotes, if this token is a
+    /// `key=value` option
+    equals_idx: Option<usize>,
+}
+
+/// Splits a line into [`Token`]s, ignoring any comment
+fn tokenize(line: &str) -> Result<Vec<Token>, ParseErrorKind> {
+    let mut tokens = Vec::new();
+    let mut current: Option<Token> = None;
+    let mut in_quotes = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                // Make sure that `""` creates an empty token
+                current.get_or_insert_with(Token::empty);
+            }
+            _ if in_quotes => current.get_or_insert_with(Token::empty).text.push(c),
+            '#' => break,
+            _ if c.is_whitespace() => tokens.extend(current.take()),
+            _ => {
+                let token = current.get_or_insert_with(Token::empty);
+                if c == '=' && token.equals_idx.is_none() {
+                    token.equals_idx = Some(token.text.len());
+                }
+                token.text.push(c);
+            }
+        }
+    }
+    if in_quotes {
+        return Err(ParseErrorKind::UnterminatedString);
+    }
+    tokens.extend(current);
+    Ok(tokens)
+}
+
+impl Token {
+    fn empty() -> Self {
+        Self {
+            text: String::new(),
+            equals_idx: None,
+        }
+    }
+}
+
+/////////////////
+// ERROR TYPES //
+/////////////////
+
+/// The ways that loading a music definition file can fail
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file couldn't be read
+    Io(std::io::Error),
+    Parse(ParseError),
+}
+
+/// The ways that parsing a music definition file can fail, along with the line which caused the
+/// problem
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The 1-indexed line number of the offending line
+    pub line: usize,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    /// A `"` was never closed
+    UnterminatedString,
+    /// The line isn't indented to the same level as any enclosing group
+    UnexpectedIndent,
+    /// The line didn't start with `group`, `regex` or `preset`
+    UnknownItem(String),
+    /// An item was missing its name/pattern/preset
+    MissingArgument(&'static str),
+    UnknownOption {
+        item_kind: &'static str,
+        option: String,
+    },
+    DuplicateOption(String),
+    InvalidWeight(String),
+    InvalidStroke(String),
+    /// A regex contains a character which isn't a bell in the composition's [`Stage`]
+    InvalidBell {
+        regex: String,
+        c: char,
+        stage: Stage,
+    },
+    RepeatedBell {
+        regex: String,
+        bell: Bell,
+    },
+    /// A regex has more bells and `x`s than the composition's [`Stage`]
+    RegexTooLong {
+        regex: String,
+        stage: Stage,
+    },
+    /// A preset had the wrong arguments for its name
+    InvalidPreset(String),
+    UnknownPreset(String),
+    /// A preset can't be generated for the composition's [`Stage`] (e.g. 56s on Minor, or runs
+    /// which are longer than the stage)
+    PresetDoesntFit {
+        preset: String,
+        stage: Stage,
+    },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "Couldn't read music file: {}", e),
+            LoadError::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.kind)
+    }
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ParseErrorKind::UnterminatedString => write!(f, "Unterminated string"),
+            ParseErrorKind::UnexpectedIndent => {
+                write!(f, "Indentation doesn't match any enclosing group")
+            }
+            ParseErrorKind::UnknownItem(item) => write!(
+                f,
+                "Unknown item '{}' (expected 'group', 'regex' or 'preset')",
+                item
+            ),
+            ParseErrorKind::MissingArgument(item_kind) => {
+                let argument = match *item_kind {
+                    "group" => "name",
+                    "regex" => "pattern",
+                    _ => "preset",
+                };
+                write!(f, "'{}' needs a {}", item_kind, argument)
+            }
+            ParseErrorKind::UnknownOption { item_kind, option } => {
+                write!(f, "'{}' has no option '{}'", item_kind, option)
+            }
+            ParseErrorKind::DuplicateOption(option) => {
+                write!(f, "Option '{}' is given twice", option)
+            }
+            ParseErrorKind::InvalidWeight(weight) => write!(f, "Invalid weight '{}'", weight),
+            ParseErrorKind::InvalidStroke(stroke) => {
+                write!(f, "Invalid stroke '{}' (expected 'hand' or 'back')", stroke)
+            }
+            ParseErrorKind::InvalidBell { regex, c, stage } => write!(
+                f,
+                "'{}' in '{}' isn't a bell on {} bells",
+                c,
+                regex,
+                stage.num_bells()
+            ),
+            ParseErrorKind::RepeatedBell { regex, bell } => {
+                write!(f, "Bell '{}' appears twice in '{}'", bell, regex)
+            }
+            ParseErrorKind::RegexTooLong { regex, stage } => {
+                write!(f, "'{}' has more than {} bells", regex, stage.num_bells())
+            }
+            ParseErrorKind::InvalidPreset(preset) => write!(f, "Invalid preset '{}'", preset),
+            ParseErrorKind::UnknownPreset(preset) => write!(f, "Unknown preset '{}'", preset),
+            ParseErrorKind::PresetDoesntFit { preset, stage } => write!(
+                f,
+                "Preset '{}' can't be used on {} bells",
+                preset,
+                stage.num_bells()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use bellframe::Stage;
+
+    use super::{parse, ParseErrorKind};
+    use crate::Music;
+
+    /// Summarise a tree of [`Music`] as a string, so that it can be easily compared
+    fn summary(music: &[Music]) -> String {
+        music
+            .iter()
+            .map(|m| match m {
+                Music::Regex {
+                    name,
+                    regex,
+                    weight,
+                    stroke,
+                } => format!("{}:{:?}:{:?}:{:?}", regex, name, weight, stroke),
+                Music::Group {
+                    name,
+                    sub_groups,
+                    weight,
+                    unique_rows,
+                } => format!(
+                    "{}:{:?}:{}({})",
+                    name,
+                    weight,
+                    unique_rows,
+                    summary(sub_groups)
+                ),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn parse_summary(source: &str) -> String {
+        summary(&parse(source, Stage::MAJOR).unwrap())
+    }
+
+    #[test]
+    fn regexes() {
+        assert_eq!(parse_summary(""), "");
+        assert_eq!(
+            parse_summary("regex *5678 # a comment\n\n  # indented comment\nregex 1x3*"),
+            "*5678:None:None:None, 1x3*:None:None:None"
+        );
+        assert_eq!(
+            parse_summary(r#"regex *6578 name="Sixty fives" weight=2.5 stroke=back"#),
+            r#"*6578:Some("Sixty fives"):Some(2.5):Some(Back)"#
+        );
+        assert_eq!(
+            parse_summary("regex *6578 stroke=Handstroke"),
+            "*6578:None:None:Some(Hand)"
+        );
+    }
+
+    #[test]
+    fn groups() {
+        // Groups pass their stroke down to their contents, but keep their own weight
+        let source = r#"
+group "56s/65s" weight=2 stroke=back unique
+    regex *6578 name=65s
+    group inner
+        regex *5678
+
+    regex 1234* stroke=hand
+group empty
+regex *78
+"#;
+        assert_eq!(
+            parse_summary(source),
+            concat!(
+                r#"56s/65s:Some(2.0):true(*6578:Some("65s"):None:Some(Back), "#,
+                r#"inner:None:false(*5678:None:None:Some(Back)), "#,
+                r#"1234*:None:None:Some(Back)), "#,
+                "empty:None:false(), ",
+                "*78:None:None:None"
+            )
+        );
+        // An empty group at the end of the file, and a tab-indented group
+        assert_eq!(
+            parse_summary("group a\n\tregex *78\ngroup b"),
+            "a:None:false(*78:None:None:None), b:None:false()"
+        );
+    }
+
+    #[test]
+    fn presets() {
+        let summary = parse_summary("preset runs_front(4) name=Runs weight=0.5 stroke=hand");
+        assert!(summary.starts_with("Runs:Some(0.5):false(1234*:None:None:Some(Hand), "));
+        let num_regexes = |source: &str| match &parse(source, Stage::MAJOR).unwrap()[..] {
+            [Music::Group { sub_groups, .. }] => sub_groups.len(),
+            _ => panic!("Expected one group"),
+        };
+        assert_eq!(num_regexes("preset \"runs_front( 4 )\""), 10);
+        assert_eq!(num_regexes("preset runs_back(8)"), 2);
+        assert_eq!(num_regexes("preset runs_front_and_back(5)"), 2);
+        assert_eq!(num_regexes("preset crus"), 4);
+        assert_eq!(num_regexes("preset near_misses"), 7);
+        for preset in [
+            "56s_65s",
+            "named_rows",
+            "tenors_reversed",
+            "little_bell_runs(4)",
+        ] {
+            assert!(parse(&format!("preset {}", preset), Stage::MAJOR).is_ok());
+        }
+        // Presets which are a single regex can also be renamed
+        assert_eq!(
+            parse_summary("preset tenors_reversed name=\"Tenors backwards\""),
+            r#"*87:Some("Tenors backwards"):Some(-1.0):None"#
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let err = |source: &str, stage: Stage| {
+            let e = parse(source, stage).unwrap_err();
+            (e.line, e.kind)
+        };
+        let major_err = |source: &str| err(source, Stage::MAJOR);
+
+        assert_eq!(
+            major_err("regex *5678\nregex \"*78"),
+            (2, ParseErrorKind::UnterminatedString)
+        );
+        assert_eq!(
+            major_err("group a\n    regex *78\n  regex *87"),
+            (3, ParseErrorKind::UnexpectedIndent)
+        );
+        assert_eq!(
+            major_err("  regex *78"),
+            (1, ParseErrorKind::UnexpectedIndent)
+        );
+        assert_eq!(
+            major_err("\n\nrun *78"),
+            (3, ParseErrorKind::UnknownItem("run".to_owned()))
+        );
+        assert_eq!(
+            major_err("regex"),
+            (1, ParseErrorKind::MissingArgument("regex"))
+        );
+        assert_eq!(
+            major_err("group a name=b"),
+            (
+                1,
+                ParseErrorKind::UnknownOption {
+                    item_kind: "group",
+                    option: "name".to_owned()
+                }
+            )
+        );
+        assert_eq!(
+            major_err("regex *78 unique"),
+            (
+                1,
+                ParseErrorKind::UnknownOption {
+                    item_kind: "regex",
+                    option: "unique".to_owned()
+                }
+            )
+        );
+        assert_eq!(
+            major_err("regex *78 weight=1 weight=2"),
+            (1, ParseErrorKind::DuplicateOption("weight".to_owned()))
+        );
+        assert_eq!(
+            major_err("regex *78 weight=inf"),
+            (1, ParseErrorKind::InvalidWeight("inf".to_owned()))
+        );
+        assert_eq!(
+            major_err("regex *78 stroke=both"),
+            (1, ParseErrorKind::InvalidStroke("both".to_owned()))
+        );
+        assert_eq!(
+            major_err("regex *79"),
+            (
+                1,
+                ParseErrorKind::InvalidBell {
+                    regex: "*79".to_owned(),
+                    c: '9',
+                    stage: Stage::MAJOR
+                }
+            )
+        );
+        assert!(matches!(
+            major_err("regex *878"),
+            (1, ParseErrorKind::RepeatedBell { .. })
+        ));
+        assert_eq!(
+            major_err("regex xxxxxxxxx"),
+            (
+                1,
+                ParseErrorKind::RegexTooLong {
+                    regex: "xxxxxxxxx".to_owned(),
+                    stage: Stage::MAJOR
+                }
+            )
+        );
+        assert!(matches!(
+            err("regex 1234567*x", Stage::TRIPLES),
+            (1, ParseErrorKind::RegexTooLong { .. })
+        ));
+        // Globs don't take up any places
+        assert!(parse("regex *12345678*", Stage::MAJOR).is_ok());
+        assert_eq!(
+            major_err("preset runs_front"),
+            (1, ParseErrorKind::InvalidPreset("runs_front".to_owned()))
+        );
+        assert_eq!(
+            major_err("preset crus(4)"),
+            (1, ParseErrorKind::InvalidPreset("crus(4)".to_owned()))
+        );
+        assert_eq!(
+            major_err("preset runs"),
+            (1, ParseErrorKind::UnknownPreset("runs".to_owned()))
+        );
+        assert_eq!(
+            major_err("preset runs_front(9)"),
+            (
+                1,
+                ParseErrorKind::PresetDoesntFit {
+                    preset: "runs_front(9)".to_owned(),
+                    stage: Stage::MAJOR
+                }
+            )
+        );
+        assert!(matches!(
+            err("preset 56s_65s", Stage::MINOR),
+            (1, ParseErrorKind::PresetDoesntFit { .. })
+        ));
+    }
+}
@@ -1,28 +1,38 @@
 pub mod part_heads;
+mod ruleoffs;
+mod save_load;
+
+pub use ruleoffs::RuleoffPreset;
+pub use save_load::{InvalidChunkReason, LoadError};
 
 use std::{
-    cell::{Cell, Ref, RefCell},
-    collections::HashSet,
+    cell::{Ref, RefCell},
+    collections::BTreeSet,
     convert::{TryFrom, TryInto},
-    ops::Deref,
+    ops::{Deref, Range},
+    path::{Path, PathBuf},
     rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use bellframe::{
-    music::Regex, row::RowAccumulator, AnnotBlock, IncompatibleStages, Row, RowBuf, Stage,
+    music::Regex, place_not::PnBlockParseError, row::RowAccumulator, AnnotBlock, Bell, Block,
+    IncompatibleStages, PnBlock, Row, RowBuf, Stage,
 };
 use emath::Pos2;
 use index_vec::index_vec;
 use jigsaw_utils::indexed_vec::{
-    ChunkIdx, ChunkVec, FragIdx, FragVec, MethodSlice, MethodVec, RowIdx, RowVec,
+    CallIdx, CallSlice, CallVec, ChunkIdx, ChunkVec, FragIdx, FragSlice, FragVec, MethodIdx,
+    MethodSlice, MethodVec, RowIdx, RowVec,
 };
 
 use crate::{
     expanded_frag::{ExpandedFrag, RowData},
-    Music,
+    method_lib::LibMethod,
+    Music, MusicPresets,
 };
 
-use self::part_heads::PartHeads;
+use self::{part_heads::PartHeads, ruleoffs::Ruleoffs};
 
 /// The minimal but complete specification for a (partial) composition.  `CompSpec` is used for
 /// undo history, and is designed to be a very compact representation which is cheap to clone and
@@ -35,9 +45,13 @@ pub struct CompSpec {
     fragments: FragVec<Rc<Fragment>>,
     part_heads: Rc<PartHeads>,
     methods: MethodVec<Rc<Method>>,
-    calls: Vec<Rc<Call>>,
+    calls: CallVec<Rc<Call>>,
     // TODO: Make this structure use `Rc`s internally
     music: Rc<Vec<Music>>,
+    /// The music definition file which `music` was loaded from, exactly as it's written in the
+    /// composition file (so it may be relative to the composition file).  If this is `None`, then
+    /// `music` is embedded in the composition file.
+    music_file: Option<PathBuf>,
     stage: Stage,
 }
 
@@ -47,16 +61,17 @@ impl CompSpec {
     // CONSTRUCTORS //
     //////////////////
 
-    /// Creates a [`CompSpec`] with a given [`Stage`] but no [`PartHeads`], [`Method`]s, [`Call`]s
-    /// or [`Fragment`]s.
-    #[allow(dead_code)]
-    pub fn empty(stage: Stage) -> Self {
+    /// Creates a [`CompSpec`] with a given [`Stage`] but no [`PartHeads`], [`Method`]s or
+    /// [`Fragment`]s.  The [`Call`]s are the [default calls](Call::defaults) for that [`Stage`],
+    /// and the [`Music`] is generated from a set of [`MusicPresets`].
+    pub fn empty(stage: Stage, music_presets: MusicPresets) -> Self {
         CompSpec {
             fragments: index_vec![],
             part_heads: Rc::new(PartHeads::one_part(stage)),
             methods: index_vec![],
-            calls: vec![],
-            music: Rc::new(vec![]),
+            calls: Call::defaults(stage).into_iter().map(Rc::new).collect(),
+            music: Rc::new(music_presets.music(stage)),
+            music_file: None,
             stage,
         }
     }
@@ -67,11 +82,14 @@ impl CompSpec {
 
         /// Create a new [`Method`] by parsing a string of place notation
         fn gen_method(shorthand: &str, name: &str, pn_str: &str) -> Rc<Method> {
-            let method = Method::with_lead_end_ruleoff(
-                bellframe::Method::from_place_not_string(String::new(), STAGE, pn_str).unwrap(),
+            let method = Method::with_ruleoff_preset(
                 name.to_owned(),
                 shorthand.to_string(),
-            );
+                pn_str,
+                STAGE,
+                RuleoffPreset::LeadEnd,
+            )
+            .unwrap();
             Rc::new(method)
         }
 
@@ -97,7 +115,14 @@ impl CompSpec {
             })
             .collect::<ChunkVec<_>>();
 
+        // Near lead end calls
+        let calls = index_vec![
+            /* 0. */ Rc::new(Call::near_bob(STAGE).unwrap()),
+            /* 1. */ Rc::new(Call::near_single(STAGE).unwrap()),
+        ];
+
         let fragment = Fragment {
+            id: FragId::new(),
             position: Pos2::new(200.0, 100.0),
             start_row: Rc::new(RowBuf::rounds(STAGE)),
             chunks,
@@ -105,19 +130,19 @@ impl CompSpec {
         };
 
         let music = Rc::new(vec![
-            Music::Group(
-                "56s/65s".to_owned(),
+            Music::group(
+                "56s/65s",
                 vec![
-                    Music::Regex(Some("65s".to_owned()), Regex::parse("*6578")),
-                    Music::Regex(Some("56s".to_owned()), Regex::parse("*5678")),
+                    Music::regex(Some("65s"), Regex::parse("*6578")),
+                    Music::regex(Some("56s"), Regex::parse("*5678")),
                 ],
             ),
             Music::runs_front_and_back(Stage::MAJOR, 4),
             Music::runs_front_and_back(Stage::MAJOR, 5),
             Music::runs_front_and_back(Stage::MAJOR, 6),
             Music::runs_front_and_back(Stage::MAJOR, 7),
-            Music::Regex(Some("Queens".to_owned()), Regex::parse("13572468")),
-            Music::Regex(Some("Backrounds".to_owned()), Regex::parse("87654321")),
+            Music::regex(Some("Queens"), Regex::parse("13572468")),
+            Music::regex(Some("Backrounds"), Regex::parse("87654321")),
         ]);
 
         CompSpec {
@@ -126,8 +151,9 @@ impl CompSpec {
                 PartHeads::parse("18234567", STAGE).unwrap(), /* PartHeads::one_part(STAGE) */
             ),
             methods,
-            calls: vec![], // No calls for now
+            calls,
             music,
+            music_file: None,
             stage: STAGE,
         }
     }
@@ -136,11 +162,8 @@ impl CompSpec {
     // GETTERS/EXPANSION CODE //
     ////////////////////////////
 
-    pub(crate) fn expand_fragments(&self) -> FragVec<ExpandedFrag> {
-        self.fragments
-            .iter()
-            .map(|f| f.expand(&self.part_heads))
-            .collect()
+    pub(crate) fn fragments(&self) -> &FragSlice<Rc<Fragment>> {
+        &self.fragments
     }
 
     pub(crate) fn part_heads(&self) -> &Rc<PartHeads> {
@@ -151,7 +174,11 @@ impl CompSpec {
         &self.methods
     }
 
-    pub(crate) fn music(&self) -> &[Music] {
+    pub(crate) fn calls(&self) -> &CallSlice<Rc<Call>> {
+        &self.calls
+    }
+
+    pub(crate) fn music(&self) -> &Rc<Vec<Music>> {
         &self.music
     }
 
@@ -159,6 +186,13 @@ impl CompSpec {
         self.stage
     }
 
+    /// The [music definition file](crate::music_file) which this composition's [`Music`] was
+    /// loaded from (relative to the composition file), or `None` if the [`Music`] is embedded in
+    /// the composition file.
+    pub fn music_file(&self) -> Option<&Path> {
+        self.music_file.as_deref()
+    }
+
     /////////////////////////
     // MODIFIERS & ACTIONS //
     /////////////////////////
@@ -175,6 +209,20 @@ impl CompSpec {
         self.part_heads = Rc::new(part_heads);
     }
 
+    /// Overwrites the [`Music`] of `self`, along with the [music definition
+    /// file](crate::music_file) that it was loaded from.  If `music_file` is `None`, then the
+    /// [`Music`] will be embedded in the composition file when it's saved.
+    pub fn set_music(&mut self, music: Vec<Music>, music_file: Option<PathBuf>) {
+        self.music = Rc::new(music);
+        self.music_file = music_file;
+    }
+
+    /// Stop referencing a music definition file, so that the current [`Music`] is embedded in the
+    /// composition file when it's saved.
+    pub fn embed_music(&mut self) {
+        self.music_file = None;
+    }
+
     /// Solo a single [`Fragment`], or unmute everything if this is the only unmuted [`Fragment`].
     pub fn solo_frag(&mut self, frag_idx: FragIdx) -> Result<(), EditError> {
         /// Helper function to set `f.is_proved`, without cloning any fragments which don't need to
@@ -206,6 +254,117 @@ impl CompSpec {
         Ok(())
     }
 
+    /// Adds a new [`Fragment`] containing plain leads of a given [`Method`], returning the
+    /// [`FragIdx`] of the new [`Fragment`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the [`Stage`]s of `self` and `start_row` don't match
+    pub fn add_fragment(
+        &mut self,
+        position: Pos2,
+        start_row: RowBuf,
+        method_idx: MethodIdx,
+        length: FragLength,
+    ) -> Result<FragIdx, EditError> {
+        assert_eq!(self.stage, start_row.stage());
+        let method = self.get_method(method_idx)?;
+        let chunk = Chunk::method(method.clone(), 0, length.num_rows(method)?);
+        // Add the new fragment to the end of the list, so it's drawn on top of the others
+        Ok(self.fragments.push(Rc::new(Fragment {
+            id: FragId::new(),
+            position,
+            start_row: Rc::new(start_row),
+            chunks: index_vec![Rc::new(chunk)],
+            is_proved: true,
+        })))
+    }
+
+    /// Adds rows of a given [`Method`] to the end of a [`Fragment`].  If the [`Fragment`] already
+    /// finishes with the same [`Method`], then the new rows continue from wherever that
+    /// [`Method`] finished.  Otherwise, the new rows start at a lead head.
+    pub fn extend_fragment(
+        &mut self,
+        frag_idx: FragIdx,
+        method_idx: MethodIdx,
+        length: FragLength,
+    ) -> Result<(), EditError> {
+        let method = self.get_method(method_idx)?.clone();
+        let num_rows = length.num_rows(&method)?;
+        self.get_fragment_mut(frag_idx)?.extend(method, num_rows);
+        Ok(())
+    }
+
+    /// Joins the [`Fragment`] at `bottom` onto the end of the [`Fragment`] at `top`, transposing
+    /// `bottom` so that it starts from the leftover row of `top`.  This is only possible if the
+    /// leftover row of `top` and the first row of `bottom` are equivalent under the
+    /// [`PartHeads`].  The joined [`Fragment`] takes the place and properties of `top`, and
+    /// `bottom` is removed.
+    pub fn join_fragments(&mut self, top: FragIdx, bottom: FragIdx) -> Result<(), EditError> {
+        if top == bottom {
+            return Err(EditError::JoinSameFragment(top));
+        }
+        let top_frag = self.get_fragment(top)?;
+        let bottom_frag = self.get_fragment(bottom)?;
+        let is_linkable = self
+            .part_heads
+            .are_equivalent(&top_frag.leftover_row(), &bottom_frag.start_row)
+            .unwrap(); // All rows in a `CompSpec` have the same `Stage`
+        if !is_linkable {
+            return Err(EditError::NotLinkable { top, bottom });
+        }
+
+        // Because chunks are stored relative to the start row of their fragment, joining is just
+        // a case of concatenating the chunks
+        let bottom_frag = self.fragments.remove(bottom);
+        let top = if bottom < top { top - 1 } else { top };
+        let top_frag = self.get_fragment_mut(top)?;
+        top_frag.id = FragId::new();
+        top_frag.chunks.extend(bottom_frag.chunks.iter().cloned());
+        top_frag.merge_chunks();
+        Ok(())
+    }
+
+    /// Transposes a [`Fragment`] so that the [`Row`] at `row_idx` becomes `new_row`.  `row_idx`
+    /// can refer to the leftover row.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the [`Stage`]s of `self` and `new_row` don't match
+    pub fn transpose_fragment(
+        &mut self,
+        frag_idx: FragIdx,
+        row_idx: isize,
+        new_row: &Row,
+    ) -> Result<(), EditError> {
+        assert_eq!(self.stage, new_row.stage());
+        self.get_fragment_mut(frag_idx)?
+            .transpose_row_to(frag_idx, row_idx, new_row)
+    }
+
+    /// Creates a copy of a [`Fragment`] at a new on-screen position, returning the [`FragIdx`] of
+    /// the copy.  The copy shares its [`Chunk`]s with the original.
+    pub fn duplicate_fragment(
+        &mut self,
+        frag_idx: FragIdx,
+        new_position: Pos2,
+    ) -> Result<FragIdx, EditError> {
+        let mut new_frag = self.get_fragment(frag_idx)?.clone();
+        new_frag.id = FragId::new();
+        new_frag.position = new_position;
+        Ok(self.fragments.push(Rc::new(new_frag)))
+    }
+
+    /// Moves a [`Fragment`] to a new on-screen position
+    pub fn move_fragment(
+        &mut self,
+        frag_idx: FragIdx,
+        new_position: Pos2,
+    ) -> Result<(), EditError> {
+        self.get_fragment_mut(frag_idx)?.position = new_position;
+        Ok(())
+    }
+
     /// Deletes the [`Fragment`] with a given [`FragIdx`]
     pub fn delete_fragment(&mut self, frag_idx: FragIdx) -> Result<(), EditError> {
         self.get_fragment(frag_idx)?; // Return error if `frag_idx` is out-of-bounds
@@ -226,6 +385,183 @@ impl CompSpec {
         Ok(())
     }
 
+    /// Sets, changes or removes the [`Call`] starting at a given row of a [`Fragment`].  Calls
+    /// can only be added in place of plain [`Method`] rows (e.g. a lead end bob can only be added
+    /// at a lead end).
+    pub fn set_call(
+        &mut self,
+        frag_idx: FragIdx,
+        row_idx: isize,
+        call_idx: Option<CallIdx>,
+    ) -> Result<(), EditError> {
+        let call = call_idx.map(|idx| self.get_call(idx)).transpose()?;
+        self.get_fragment_mut(frag_idx)?
+            .set_call(frag_idx, row_idx, call)
+    }
+
+    /// Adds a new [`Method`] to the composition, with rule-offs placed according to a
+    /// [`RuleoffPreset`].  Returns the [`MethodIdx`] of the new [`Method`].
+    pub fn add_method(
+        &mut self,
+        name: String,
+        shorthand: String,
+        place_notation: &str,
+        ruleoffs: RuleoffPreset,
+    ) -> Result<MethodIdx, EditError> {
+        self.check_shorthand(&shorthand, None)?;
+        let method =
+            Method::with_ruleoff_preset(name, shorthand, place_notation, self.stage, ruleoffs)
+                .map_err(EditError::PlaceNotation)?;
+        Ok(self.methods.push(Rc::new(method)))
+    }
+
+    /// Adds a [`Method`] from a [`MethodLib`](crate::method_lib::MethodLib) to the composition,
+    /// with a rule-off at every lead end.  If the first letter of its name is already used as a
+    /// shorthand, then the method is given the first unused letter instead.  Returns the [`MethodIdx`] of the new [`Method`].
+    pub fn add_lib_method(&mut self, lib_method: &LibMethod) -> Result<MethodIdx, EditError> {
+        if lib_method.stage != self.stage {
+            return Err(EditError::WrongStage {
+                comp_stage: self.stage,
+                method_stage: lib_method.stage,
+            });
+        }
+        // Use the first letter of the method's name, unless another method already has it
+        let shorthand = std::iter::once(lib_method.default_shorthand())
+            .chain(('A'..='Z').map(String::from))
+            .find(|s| self.check_shorthand(s, None).is_ok())
+            .ok_or(EditError::NoFreeShorthand)?;
+        self.add_method(
+            lib_method.name.clone(),
+            shorthand,
+            &lib_method.place_notation,
+            RuleoffPreset::LeadEnd,
+        )
+    }
+
+    /// Changes the place notation and rule-offs of a [`Method`], re-expanding every [`Chunk`]
+    /// which uses it.  If `ruleoffs` is `None`, then the existing rule-offs are kept (or reset to
+    /// lead end rule-offs if the lead length changes).  If the lead length doesn't change, then
+    /// the positions of [`Chunk`]s within the lead are kept; otherwise [`Call`]s are moved to
+    /// their usual place in the new lead and other [`Chunk`]s are wrapped into the new lead.
+    pub fn edit_method(
+        &mut self,
+        method_idx: MethodIdx,
+        place_notation: &str,
+        ruleoffs: Option<RuleoffPreset>,
+    ) -> Result<(), EditError> {
+        let old_method = self.get_method(method_idx)?.clone();
+        let mut new_method = Method::new(
+            old_method.name().clone(),
+            old_method.shorthand().clone(),
+            place_notation,
+            self.stage,
+            old_method.ruleoffs_above.clone(),
+        )
+        .map_err(EditError::PlaceNotation)?;
+        let is_same_lead_len = new_method.lead_len() == old_method.lead_len();
+        match ruleoffs {
+            Some(preset) => new_method.set_ruleoff_preset(preset),
+            None if !is_same_lead_len => new_method.set_ruleoff_preset(RuleoffPreset::LeadEnd),
+            None => {} // Keep the old rule-offs
+        }
+        let new_method = Rc::new(new_method);
+
+        // Replace the method in every fragment which uses it
+        for frag in self.fragments.iter_mut() {
+            if frag.chunks.iter().any(|c| c.uses_method(&old_method)) {
+                Rc::make_mut(frag).replace_method(&old_method, &new_method);
+            }
+        }
+        self.methods[method_idx] = new_method;
+        Ok(())
+    }
+
+    /// Sets the name and shorthand of a [`Method`].  Method names aren't part of the undo
+    /// history, so this only needs `&self`.
+    pub fn set_method_names(
+        &self,
+        method_idx: MethodIdx,
+        name: String,
+        shorthand: String,
+    ) -> Result<(), EditError> {
+        let method = self.get_method(method_idx)?;
+        self.check_shorthand(&shorthand, Some(method_idx))?;
+        *method.name.borrow_mut() = name;
+        *method.shorthand.borrow_mut() = shorthand;
+        Ok(())
+    }
+
+    /// Checks that `shorthand` can be given to a [`Method`].  Shorthands are typed to extend
+    /// fragments, so each one must be a single letter which isn't the shorthand of any other
+    /// [`Method`] (ignoring case).  `method_idx` is the [`Method`] being given the shorthand, if
+    /// it's already in the composition.
+    pub fn check_shorthand(
+        &self,
+        shorthand: &str,
+        method_idx: Option<MethodIdx>,
+    ) -> Result<(), EditError> {
+        let mut chars = shorthand.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => {}
+            _ => return Err(EditError::InvalidShorthand(shorthand.to_owned())),
+        }
+        let clashing_method = self.methods.iter_enumerated().find(|(idx, m)| {
+            Some(*idx) != method_idx && m.shorthand().eq_ignore_ascii_case(shorthand)
+        });
+        match clashing_method {
+            Some((idx, _)) => Err(EditError::ShorthandUsed {
+                shorthand: shorthand.to_owned(),
+                method_idx: idx,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes a [`Method`] from the composition.  This fails if the [`Method`] is used anywhere
+    /// in the composition.
+    pub fn remove_method(&mut self, method_idx: MethodIdx) -> Result<(), EditError> {
+        let method = self.get_method(method_idx)?;
+        let is_used = self
+            .fragments
+            .iter()
+            .any(|frag| frag.chunks.iter().any(|c| c.uses_method(method)));
+        if is_used {
+            return Err(EditError::MethodUsed(method_idx));
+        }
+        self.methods.remove(method_idx);
+        Ok(())
+    }
+
+    /// Returns the range of row indices covered by the plain lead which contains a given row of a
+    /// [`Fragment`] (i.e. the rows which would be hidden if that lead was folded)
+    pub(crate) fn plain_lead_containing(
+        &self,
+        frag_idx: FragIdx,
+        row_idx: isize,
+    ) -> Result<Range<usize>, EditError> {
+        let frag = self.get_fragment(frag_idx)?;
+        frag.get_row_data(frag_idx, row_idx)?; // Return error if `row_idx` is out-of-bounds
+        frag.plain_lead_containing(row_idx as usize)
+            .ok_or(EditError::NotPlainLead { frag_idx, row_idx })
+    }
+
+    fn get_method(&self, idx: MethodIdx) -> Result<&Rc<Method>, EditError> {
+        self.methods.get(idx).ok_or(EditError::MethodOutOfRange {
+            idx,
+            len: self.methods.len(),
+        })
+    }
+
+    fn get_call(&self, idx: CallIdx) -> Result<Rc<Call>, EditError> {
+        self.calls
+            .get(idx)
+            .cloned()
+            .ok_or(EditError::CallOutOfRange {
+                idx,
+                len: self.calls.len(),
+            })
+    }
+
     fn get_fragment(&self, idx: FragIdx) -> Result<&Fragment, EditError> {
         self.fragments
             .get(idx)
@@ -245,9 +581,53 @@ impl CompSpec {
     }
 }
 
+/// A unique identifier for a [`Fragment`].  A [`Fragment`] keeps its `FragId` through any edit
+/// which doesn't split it or join it to another (e.g. moving, muting, transposing or extending
+/// it), and therefore through undo and redo.  Splitting, joining or duplicating [`Fragment`]s creates
+/// [`Fragment`]s with new `FragId`s.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub(crate) struct FragId(usize);
+
+impl FragId {
+    fn new() -> Self {
+        // IDs are never reused, even by other `CompSpec`s, so that anything stored by `FragId`
+        // (e.g. folds) is never applied to the wrong `Fragment`
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// How many [`Row`]s should be in a newly created [`Fragment`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum FragLength {
+    /// A given number of [`Row`]s
+    Rows(usize),
+    /// A given number of plain leads
+    Leads(usize),
+    /// Enough plain leads to bring the [`Method`] back to its start row
+    PlainCourse,
+}
+
+impl FragLength {
+    /// The number of [`Row`]s of `method` which this length refers to
+    fn num_rows(self, method: &Method) -> Result<usize, EditError> {
+        let num_rows = match self {
+            FragLength::Rows(n) => n,
+            FragLength::Leads(n) => n * method.lead_len(),
+            FragLength::PlainCourse => method.leads_per_course() * method.lead_len(),
+        };
+        match num_rows {
+            0 => Err(EditError::EmptyFragment),
+            n => Ok(n),
+        }
+    }
+}
+
 /// A single `Fragment` of composition.
 #[derive(Debug, Clone)]
 pub struct Fragment {
+    /// Identifies this `Fragment` across every [`CompSpec`] in the undo history
+    id: FragId,
     /// The on-screen location of the top-left corner of the top row this `Frag`
     position: Pos2,
     start_row: Rc<RowBuf>,
@@ -259,6 +639,10 @@ pub struct Fragment {
 }
 
 impl Fragment {
+    pub(crate) fn id(&self) -> FragId {
+        self.id
+    }
+
     /// Toggles whether or not this `Fragment` is proved.  This never fails, but returns a
     /// [`Result`] to make it more convenient to use with
     /// [`History::apply_frag_edit`](crate::history::History::apply_frag_edit).
@@ -273,6 +657,136 @@ impl Fragment {
         self.chunks.iter().map(|c| c.len()).sum()
     }
 
+    /// Changes the start row of `self` so that the [`Row`] at `row_idx` becomes `new_row`
+    fn transpose_row_to(
+        &mut self,
+        frag_idx: FragIdx,
+        row_idx: isize,
+        new_row: &Row,
+    ) -> Result<(), EditError> {
+        let current_row = if row_idx == self.len() as isize {
+            self.leftover_row()
+        } else {
+            self.get_row_data(frag_idx, row_idx)?.2
+        };
+        // Find the transposition which maps `current_row` to `new_row`, and apply it to the start
+        // row (which also applies it to every other row in the fragment)
+        let transposition = Row::solve_xa_equals_b(&current_row, new_row).unwrap();
+        self.start_row = Rc::new(transposition.as_row() * self.start_row.as_row());
+        Ok(())
+    }
+
+    /// Replaces every use of `old_method` with `new_method`.  If the lead lengths differ, then
+    /// [`Call`]s are moved to wherever they should be in the new [`Method`]'s lead, and method
+    /// [`Chunk`]s which started part-way through a lead are wrapped into the new lead.
+    fn replace_method(&mut self, old_method: &Rc<Method>, new_method: &Rc<Method>) {
+        let is_same_lead_len = old_method.lead_len() == new_method.lead_len();
+        for chunk in self.chunks.iter_mut() {
+            if !chunk.uses_method(old_method) {
+                continue;
+            }
+            let new_chunk = match chunk.as_ref() {
+                Chunk::Method {
+                    start_sub_lead_index,
+                    length,
+                    ..
+                } => {
+                    // `Chunk::method` wraps `start_sub_lead_index` into the new lead, so chunks
+                    // which start part-way through a longer lead (e.g. after a split) stay valid
+                    Chunk::method(new_method.clone(), *start_sub_lead_index, *length)
+                }
+                Chunk::Call {
+                    call,
+                    start_sub_lead_index,
+                    ..
+                } => Chunk::Call {
+                    call: call.clone(),
+                    method: new_method.clone(),
+                    start_sub_lead_index: if is_same_lead_len {
+                        *start_sub_lead_index
+                    } else {
+                        call.start_sub_lead_index(new_method)
+                    },
+                },
+            };
+            *chunk = Rc::new(new_chunk);
+        }
+        self.merge_chunks();
+    }
+
+    /// Returns the range of row indices covered by the plain lead which contains the row at
+    /// `row_idx`, or `None` if that row isn't part of a full lead of a single [`Method`] (e.g.
+    /// because the lead contains a [`Call`] or is cut short by the end of the `Fragment`).
+    fn plain_lead_containing(&self, row_idx: usize) -> Option<Range<usize>> {
+        let mut chunk_start_idx = 0;
+        for chunk in &self.chunks {
+            let chunk_range = chunk_start_idx..chunk_start_idx + chunk.len();
+            if chunk_range.contains(&row_idx) {
+                return match chunk.as_ref() {
+                    Chunk::Method {
+                        method,
+                        start_sub_lead_index,
+                        ..
+                    } => {
+                        let sub_lead_idx =
+                            (start_sub_lead_index + row_idx - chunk_start_idx) % method.lead_len();
+                        // Subtraction can't underflow, because the lead head's sub-lead index is
+                        // never greater than `start_sub_lead_index + row_idx - chunk_start_idx`
+                        let lead_head_idx = row_idx - sub_lead_idx;
+                        let lead = lead_head_idx..lead_head_idx + method.lead_len();
+                        // The whole lead must be within this chunk
+                        (lead.start >= chunk_range.start && lead.end <= chunk_range.end)
+                            .then_some(lead)
+                    }
+                    Chunk::Call { .. } => None,
+                };
+            }
+            chunk_start_idx = chunk_range.end;
+        }
+        None
+    }
+
+    /// The ranges of row indices covered by the leads starting at each of `lead_heads`, in order.
+    /// Indices which aren't the start of a plain lead are ignored.
+    pub(crate) fn folded_leads(&self, lead_heads: &BTreeSet<usize>) -> Vec<Range<usize>> {
+        lead_heads
+            .iter()
+            .filter_map(|&lead_head| {
+                self.plain_lead_containing(lead_head)
+                    .filter(|lead| lead.start == lead_head)
+            })
+            .collect()
+    }
+
+    /// Computes the leftover [`Row`] of this `Fragment` (i.e. the [`Row`] after the last
+    /// [`Chunk`])
+    fn leftover_row(&self) -> RowBuf {
+        let mut accum = RowAccumulator::new(self.start_row.as_ref().clone());
+        for chunk in &self.chunks {
+            accum *= chunk.transposition();
+        }
+        accum.into_total()
+    }
+
+    /// Adds `num_rows` rows of `method` to the end of `self`, continuing the lead of the last
+    /// [`Chunk`] if it's the same [`Method`]
+    fn extend(&mut self, method: Rc<Method>, num_rows: usize) {
+        let start_sub_lead_index = match self.chunks.last() {
+            Some(last_chunk) => {
+                let (last_method, end_sub_lead_index) = last_chunk.method_at_end();
+                if Rc::ptr_eq(last_method, &method) {
+                    end_sub_lead_index
+                } else {
+                    0
+                }
+            }
+            None => 0,
+        };
+        let new_chunk = Chunk::method(method, start_sub_lead_index, num_rows);
+        self.chunks.push(Rc::new(new_chunk));
+        self.merge_chunks();
+    }
+
     /// Shortens `self` such that the row at `split_idx` becomes leftover, returning a new
     /// `Fragment` containing the remaining [`Row`]s
     fn split(
@@ -291,6 +805,7 @@ impl Fragment {
         let (chunk_before_split, chunk_after_split) = chunk_being_split.split(sub_chunk_idx)?;
         // Put the first half of the split chunk back onto `self` (if it's non-empty)
         self.chunks.extend(chunk_before_split);
+        self.id = FragId::new();
 
         // Construct the chunks for the other fragment
         let mut new_frag_chunks = ChunkVec::with_capacity(other_chunks.len() + 1);
@@ -298,6 +813,7 @@ impl Fragment {
         new_frag_chunks.extend(other_chunks);
         // Construct and return the fragment containing the part of `self` after the split
         Ok(Fragment {
+            id: FragId::new(),
             position: new_frag_pos,
             start_row: Rc::new(new_frag_start_row),
             chunks: new_frag_chunks,
@@ -305,6 +821,125 @@ impl Fragment {
         })
     }
 
+    /// Sets, changes or removes the [`Call`] which starts at a given row.  If `new_call` is
+    /// `None`, then any existing call at that row is replaced by the plain lead of its method.
+    fn set_call(
+        &mut self,
+        frag_idx: FragIdx,
+        row_idx: isize,
+        new_call: Option<Rc<Call>>,
+    ) -> Result<(), EditError> {
+        let (chunk_idx, sub_chunk_idx, _row) = self.get_row_data(frag_idx, row_idx)?;
+
+        // If there's already a call at this row, then replace it with the rows of the plain lead
+        // that it covers.  This way, setting a call is always equivalent to adding a call to a
+        // plain lead.
+        if let Chunk::Call {
+            call,
+            method,
+            start_sub_lead_index,
+        } = self.chunks[chunk_idx].as_ref()
+        {
+            // Calls can only be modified from their first row
+            if sub_chunk_idx != 0 {
+                return Err(EditError::OverlappingCalls);
+            }
+            if matches!(&new_call, Some(c) if Rc::ptr_eq(c, call)) {
+                return Err(EditError::NoCallChange);
+            }
+            let plain_chunk = Chunk::method(method.clone(), *start_sub_lead_index, call.len());
+            self.chunks[chunk_idx] = Rc::new(plain_chunk);
+        } else if new_call.is_none() {
+            // Removing a call from a plain lead does nothing
+            return Err(EditError::NoCallChange);
+        }
+        // Merging the chunks means that the call being added will always be contained within one
+        // `Chunk::Method`, even if `row_idx` was at a chunk boundary.
+        self.merge_chunks();
+
+        if let Some(call) = new_call {
+            self.add_call(frag_idx, row_idx, call)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a [`Call`] starting at a given row, replacing the rows of a [`Chunk::Method`].
+    fn add_call(
+        &mut self,
+        frag_idx: FragIdx,
+        row_idx: isize,
+        call: Rc<Call>,
+    ) -> Result<(), EditError> {
+        let (chunk_idx, sub_chunk_idx, _row) = self.get_row_data(frag_idx, row_idx)?;
+        let (method, start_sub_lead_index, length) = match self.chunks[chunk_idx].as_ref() {
+            Chunk::Method {
+                method,
+                start_sub_lead_index,
+                length,
+                transposition: _,
+            } => (method.clone(), *start_sub_lead_index, *length),
+            Chunk::Call { .. } => return Err(EditError::OverlappingCalls),
+        };
+
+        // Check that the call is being placed in the right place in the lead
+        let sub_lead_idx = (start_sub_lead_index + sub_chunk_idx) % method.lead_len();
+        let call_sub_lead_idx = call.start_sub_lead_index(&method);
+        if sub_lead_idx != call_sub_lead_idx {
+            return Err(EditError::WrongCallLocation {
+                sub_lead_idx,
+                call_sub_lead_idx,
+            });
+        }
+        // Check that the rows covered by the call are all in this chunk
+        let rows_after_call = length
+            .checked_sub(sub_chunk_idx + call.len())
+            .ok_or(EditError::CallDoesntFit)?;
+
+        // Replace the method chunk with up to three chunks: the plain rows before the call, the
+        // call itself, and the plain rows after the call.
+        let mut new_chunks = Vec::with_capacity(3);
+        if sub_chunk_idx > 0 {
+            new_chunks.push(Chunk::method(
+                method.clone(),
+                start_sub_lead_index,
+                sub_chunk_idx,
+            ));
+        }
+        new_chunks.push(Chunk::Call {
+            call: call.clone(),
+            method: method.clone(),
+            start_sub_lead_index: sub_lead_idx,
+        });
+        if rows_after_call > 0 {
+            new_chunks.push(Chunk::method(
+                method,
+                sub_lead_idx + call.len(),
+                rows_after_call,
+            ));
+        }
+        self.chunks
+            .splice(chunk_idx..=chunk_idx, new_chunks.into_iter().map(Rc::new));
+        Ok(())
+    }
+
+    /// Merge any adjacent [`Chunk::Method`]s which are of the same [`Method`] and where the
+    /// second [`Chunk`] continues from exactly where the first one finished.
+    fn merge_chunks(&mut self) {
+        let mut merged_chunks = ChunkVec::<Rc<Chunk>>::with_capacity(self.chunks.len());
+        for chunk in self.chunks.drain(..) {
+            let merged_chunk = merged_chunks
+                .last()
+                .and_then(|last_chunk| last_chunk.merge_with(&chunk));
+            match merged_chunk {
+                Some(c) => *merged_chunks.last_mut().unwrap() = Rc::new(c),
+                None => {
+                    merged_chunks.push(chunk);
+                }
+            }
+        }
+        self.chunks = merged_chunks;
+    }
+
     /// Given a (possibly negative) row index, this returns a tuple of
     /// `(chunk index, sub-chunk index, row)` at that index, or `None` if the index is
     /// out-of-bounds.
@@ -361,7 +996,9 @@ impl Fragment {
         /// Returns `Some(RowIdx)` if `idx` is within `0..len`, else `None`
         fn test_idx_option(idx: isize, len: usize) -> Option<RowIdx> {
             let positive_idx = usize::try_from(idx).ok()?;
-            (positive_idx < len).then(|| positive_idx).map(RowIdx::from)
+            (positive_idx < len)
+                .then_some(positive_idx)
+                .map(RowIdx::from)
         }
 
         let len = self.len();
@@ -383,7 +1020,6 @@ enum Chunk {
         length: usize,
         transposition: RowBuf,
     },
-    #[allow(dead_code)]
     Call {
         call: Rc<Call>,
         method: Rc<Method>,
@@ -455,7 +1091,7 @@ impl Chunk {
                 accum.accumulate(method.inner.row_in_plain_lead(end_sub_lead_index))
             }
             // For a call, we just accumulate the `row_idx`th row of the call
-            Chunk::Call { call, .. } => accum.accumulate(&call.inner.block().row_vec()[row_idx]),
+            Chunk::Call { call, .. } => accum.accumulate(&call.block.row_vec()[row_idx]),
         }
     }
 
@@ -463,15 +1099,75 @@ impl Chunk {
     fn len(&self) -> usize {
         match self {
             Chunk::Method { length, .. } => *length,
-            Chunk::Call { call, .. } => call.inner.len(),
+            Chunk::Call { call, .. } => call.len(),
+        }
+    }
+
+    /// Returns `true` if this `Chunk` contains rows of `method`
+    fn uses_method(&self, method: &Rc<Method>) -> bool {
+        match self {
+            Chunk::Method { method: m, .. } | Chunk::Call { method: m, .. } => {
+                Rc::ptr_eq(m, method)
+            }
         }
     }
 
+    /// The [`Method`] of this `Chunk`, along with the sub-lead index of the [`Row`] directly after
+    /// its last row (i.e. the [`Row`] which would start the next `Chunk` if it continued the same
+    /// [`Method`])
+    fn method_at_end(&self) -> (&Rc<Method>, usize) {
+        let (method, start_sub_lead_index) = match self {
+            Chunk::Method {
+                method,
+                start_sub_lead_index,
+                ..
+            } => (method, start_sub_lead_index),
+            Chunk::Call {
+                method,
+                start_sub_lead_index,
+                ..
+            } => (method, start_sub_lead_index),
+        };
+        (
+            method,
+            (start_sub_lead_index + self.len()) % method.lead_len(),
+        )
+    }
+
     /// The transposition caused by this `Chunk`
     fn transposition(&self) -> &Row {
         match self {
             Chunk::Method { transposition, .. } => transposition,
-            Chunk::Call { call, .. } => call.inner.transposition(),
+            Chunk::Call { call, .. } => call.transposition(),
+        }
+    }
+
+    /// If `self` and `next` are both [`Chunk::Method`]s of the same [`Method`], and `next` starts
+    /// exactly where `self` finishes, then this returns a single [`Chunk`] covering both.
+    fn merge_with(&self, next: &Chunk) -> Option<Chunk> {
+        match (self, next) {
+            (
+                Chunk::Method {
+                    method,
+                    start_sub_lead_index,
+                    length,
+                    transposition: _,
+                },
+                Chunk::Method {
+                    method: next_method,
+                    start_sub_lead_index: next_start_sub_lead_index,
+                    length: next_length,
+                    transposition: _,
+                },
+            ) => {
+                let is_continuation = Rc::ptr_eq(method, next_method)
+                    && (start_sub_lead_index + length) % method.lead_len()
+                        == *next_start_sub_lead_index;
+                is_continuation.then(|| {
+                    Chunk::method(method.clone(), *start_sub_lead_index, length + next_length)
+                })
+            }
+            _ => None,
         }
     }
 
@@ -523,27 +1219,43 @@ pub(crate) struct Method {
     /// through an [`Rc`]).  For example, `B` is often used as a shorthand for `"Bristol Surprise
     /// Major"`.
     shorthand: RefCell<String>,
+    /// The place notation of this `Method`, exactly as it was specified
+    place_notation: String,
     /// Which locations in the lead should have lines drawn **above** them
-    ruleoffs_above: HashSet<usize>, // TODO: Use a bitmask
+    ruleoffs_above: Ruleoffs,
 }
 
 impl Method {
-    fn with_lead_end_ruleoff(inner: bellframe::Method, name: String, shorthand: String) -> Self {
-        Self::new(inner, name, shorthand, std::iter::once(0).collect())
+    /// Creates a new `Method` by parsing a string of place notation, with rule-offs placed
+    /// according to a [`RuleoffPreset`]
+    fn with_ruleoff_preset(
+        name: String,
+        shorthand: String,
+        place_notation: &str,
+        stage: Stage,
+        preset: RuleoffPreset,
+    ) -> Result<Self, PnBlockParseError> {
+        let mut method = Self::new(name, shorthand, place_notation, stage, Ruleoffs::default())?;
+        method.set_ruleoff_preset(preset);
+        Ok(method)
     }
 
+    /// Creates a new `Method` by parsing a string of place notation
     fn new(
-        inner: bellframe::Method,
         name: String,
         shorthand: String,
-        ruleoffs: HashSet<usize>,
-    ) -> Self {
-        Self {
+        place_notation: &str,
+        stage: Stage,
+        ruleoffs_above: Ruleoffs,
+    ) -> Result<Self, PnBlockParseError> {
+        let inner = bellframe::Method::from_place_not_string(String::new(), stage, place_notation)?;
+        Ok(Self {
             inner,
             name: RefCell::new(name),
             shorthand: RefCell::new(shorthand),
-            ruleoffs_above: ruleoffs,
-        }
+            place_notation: place_notation.to_owned(),
+            ruleoffs_above,
+        })
     }
 
     #[inline]
@@ -551,32 +1263,212 @@ impl Method {
         self.inner.lead_len()
     }
 
-    pub fn shorthand(&self) -> Ref<String> {
+    pub fn shorthand(&self) -> Ref<'_, String> {
         self.shorthand.borrow()
     }
 
-    pub fn name(&self) -> Ref<String> {
+    pub fn name(&self) -> Ref<'_, String> {
         self.name.borrow()
     }
 
+    #[inline]
+    pub fn place_notation(&self) -> &str {
+        &self.place_notation
+    }
+
+    /// The number of leads in a plain course of this `Method`
+    pub fn leads_per_course(&self) -> usize {
+        self.inner.lead_head().closure().len()
+    }
+
     pub fn is_ruleoff_below(&self, sub_lead_idx: usize) -> bool {
         // We store which rows have ruleoffs **above** them, so we have to query the row below the
         // one specified by `sub_lead_idx`
         let idx = (sub_lead_idx + 1) % self.inner.lead_len();
-        self.ruleoffs_above.contains(&idx)
+        self.ruleoffs_above.is_ruleoff_above(idx)
+    }
+
+    /// The [`RuleoffPreset`] which generates this `Method`'s rule-offs, or `None` if they don't
+    /// match any preset
+    pub fn ruleoff_preset(&self) -> Option<RuleoffPreset> {
+        self.ruleoffs_above.preset(self.lead_len())
+    }
+
+    fn set_ruleoff_preset(&mut self, preset: RuleoffPreset) {
+        self.ruleoffs_above = Ruleoffs::from_preset(preset, self.lead_len());
     }
 }
 
+/// The definition of a `Call` which can be used in the composition.  Each `Call` replaces a
+/// fixed block of [`Row`]s in a [`Method`] with the rows generated by its own place notation.
 #[derive(Debug, Clone)]
 pub(crate) struct Call {
-    inner: bellframe::Call,
+    /// The symbol used to label this `Call` in the composition (e.g. `-` for a bob)
+    symbol: char,
+    /// The place notation of this `Call`, exactly as it was specified
+    place_notation: String,
+    /// The [`Row`]s generated by this `Call`, starting from rounds.  A `Call` replaces exactly
+    /// as many rows of its [`Method`] as it generates.
+    block: Block,
+    /// The sub-lead index of the first [`Row`] **after** this `Call`, i.e. the point where the
+    /// plain [`Method`] resumes.  Lead end calls have this set to `0`, because they finish at the
+    /// lead head.  We store the end rather than the start so that the same `Call` can be used for
+    /// methods with different lead lengths.
+    sub_lead_idx_after: usize,
+    /// The name of the calling position for each place that the observation bell could occupy
+    /// at the end of this `Call`.  E.g. for `14` bobs on Major, the tenor ending in 8ths is `H`
+    /// (Home), and ending in 7ths is `W` (Wrong).
+    calling_positions: Vec<String>,
 }
 
-/// A point where the composition can be folded.  Composition folding is not part of the undo
-/// history and therefore relies on interior mutability.
-#[derive(Debug, Clone)]
-pub(crate) struct Fold {
-    is_open: Cell<bool>,
+impl Call {
+    /// Creates a new `Call` by parsing a string of place notation
+    fn new(
+        symbol: char,
+        place_notation: &str,
+        sub_lead_idx_after: usize,
+        calling_positions: Vec<String>,
+        stage: Stage,
+    ) -> Result<Self, PnBlockParseError> {
+        let block = PnBlock::parse(place_notation, stage)?.to_block_from_rounds();
+        Ok(Self {
+            symbol,
+            place_notation: place_notation.to_owned(),
+            block,
+            sub_lead_idx_after,
+            calling_positions,
+        })
+    }
+
+    /// Creates a lead end `Call` with the standard calling positions for its [`Stage`]
+    fn lead_end(symbol: char, place_notation: &str, stage: Stage) -> Self {
+        let calling_positions = default_calling_positions(stage);
+        Self::new(symbol, place_notation, 0, calling_positions, stage)
+            .expect("Default calls should always have valid place notation")
+    }
+
+    /// The standard set of lead end calls for a given [`Stage`]: the near bob and single, followed
+    /// by the far bob and single (if they are different from the near calls).
+    pub fn defaults(stage: Stage) -> Vec<Self> {
+        [
+            Self::near_bob(stage),
+            Self::near_single(stage),
+            Self::far_bob(stage),
+            Self::far_single(stage),
+        ]
+        .iter()
+        .flatten()
+        .cloned()
+        .collect()
+    }
+
+    /// The near lead end bob for a given [`Stage`]: `14` on even stages, or Grandsire-style `3.1`
+    /// on odd stages.  Returns `None` if the [`Stage`] is too small to have a bob.
+    pub fn near_bob(stage: Stage) -> Option<Self> {
+        let pn = if stage.is_even() { "14" } else { "3.1" };
+        (stage >= Stage::MINIMUS).then(|| Self::lead_end('-', pn, stage))
+    }
+
+    /// The near lead end single for a given [`Stage`]: `1234` on even stages, or Grandsire-style
+    /// `3.123` on odd stages.  Returns `None` if the [`Stage`] is too small to have a single.
+    pub fn near_single(stage: Stage) -> Option<Self> {
+        let pn = if stage.is_even() { "1234" } else { "3.123" };
+        (stage >= Stage::MINIMUS).then(|| Self::lead_end('s', pn, stage))
+    }
+
+    /// The far lead end bob for a given [`Stage`] (e.g. `16` on Major).  Returns `None` for odd
+    /// stages, and for stages where the far bob would be the same as the near bob.
+    pub fn far_bob(stage: Stage) -> Option<Self> {
+        if !has_far_calls(stage) {
+            return None;
+        }
+        let pn = format!("1{}", Bell::from_index(stage.num_bells() - 3).name());
+        Some(Self::lead_end('-', &pn, stage))
+    }
+
+    /// The far lead end single for a given [`Stage`] (e.g. `1678` on Major).  Returns `None` for
+    /// odd stages, and for stages where the far single would be the same as the near single.
+    pub fn far_single(stage: Stage) -> Option<Self> {
+        if !has_far_calls(stage) {
+            return None;
+        }
+        let n = stage.num_bells();
+        let pn = std::iter::once(Bell::TREBLE)
+            .chain((n - 3..n).map(Bell::from_index))
+            .map(Bell::name)
+            .collect::<String>();
+        Some(Self::lead_end('s', &pn, stage))
+    }
+
+    /// The sub-lead index of the first [`Row`] of `method` which is replaced by this `Call`
+    fn start_sub_lead_index(&self, method: &Method) -> usize {
+        let lead_len = method.lead_len();
+        (self.sub_lead_idx_after + lead_len - self.len() % lead_len) % lead_len
+    }
+
+    /// The number of [`Row`]s generated (and replaced) by this `Call`
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.block.len()
+    }
+
+    /// The transposition from the first [`Row`] of this `Call` to the first [`Row`] after it
+    #[inline]
+    fn transposition(&self) -> &Row {
+        self.block.leftover_row()
+    }
+
+    /// The symbol used to label this `Call` (e.g. `'-'` for bobs or `'s'` for singles)
+    #[inline]
+    pub fn symbol(&self) -> char {
+        self.symbol
+    }
+
+    #[inline]
+    pub fn place_notation(&self) -> &str {
+        &self.place_notation
+    }
+
+    /// The sub-lead index of the first [`Row`] after this `Call` (`0` for lead end calls)
+    #[inline]
+    pub fn sub_lead_idx_after(&self) -> usize {
+        self.sub_lead_idx_after
+    }
+
+    /// Gets the name of the calling position where the observation bell finishes in a given
+    /// `place` (e.g. `"H"`), or `None` if that place has no calling position.
+    pub fn calling_position(&self, place: usize) -> Option<&str> {
+        self.calling_positions.get(place).map(String::as_str)
+    }
+}
+
+/// Far calls only exist on even stages, and below Major they would be identical to the near calls
+fn has_far_calls(stage: Stage) -> bool {
+    stage.is_even() && stage >= Stage::MAJOR
+}
+
+/// Generates the standard calling positions for a lead end call, indexed by the place of the
+/// observation bell after the call.  The back bells are always `M`, `W` and `H`, and the front
+/// places are `I` (2nds), `B` (3rds, sometimes called `O`), `F` (4ths) and `V` (5ths).  These
+/// are the same for near and far calls.  Any other places are named by their place number.
+fn default_calling_positions(stage: Stage) -> Vec<String> {
+    let n = stage.num_bells();
+    (0..n)
+        .map(|place| {
+            let name = match place {
+                p if p + 1 == n => "H",
+                p if p + 2 == n => "W",
+                p if p + 3 == n => "M",
+                1 => "I",
+                2 => "B",
+                3 => "F",
+                4 => "V",
+                // Fall back on the place's number for unnamed places
+                p => return Bell::from_index(p).name(),
+            };
+            name.to_owned()
+        })
+        .collect()
 }
 
 /////////////////
@@ -597,6 +1489,58 @@ pub enum EditError {
     },
     // Trying to split the region covered by a call
     SplitCall,
+    CallOutOfRange {
+        idx: CallIdx,
+        len: usize,
+    },
+    MethodOutOfRange {
+        idx: MethodIdx,
+        len: usize,
+    },
+    /// Trying to create a [`Fragment`] which contains no rows
+    EmptyFragment,
+    /// Trying to join two [`Fragment`]s where the leftover row of `top` isn't equivalent to the
+    /// first row of `bottom`
+    NotLinkable {
+        top: FragIdx,
+        bottom: FragIdx,
+    },
+    /// Trying to join a [`Fragment`] onto itself
+    JoinSameFragment(FragIdx),
+    /// The place notation given for a [`Method`] couldn't be parsed
+    PlaceNotation(PnBlockParseError),
+    /// Trying to remove a [`Method`] which is still used in the composition
+    MethodUsed(MethodIdx),
+    /// Trying to give a [`Method`] a shorthand which isn't a single letter
+    InvalidShorthand(String),
+    /// Trying to give a [`Method`] the same shorthand as another [`Method`]
+    ShorthandUsed {
+        shorthand: String,
+        method_idx: MethodIdx,
+    },
+    /// Trying to add a [`Method`] when every letter is already used as a shorthand
+    NoFreeShorthand,
+    /// Trying to fold a row which isn't part of a plain lead
+    NotPlainLead {
+        frag_idx: FragIdx,
+        row_idx: isize,
+    },
+    /// Trying to add a [`Method`] of a different [`Stage`] to the composition
+    WrongStage {
+        comp_stage: Stage,
+        method_stage: Stage,
+    },
+    /// Trying to add a call part-way through another call
+    OverlappingCalls,
+    /// Trying to add a call at the wrong place in the lead (e.g. a lead end call at the half-lead)
+    WrongCallLocation {
+        sub_lead_idx: usize,
+        call_sub_lead_idx: usize,
+    },
+    /// Trying to add a call which covers more rows than are left in the method
+    CallDoesntFit,
+    /// Trying to set a call which is already there, or trying to remove a call from a plain lead
+    NoCallChange,
 }
 
 ///////////////
@@ -604,7 +1548,7 @@ pub enum EditError {
 ///////////////
 
 impl Fragment {
-    fn expand(&self, part_heads: &PartHeads) -> ExpandedFrag {
+    pub(crate) fn expand(&self, part_heads: &PartHeads) -> ExpandedFrag {
         let mut rows_in_one_part = AnnotBlock::<()>::empty(self.start_row.stage());
         rows_in_one_part.pre_multiply(&self.start_row).unwrap(); // Set the start row of the first chunk
         let mut row_data = RowVec::<RowData>::with_capacity(self.len() + 1);
@@ -679,16 +1623,469 @@ impl Chunk {
             }
             Chunk::Call {
                 call,
-                method: _,
-                start_sub_lead_index: _,
+                method,
+                start_sub_lead_index,
             } => {
-                let block = call.inner.block();
-                // TODO: Extend row data
-                // Extend rows
+                let block = &call.block;
+                let lead_len = method.lead_len();
+                // Extend row data.  The rows of a call replace the rows of the method that it
+                // covers, so each row of the call takes the sub-lead index of the row it
+                // replaces.  This way, ruleoffs are still drawn at the lead ends and the method
+                // chunk after the call (which starts at `start_sub_lead_index + call.len()`) isn't
+                // detected as a splice.
+                row_data.extend((0..block.len()).map(|i| {
+                    let sub_lead_idx = (*start_sub_lead_index + i) % lead_len;
+                    RowData {
+                        method_source: Some((method.clone(), sub_lead_idx)),
+                        call_source: Some((call.clone(), i)),
+                        is_proved,
+                    }
+                }));
+                // Extend rows.  `block` starts at rounds, so `extend` transposes it to start
+                // wherever the previous chunk finished
                 rows_in_one_part.extend(block).unwrap();
-                // Update the start row of the next chunk
-                todo!() // Decide what lead indices should be given
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use bellframe::{music::Regex, RowBuf, Stage};
+    use emath::Pos2;
+    use jigsaw_utils::indexed_vec::{CallIdx, ChunkIdx, FragIdx, MethodIdx, PartIdx, RowIdx};
+
+    use crate::full::FullState;
+
+    use super::{Call, Chunk, CompSpec, EditError, FragLength, PartHeads, RuleoffPreset};
+    use crate::{Music, MusicPresets};
+
+    /// Creates a composition of Plain Bob Doubles (which has 10-row leads) containing one
+    /// fragment of a given length, starting at rounds.  The default calls on Doubles are
+    /// Grandsire-style bobs and singles, which cover the last two rows of the lead.
+    fn plain_bob(length: FragLength) -> CompSpec {
+        let mut spec = CompSpec::empty(Stage::DOUBLES, MusicPresets::None);
+        spec.add_method(
+            "Plain Bob".to_owned(),
+            "P".to_owned(),
+            "5.1.5.1.5,125",
+            RuleoffPreset::LeadEnd,
+        )
+        .unwrap();
+        let rounds = RowBuf::rounds(Stage::DOUBLES);
+        spec.add_fragment(Pos2::new(0.0, 0.0), rounds, MethodIdx::new(0), length)
+            .unwrap();
+        spec
+    }
+
+    /// The `(call symbol, start sub-lead index, length)` of every [`Chunk`] in a fragment.  The
+    /// call symbol is `None` for method chunks.
+    fn chunks(spec: &CompSpec, frag_idx: usize) -> Vec<(Option<char>, usize, usize)> {
+        (spec.fragments[FragIdx::new(frag_idx)].chunks.iter())
+            .map(|chunk| match chunk.as_ref() {
+                Chunk::Method {
+                    start_sub_lead_index,
+                    length,
+                    ..
+                } => (None, *start_sub_lead_index, *length),
+                Chunk::Call {
+                    call,
+                    start_sub_lead_index,
+                    ..
+                } => (Some(call.symbol), *start_sub_lead_index, call.len()),
+            })
+            .collect()
+    }
+
+    /// The [`Row`] at a given index of a fragment, as it's displayed in the first part
+    fn row(spec: &CompSpec, frag_idx: usize, row_idx: usize) -> String {
+        let full_state = FullState::new(spec);
+        let frag = &full_state.fragments[FragIdx::new(frag_idx)];
+        frag.row_in_part(PartIdx::new(0), RowIdx::new(row_idx))
+            .to_string()
+    }
+
+    /// Shortening a method's lead must wrap chunks which start part-way through a lead of the old
+    /// method, rather than leaving them starting past the end of the new lead
+    #[test]
+    fn edit_method_shorter_lead_after_split() {
+        let mut spec = CompSpec::example();
+        // Split the first lead (of Deva, which has a 32-row lead) after 20 rows
+        spec.split_fragment(FragIdx::new(0), 20, Pos2::new(500.0, 100.0))
+            .unwrap();
+        let part_len = FullState::new(&spec).stats.part_len;
+        // Replace Deva with a 16-row lead
+        spec.edit_method(MethodIdx::new(0), "x18x18x18x18,12", None)
+            .unwrap();
+
+        // The second half of the split lead now starts 4 rows into the new lead
+        match spec.fragments[FragIdx::new(1)].chunks[ChunkIdx::new(0)].as_ref() {
+            Chunk::Method {
+                start_sub_lead_index,
+                ..
+            } => assert_eq!(*start_sub_lead_index, 4),
+            Chunk::Call { .. } => panic!("Expected a method chunk"),
+        }
+        // Expanding the composition mustn't panic, and the fragments keep their lengths
+        let full_state = FullState::new(&spec);
+        assert_eq!(full_state.fragments.len(), 2);
+        assert_eq!(full_state.stats.part_len, part_len);
+    }
+
+    #[test]
+    fn method_shorthands() {
+        let mut spec = CompSpec::example();
+        let add = |spec: &mut CompSpec, shorthand: &str| {
+            spec.add_method(
+                "Cambridge".to_owned(),
+                shorthand.to_owned(),
+                "-38-14-1258-36-14-58-16-78,12",
+                RuleoffPreset::LeadEnd,
+            )
+        };
+        // Shorthands must be single letters ...
+        for shorthand in ["", "Cm", "1"] {
+            let result = add(&mut spec, shorthand);
+            assert!(matches!(result, Err(EditError::InvalidShorthand(_))));
+        }
+        // ... which aren't used by any other method, ignoring case
+        assert!(matches!(
+            add(&mut spec, "b"),
+            Err(EditError::ShorthandUsed { method_idx, .. }) if method_idx == MethodIdx::new(1)
+        ));
+        let cambridge = add(&mut spec, "c").unwrap();
+
+        // Renaming a method can keep its own shorthand, but not take another method's
+        spec.set_method_names(cambridge, "Cambridge".to_owned(), "C".to_owned())
+            .unwrap();
+        assert!(matches!(
+            spec.set_method_names(cambridge, "Cambridge".to_owned(), "Y".to_owned()),
+            Err(EditError::ShorthandUsed { .. })
+        ));
+        assert_eq!(*spec.methods[cambridge].shorthand(), "C");
+    }
+
+    #[test]
+    fn empty() {
+        for presets in MusicPresets::ALL {
+            let spec = CompSpec::empty(Stage::MAJOR, presets);
+            let full_state = FullState::new(&spec);
+            assert_eq!(full_state.stats.part_len, 0);
+            assert_eq!(
+                full_state.music.groups().len(),
+                presets.music(Stage::MAJOR).len()
+            );
+            assert!(full_state.methods.is_empty());
+            assert_eq!(
+                full_state.calls.len(),
+                super::Call::defaults(Stage::MAJOR).len()
+            );
+            // Empty compositions can be saved and reloaded
+            let json = spec.to_json();
+            let reloaded = CompSpec::from_json(&json, std::path::Path::new("")).unwrap();
+            assert_eq!(reloaded.to_json(), json);
+        }
+    }
+
+    /// Music set with `CompSpec::set_music` isn't checked against the stage, so a regex which is
+    /// too long must be handled when counting music
+    #[test]
+    fn regex_longer_than_stage() {
+        let mut spec = CompSpec::example();
+        spec.music = Rc::new(vec![Music::regex(None, Regex::parse("xxxxxxxxx"))]);
+        let full_state = FullState::new(&spec);
+        assert_eq!(full_state.music.max_count, 0);
+        assert_eq!(full_state.music.total_count, 0);
+    }
+
+    ///////////////
+    // FRAGMENTS //
+    ///////////////
+
+    #[test]
+    fn add_fragment() {
+        // Plain Bob Doubles has lead heads 13524, 15432, 14253 and then rounds
+        let spec = plain_bob(FragLength::Leads(2));
+        let frag = &spec.fragments[FragIdx::new(0)];
+        assert_eq!(frag.len(), 20);
+        assert_eq!(chunks(&spec, 0), [(None, 0, 20)]);
+        assert_eq!(row(&spec, 0, 0), "12345");
+        assert_eq!(row(&spec, 0, 10), "13524");
+        assert_eq!(frag.leftover_row().to_string(), "15432");
+        // A plain course ends at the row it started from
+        let spec = plain_bob(FragLength::PlainCourse);
+        let frag = &spec.fragments[FragIdx::new(0)];
+        assert_eq!(frag.len(), 40);
+        assert_eq!(row(&spec, 0, 30), "14253");
+        assert_eq!(frag.leftover_row().to_string(), "12345");
+
+        // Fragments can't be empty, or use methods which don't exist
+        let mut spec = plain_bob(FragLength::Leads(1));
+        let rounds = RowBuf::rounds(Stage::DOUBLES);
+        assert!(matches!(
+            spec.add_fragment(
+                Pos2::ZERO,
+                rounds.clone(),
+                MethodIdx::new(0),
+                FragLength::Leads(0)
+            ),
+            Err(EditError::EmptyFragment)
+        ));
+        assert!(matches!(
+            spec.add_fragment(Pos2::ZERO, rounds, MethodIdx::new(1), FragLength::Leads(1)),
+            Err(EditError::MethodOutOfRange { len: 1, .. })
+        ));
+        assert_eq!(spec.fragments.len(), 1);
+    }
+
+    #[test]
+    fn extend_fragment() {
+        let frag_idx = FragIdx::new(0);
+        let mut spec = plain_bob(FragLength::Rows(6));
+        // Extending with the same method continues its lead, and merges into the same chunk
+        spec.extend_fragment(frag_idx, MethodIdx::new(0), FragLength::Leads(1))
+            .unwrap();
+        assert_eq!(chunks(&spec, 0), [(None, 0, 16)]);
+        let one_chunk = plain_bob(FragLength::Rows(16));
+        for row_idx in 0..16 {
+            assert_eq!(row(&spec, 0, row_idx), row(&one_chunk, 0, row_idx));
+        }
+        // The same goes for extending by a number of rows
+        spec.extend_fragment(frag_idx, MethodIdx::new(0), FragLength::Rows(4))
+            .unwrap();
+        assert_eq!(chunks(&spec, 0), [(None, 0, 20)]);
+        assert_eq!(spec.fragments[frag_idx].leftover_row().to_string(), "15432");
+    }
+
+    #[test]
+    fn join_fragments() {
+        let (top, bottom) = (FragIdx::new(0), FragIdx::new(1));
+        let mut spec = plain_bob(FragLength::Leads(1));
+        // Add a second lead which starts at rounds, rather than the first lead's leftover row
+        let rounds = RowBuf::rounds(Stage::DOUBLES);
+        spec.add_fragment(Pos2::ZERO, rounds, MethodIdx::new(0), FragLength::Leads(1))
+            .unwrap();
+        assert!(matches!(
+            spec.join_fragments(top, bottom),
+            Err(EditError::NotLinkable { .. })
+        ));
+        assert!(matches!(
+            spec.join_fragments(top, top),
+            Err(EditError::JoinSameFragment(_))
+        ));
+        assert_eq!(spec.fragments.len(), 2);
+
+        // If 13524 is a part head, then it's equivalent to rounds so the fragments can be joined.
+        // `bottom` is transposed so that it continues from the leftover row of `top`.
+        spec.set_part_heads(PartHeads::parse("13524", Stage::DOUBLES).unwrap());
+        spec.join_fragments(top, bottom).unwrap();
+        assert_eq!(spec.fragments.len(), 1);
+        assert_eq!(chunks(&spec, 0), [(None, 0, 20)]);
+        assert_eq!(row(&spec, 0, 10), "13524");
+        assert_eq!(spec.fragments[top].leftover_row().to_string(), "15432");
+    }
+
+    #[test]
+    fn transpose_fragment() {
+        let frag_idx = FragIdx::new(0);
+        let mut spec = plain_bob(FragLength::Leads(1));
+        let rounds = RowBuf::rounds(Stage::DOUBLES);
+        assert_eq!(row(&spec, 0, 3), "42513");
+        // Transposing a row in the middle of the fragment moves every other row with it
+        spec.transpose_fragment(frag_idx, 3, &rounds).unwrap();
+        assert_eq!(row(&spec, 0, 3), "12345");
+        assert_eq!(row(&spec, 0, 0), "42513");
+        assert_eq!(chunks(&spec, 0), [(None, 0, 10)]);
+        // The leftover row can also be transposed, but nothing after it
+        spec.transpose_fragment(frag_idx, 10, &rounds).unwrap();
+        assert_eq!(spec.fragments[frag_idx].leftover_row(), rounds);
+        assert!(matches!(
+            spec.transpose_fragment(frag_idx, 11, &rounds),
+            Err(EditError::RowOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn duplicate_fragment() {
+        let frag_idx = FragIdx::new(0);
+        let mut spec = plain_bob(FragLength::Leads(2));
+        spec.set_call(frag_idx, 8, Some(CallIdx::new(0))).unwrap();
+        let new_position = Pos2::new(300.0, 0.0);
+        let copy_idx = spec.duplicate_fragment(frag_idx, new_position).unwrap();
+        assert_eq!(copy_idx, FragIdx::new(1));
+
+        // The copy is in a new place, but shares its start row and chunks with the original
+        let (original, copy) = (&spec.fragments[frag_idx], &spec.fragments[copy_idx]);
+        assert_eq!(copy.position, new_position);
+        assert_ne!(copy.position, original.position);
+        assert!(Rc::ptr_eq(&copy.start_row, &original.start_row));
+        assert_eq!(copy.chunks.len(), 3);
+        for (c1, c2) in original.chunks.iter().zip(&copy.chunks) {
+            assert!(Rc::ptr_eq(c1, c2));
+        }
+        // Editing the copy doesn't change the original
+        spec.set_call(copy_idx, 8, None).unwrap();
+        assert_eq!(
+            chunks(&spec, 0),
+            [(None, 0, 8), (Some('-'), 8, 2), (None, 0, 10)]
+        );
+        assert_eq!(chunks(&spec, 1), [(None, 0, 20)]);
+    }
+
+    ///////////
+    // CALLS //
+    ///////////
+
+    #[test]
+    fn set_call() {
+        let mut spec = plain_bob(FragLength::Leads(3));
+        let frag_idx = FragIdx::new(0);
+        let bob = Some(CallIdx::new(0));
+        let single = Some(CallIdx::new(1));
+
+        // Adding a call splits the method chunk around it
+        spec.set_call(frag_idx, 8, bob).unwrap();
+        assert_eq!(
+            chunks(&spec, 0),
+            [(None, 0, 8), (Some('-'), 8, 2), (None, 0, 20)]
+        );
+        // Calls at the last lead end don't need any rows after them
+        spec.set_call(frag_idx, 28, bob).unwrap();
+        assert_eq!(
+            chunks(&spec, 0),
+            [
+                (None, 0, 8),
+                (Some('-'), 8, 2),
+                (None, 0, 18),
+                (Some('-'), 8, 2)
+            ]
+        );
+        // Changing a call keeps the chunks around it
+        spec.set_call(frag_idx, 8, single).unwrap();
+        assert_eq!(
+            chunks(&spec, 0),
+            [
+                (None, 0, 8),
+                (Some('s'), 8, 2),
+                (None, 0, 18),
+                (Some('-'), 8, 2)
+            ]
+        );
+        // Removing the calls merges the plain chunks back together
+        spec.set_call(frag_idx, 8, None).unwrap();
+        spec.set_call(frag_idx, 28, None).unwrap();
+        assert_eq!(chunks(&spec, 0), [(None, 0, 30)]);
+    }
+
+    /// Cycling the call at a lead end (which the GUI does when the call shortcut is pressed
+    /// repeatedly) goes through every call before returning to the original plain lead
+    #[test]
+    fn cycle_calls() {
+        let mut spec = plain_bob(FragLength::Leads(1));
+        let frag_idx = FragIdx::new(0);
+        let original_chunks = chunks(&spec, 0);
+        let original_leftover_row = spec.fragments[frag_idx].leftover_row();
+
+        let mut chunks_after_each_edit = Vec::new();
+        for call_idx in [Some(CallIdx::new(0)), Some(CallIdx::new(1)), None] {
+            spec.set_call(frag_idx, 8, call_idx).unwrap();
+            chunks_after_each_edit.push(chunks(&spec, 0));
+        }
+        assert_eq!(
+            chunks_after_each_edit,
+            [
+                vec![(None, 0, 8), (Some('-'), 8, 2)],
+                vec![(None, 0, 8), (Some('s'), 8, 2)],
+                original_chunks,
+            ]
+        );
+        assert_eq!(
+            spec.fragments[frag_idx].leftover_row(),
+            original_leftover_row
+        );
+    }
+
+    #[test]
+    fn set_call_errors() {
+        let mut spec = plain_bob(FragLength::Rows(19));
+        let frag_idx = FragIdx::new(0);
+        let bob = Some(CallIdx::new(0));
+
+        // Calls have to go at the right place in the lead ...
+        assert!(matches!(
+            spec.set_call(frag_idx, 5, bob),
+            Err(EditError::WrongCallLocation {
+                sub_lead_idx: 5,
+                call_sub_lead_idx: 8
+            })
+        ));
+        // ... and all their rows have to fit in the fragment
+        assert!(matches!(
+            spec.set_call(frag_idx, 18, bob),
+            Err(EditError::CallDoesntFit)
+        ));
+        // The call and row must exist
+        assert!(matches!(
+            spec.set_call(frag_idx, 8, Some(CallIdx::new(2))),
+            Err(EditError::CallOutOfRange { len: 2, .. })
+        ));
+        assert!(matches!(
+            spec.set_call(frag_idx, 19, bob),
+            Err(EditError::RowOutOfRange { frag_len: 19, .. })
+        ));
+        assert!(matches!(
+            spec.set_call(FragIdx::new(1), 8, bob),
+            Err(EditError::FragOutOfRange { len: 1, .. })
+        ));
+        // Edits which wouldn't change anything are errors
+        assert!(matches!(
+            spec.set_call(frag_idx, 8, None),
+            Err(EditError::NoCallChange)
+        ));
+        spec.set_call(frag_idx, 8, bob).unwrap();
+        assert!(matches!(
+            spec.set_call(frag_idx, 8, bob),
+            Err(EditError::NoCallChange)
+        ));
+        // Calls can only be changed from their first row
+        assert!(matches!(
+            spec.set_call(frag_idx, 9, None),
+            Err(EditError::OverlappingCalls)
+        ));
+        // None of the failed edits should have changed the fragment
+        assert_eq!(
+            chunks(&spec, 0),
+            [(None, 0, 8), (Some('-'), 8, 2), (None, 0, 9)]
+        );
+    }
+
+    #[test]
+    fn calling_positions() {
+        let mut spec = CompSpec::example();
+        let frag_idx = FragIdx::new(0);
+        // A bob at the end of the first lead (Deva) and a single at the end of the second
+        // (Yorkshire).  Both leads are 32 rows long, and both calls only replace the lead end.
+        spec.set_call(frag_idx, 31, Some(CallIdx::new(0))).unwrap();
+        spec.set_call(frag_idx, 63, Some(CallIdx::new(1))).unwrap();
+        let full_state = FullState::new(&spec);
+        let calling_positions = full_state.fragments[frag_idx]
+            .rows_in_part(PartIdx::new(0))
+            .filter_map(|(idx, data)| Some((idx.index(), data.calling_position?)))
+            .collect::<Vec<_>>();
+        // The bob makes 17864523 and the single makes 14823756, which both put the tenor in 3rds
+        assert_eq!(calling_positions, [(31, "B"), (63, "B")]);
+
+        // Near and far calls use the same names for the same places
+        let names = |call: Call| {
+            (0..8)
+                .map(|place| call.calling_position(place).unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        let expected = ["1", "I", "B", "F", "V", "M", "W", "H"];
+        assert_eq!(names(Call::near_bob(Stage::MAJOR).unwrap()), expected);
+        assert_eq!(names(Call::far_bob(Stage::MAJOR).unwrap()), expected);
+        assert_eq!(names(Call::far_single(Stage::MAJOR).unwrap()), expected);
+    }
+}
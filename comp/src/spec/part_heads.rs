@@ -1,14 +1,16 @@
 //! Code for part head specification.
 
-use std::{cell::RefCell, collections::HashSet};
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fmt::{Display, Formatter},
+    ops::Range,
+};
 
-use bellframe::{IncompatibleStages, InvalidRowError, Row, RowBuf, Stage};
+use bellframe::{Bell, IncompatibleStages, InvalidRowError, Row, RowBuf, Stage};
 use itertools::Itertools;
 use serde::Serialize;
 
-/// The possible ways that parsing a part head specification can fail
-pub type ParseError = InvalidRowError;
-
 /// A struct that stores a specification for a set of part heads.  This contains the [`String`]
 /// that the user entered into the part head box (which must be valid), as well as the
 /// generated set of part heads.  The following invariants must be upheld:
@@ -91,24 +93,37 @@ impl PartHeads {
         }
     }
 
-    /// Attempt to parse then expand a [`str`]ing as a comma-delimited sequence of [`Row`]s.  This
-    /// returns an [`InvalidRowError`] if any of the [`Row`]s couldn't be parsed.
+    /// Attempt to parse then expand a [`str`]ing as a comma-delimited sequence of items, each of
+    /// which generates a set of [`Row`]s.  The items can be:
+    /// - A [`Row`], which generates its closure (i.e. every power of it).  So `1342` generates
+    ///   `[1234, 1342, 1423]`.
+    /// - `cyclic`, which generates every cyclic part head above the treble (e.g. `[12345, 13452,
+    ///   14523, 15234]` on Doubles)
+    /// - `full-cyclic`, which generates every cyclic part head including the treble (e.g.
+    ///   `[12345, 23451, 34512, 45123, 51234]` on Doubles)
+    /// - `<n>-part`, which generates the usual `n`-part group by cycling bells `2` to `n + 1`.  So
+    ///   `3-part` is equivalent to `1342`.
+    /// - A list of [`Row`]s in square brackets (e.g. `[1234, 1342]`), which generates exactly
+    ///   those [`Row`]s, even if they aren't closed under multiplication.
     ///
-    /// Expansion converts a sequence of 'generator' rows to the full sequence of part heads, given
-    /// the generators (e.g. `[1342]` will expand to `[1234, 1342, 1423]`, and `[13452, 15432]`
-    /// will expand to `[12345, 15432, 13452, 12543, ...]`).
-    fn parse_and_expand(s: &str, stage: Stage) -> Result<Vec<RowBuf>, InvalidRowError> {
-        let generators = s
-            .split(',')
-            .map(|sub_str| RowBuf::parse_with_stage(sub_str, stage))
-            .collect::<Result<Vec<_>, _>>()?;
-        // Get the closure (i.e. every power of) each generator
-        let closures = generators
-            .iter()
-            .map(|r| r.closure_from_rounds())
-            .collect_vec();
-        // Multiply every set of powers of `generators` to get the part heads
-        let part_heads = Row::multi_cartesian_product(&closures).unwrap();
+    /// The part heads are then every product of one [`Row`] from each item's set (e.g.
+    /// `13452, 15432` will expand to `[12345, 15432, 13452, 12543, ...]`).  Whitespace and empty
+    /// items are ignored, and the empty string generates only rounds.  The first part head is
+    /// always rounds, so lists have to contain rounds (which is moved to the front), and items
+    /// which would generate the same part head more than once (e.g. `3-part, 1342`) are rejected.
+    fn parse_and_expand(s: &str, stage: Stage) -> Result<Vec<RowBuf>, ParseError> {
+        let mut part_heads = vec![RowBuf::rounds(stage)];
+        for span in split_items(s)? {
+            let row_set = parse_item(s, span.clone(), stage)?;
+            // Multiply the existing part heads by every row generated by this item.  If two of
+            // these products are the same, then this item overlaps with the items before it
+            part_heads = Row::multi_cartesian_product([&part_heads, &row_set]).unwrap();
+            if !part_heads.iter().all_unique() {
+                return Err(ParseError::new(s, span, ParseErrorKind::RedundantPartHeads));
+            }
+        }
+        // Every item's set starts with rounds, so their product must too
+        debug_assert!(part_heads[0].is_rounds());
         Ok(part_heads)
     }
 
@@ -192,6 +207,251 @@ impl PartialEq for PartHeads {
     }
 }
 
+/////////////
+// PARSING //
+/////////////
+
+/// Split `s` into the (trimmed) spans of its comma-separated items, ignoring commas inside square
+/// brackets.  Empty items are skipped.
+fn split_items(s: &str) -> Result<Vec<Range<usize>>, ParseError> {
+    let mut items = Vec::new();
+    let mut item_start = 0;
+    // The index of the '[' which opened the list we're currently inside (if any)
+    let mut list_start: Option<usize> = None;
+    for (i, c) in s.char_indices() {
+        match (c, list_start) {
+            (',', None) => {
+                items.push(item_start..i);
+                item_start = i + 1;
+            }
+            ('[', None) => list_start = Some(i),
+            (']', Some(_)) => list_start = None,
+            // Lists can't be nested, and every ']' must close a list
+            ('[', Some(_)) | (']', None) => {
+                return Err(ParseError::new(
+                    s,
+                    i..i + 1,
+                    ParseErrorKind::UnmatchedBracket,
+                ))
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = list_start {
+        return Err(ParseError::new(
+            s,
+            start..s.len(),
+            ParseErrorKind::UnmatchedBracket,
+        ));
+    }
+    items.push(item_start..s.len());
+
+    Ok(items
+        .into_iter()
+        .map(|span| trim_span(s, span))
+        .filter(|span| !span.is_empty())
+        .collect_vec())
+}
+
+/// Parse a single item of a part head specification into the set of [`Row`]s which it generates
+fn parse_item(s: &str, span: Range<usize>, stage: Stage) -> Result<Vec<RowBuf>, ParseError> {
+    let token = &s[span.clone()];
+
+    // Explicit lists of part heads
+    if token.contains('[') {
+        // A list has to make up the whole item (i.e. `1342 [1234]` is not allowed)
+        if !(token.starts_with('[') && token.ends_with(']')) {
+            return Err(ParseError::new(s, span, ParseErrorKind::MisplacedList));
+        }
+        return parse_list(s, span.start + 1..span.end - 1, stage);
+    }
+
+    // Named groups.  These are case- and whitespace-insensitive
+    let name = token
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    let num_bells = stage.num_bells();
+    let generator = if name == "cyclic" {
+        Some(cycle_bells(stage, 1..num_bells))
+    } else if name == "full-cyclic" {
+        Some(cycle_bells(stage, 0..num_bells))
+    } else if let Some(num_parts) = name.strip_suffix("-part") {
+        let num_parts = num_parts
+            .parse::<usize>()
+            .map_err(|_| ParseError::new(s, span.clone(), ParseErrorKind::UnknownName))?;
+        // An n-part cycles bells `2..=n+1`, so needs at least `n + 1` bells
+        if num_parts == 0 || num_parts >= num_bells.max(2) {
+            let kind = ParseErrorKind::InvalidNumParts { num_parts, stage };
+            return Err(ParseError::new(s, span, kind));
+        }
+        Some(cycle_bells(stage, 1..num_parts + 1))
+    } else {
+        None
+    };
+
+    // Anything else must be a single row, which generates its closure
+    let generator = match generator {
+        Some(g) => g,
+        None => parse_row(s, span, stage)?,
+    };
+    Ok(generator.closure_from_rounds())
+}
+
+/// Parse the contents of a square-bracketed list of part heads (`span` excludes the brackets)
+fn parse_list(s: &str, span: Range<usize>, stage: Stage) -> Result<Vec<RowBuf>, ParseError> {
+    let mut rows = Vec::<RowBuf>::new();
+    let mut row_start = span.start;
+    let row_ends = s[span.clone()]
+        .match_indices(',')
+        .map(|(i, _)| span.start + i)
+        .chain(std::iter::once(span.end));
+    for row_end in row_ends {
+        let row_span = trim_span(s, row_start..row_end);
+        row_start = row_end + 1;
+        if row_span.is_empty() {
+            continue;
+        }
+        let row = parse_row(s, row_span.clone(), stage)?;
+        if rows.contains(&row) {
+            return Err(ParseError::new(
+                s,
+                row_span,
+                ParseErrorKind::DuplicatePartHead,
+            ));
+        }
+        rows.push(row);
+    }
+    // Include the brackets in the span of errors which refer to the whole list
+    let list_span = span.start - 1..span.end + 1;
+    if rows.is_empty() {
+        return Err(ParseError::new(s, list_span, ParseErrorKind::EmptyList));
+    }
+    // Rounds has to be the first part head, so that the first part starts where the composition
+    // does
+    let rounds_idx = rows
+        .iter()
+        .position(|r| r.is_rounds())
+        .ok_or_else(|| ParseError::new(s, list_span, ParseErrorKind::ListWithoutRounds))?;
+    rows[..=rounds_idx].rotate_right(1);
+    Ok(rows)
+}
+
+/// Parse a single [`Row`], extending it to the given [`Stage`] if needed
+fn parse_row(s: &str, span: Range<usize>, stage: Stage) -> Result<RowBuf, ParseError> {
+    let token = &s[span.clone()];
+    // Letters which aren't bell names are most likely a misspelled group name, so shouldn't be
+    // silently ignored
+    if token
+        .chars()
+        .any(|c| c.is_alphabetic() && Bell::from_name(c).is_none())
+    {
+        return Err(ParseError::new(s, span, ParseErrorKind::UnknownName));
+    }
+    RowBuf::parse_with_stage(token, stage)
+        .map_err(|e| ParseError::new(s, span, ParseErrorKind::InvalidRow(e)))
+}
+
+/// Creates the [`Row`] which cycles the bells at the given indices one place to the left (i.e.
+/// `cycle_bells(Stage::DOUBLES, 1..4)` is `13425`).
+fn cycle_bells(stage: Stage, indices: Range<usize>) -> RowBuf {
+    let mut bells = (0..stage.num_bells()).map(Bell::from_index).collect_vec();
+    bells[indices].rotate_left(1);
+    RowBuf::from_vec(bells).expect("Cycling rounds should always give a valid row")
+}
+
+/// Shrink `span` so that the section of `s` it covers has no leading or trailing whitespace
+fn trim_span(s: &str, span: Range<usize>) -> Range<usize> {
+    let sub_str = &s[span.clone()];
+    let start = span.start + (sub_str.len() - sub_str.trim_start().len());
+    let end = span.end - (sub_str.len() - sub_str.trim_end().len());
+    start..end.max(start)
+}
+
+/// The ways that parsing a part head specification can fail, along with the token which caused
+/// the problem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The byte range of the offending token within the part head string
+    pub span: Range<usize>,
+    /// The offending token itself
+    pub token: String,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// A token which should be a [`Row`] doesn't form a valid [`Row`]
+    InvalidRow(InvalidRowError),
+    /// A token contains letters which aren't bell names, but isn't a known group name
+    UnknownName,
+    /// An `<n>-part` group doesn't exist on this [`Stage`]
+    InvalidNumParts { num_parts: usize, stage: Stage },
+    /// A `[` is never closed, a `]` never opened, or lists are nested
+    UnmatchedBracket,
+    /// A list is part of a larger item (e.g. `1342 [1234]`)
+    MisplacedList,
+    /// A list doesn't contain any part heads
+    EmptyList,
+    /// The same part head appears twice in a list
+    DuplicatePartHead,
+    /// A list doesn't contain rounds
+    ListWithoutRounds,
+    /// An item generates part heads which have already been generated by earlier items (e.g. the
+    /// `1342` in `3-part, 1342`)
+    RedundantPartHeads,
+}
+
+impl ParseError {
+    fn new(s: &str, span: Range<usize>, kind: ParseErrorKind) -> Self {
+        Self {
+            token: s[span.clone()].to_owned(),
+            span,
+            kind,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let token = &self.token;
+        match &self.kind {
+            ParseErrorKind::InvalidRow(e) => write!(f, "Invalid row '{}': {}", token, e),
+            ParseErrorKind::UnknownName => write!(
+                f,
+                "Unknown part heads '{}' (expected a row, 'cyclic', 'full-cyclic' or '<n>-part')",
+                token
+            ),
+            ParseErrorKind::InvalidNumParts { num_parts, stage } => write!(
+                f,
+                "'{}': can't make a {}-part on {} bells",
+                token,
+                num_parts,
+                stage.num_bells()
+            ),
+            ParseErrorKind::UnmatchedBracket => write!(f, "Unmatched bracket '{}'", token),
+            ParseErrorKind::MisplacedList => {
+                write!(f, "'{}': lists must be separated by commas", token)
+            }
+            ParseErrorKind::EmptyList => write!(f, "Empty list '{}'", token),
+            ParseErrorKind::DuplicatePartHead => {
+                write!(f, "Part head '{}' appears twice in a list", token)
+            }
+            ParseErrorKind::ListWithoutRounds => {
+                write!(f, "List '{}' doesn't contain rounds", token)
+            }
+            ParseErrorKind::RedundantPartHeads => write!(
+                f,
+                "'{}' generates part heads which are already generated by earlier items",
+                token
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 /// The result of successfully [`reparsing`](PartHeads::try_reparse) a sequence of [`PartHeads`] - i.e.
 /// modifying the 'part head' box in the GUI.
 #[derive(Debug, Clone)]
@@ -204,3 +464,113 @@ pub enum ReparseOk {
     /// [`PartHeads`] is returned.
     DifferentRows(PartHeads),
 }
+
+#[cfg(test)]
+mod tests {
+    use bellframe::{RowBuf, Stage};
+
+    use super::{ParseErrorKind, PartHeads};
+
+    fn rows(s: &str, stage: Stage) -> Vec<String> {
+        let part_heads = PartHeads::parse(s, stage).unwrap();
+        part_heads.rows().iter().map(RowBuf::to_string).collect()
+    }
+
+    fn err(s: &str, stage: Stage) -> (std::ops::Range<usize>, String, ParseErrorKind) {
+        let e = PartHeads::parse(s, stage).unwrap_err();
+        (e.span, e.token, e.kind)
+    }
+
+    #[test]
+    fn expand() {
+        #[rustfmt::skip]
+        let cases: &[(&str, Stage, &[&str])] = &[
+            ("", Stage::MAJOR, &["12345678"]),
+            (" , ,", Stage::MAJOR, &["12345678"]),
+            ("1342", Stage::MAJOR, &["12345678", "13425678", "14235678"]),
+            ("3-part", Stage::MAJOR, &["12345678", "13425678", "14235678"]),
+            ("cyclic", Stage::DOUBLES, &["12345", "13452", "14523", "15234"]),
+            (" Cyclic ", Stage::DOUBLES, &["12345", "13452", "14523", "15234"]),
+            ("full - cyclic", Stage::DOUBLES, &["12345", "23451", "34512", "45123", "51234"]),
+            ("[1234, 1342]", Stage::MAJOR, &["12345678", "13425678"]),
+            // Rounds is moved to the front of lists
+            ("[ 1342 ,1423, 1234 ]", Stage::MAJOR, &["12345678", "13425678", "14235678"]),
+            (
+                "3-part, [12345678, 12345687]",
+                Stage::MAJOR,
+                &["12345678", "13425678", "14235678", "12345687", "13425687", "14235687"],
+            ),
+        ];
+        for &(s, stage, exp_rows) in cases {
+            assert_eq!(rows(s, stage), exp_rows, "expanding {:?}", s);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        #[rustfmt::skip]
+        let cases: &[(&str, std::ops::Range<usize>, &str)] = &[
+            ("foo", 0..3, "foo"),
+            ("1342, cyclc", 6..11, "cyclc"),
+            ("8-part", 0..6, "8-part"),
+            ("0-part", 0..6, "0-part"),
+            ("1342, [1234", 6..11, "[1234"),
+            ("1234]", 4..5, "]"),
+            ("[1234, [1342]]", 7..8, "["),
+            ("1342 [1234]", 0..11, "1342 [1234]"),
+            ("[ , ]", 0..5, "[ , ]"),
+            ("[1234, 1342, 1234]", 13..17, "1234"),
+            ("[1342, 1423]", 0..12, "[1342, 1423]"),
+            ("3-part, 1342", 8..12, "1342"),
+            ("[1234, 1342], 1423", 14..18, "1423"),
+            ("1342, 1423", 6..10, "1423"),
+        ];
+        for (s, exp_span, exp_token) in cases {
+            let (span, token, _) = err(s, Stage::MAJOR);
+            assert_eq!(
+                (&span, token.as_str()),
+                (exp_span, *exp_token),
+                "parsing {:?}",
+                s
+            );
+        }
+
+        // Check the kinds of a few errors
+        let kind = |s: &str| err(s, Stage::MAJOR).2;
+        assert_eq!(kind("foo"), ParseErrorKind::UnknownName);
+        assert_eq!(
+            kind("8-part"),
+            ParseErrorKind::InvalidNumParts {
+                num_parts: 8,
+                stage: Stage::MAJOR
+            }
+        );
+        assert_eq!(kind("1234]"), ParseErrorKind::UnmatchedBracket);
+        assert_eq!(kind("1342 [1234]"), ParseErrorKind::MisplacedList);
+        assert_eq!(kind("[ , ]"), ParseErrorKind::EmptyList);
+        assert_eq!(
+            kind("[1234, 1342, 1234]"),
+            ParseErrorKind::DuplicatePartHead
+        );
+        assert_eq!(kind("[1342, 1423]"), ParseErrorKind::ListWithoutRounds);
+        assert_eq!(kind("3-part, 1342"), ParseErrorKind::RedundantPartHeads);
+        assert!(matches!(kind("1123"), ParseErrorKind::InvalidRow(_)));
+    }
+
+    #[test]
+    fn reparse() {
+        let part_heads = PartHeads::parse("3-part", Stage::MAJOR).unwrap();
+        // Same rows, so the spec string is updated in place
+        assert!(matches!(
+            part_heads.try_reparse("1342"),
+            Ok(super::ReparseOk::SameRows)
+        ));
+        assert_eq!(part_heads.spec_string(), "1342");
+        // Different rows
+        match part_heads.try_reparse("cyclic") {
+            Ok(super::ReparseOk::DifferentRows(new)) => assert_eq!(new.len(), 7),
+            r => panic!("Expected different rows, got {:?}", r),
+        }
+        assert!(part_heads.try_reparse("[1342]").is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! Code for specifying where rule-offs are drawn in a [`Method`](super::Method)
+
+use std::fmt::{Display, Formatter};
+
+/// The number of sub-lead indices stored in each word of a [`Ruleoffs`] bitmask
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// The set of sub-lead indices of a [`Method`](super::Method) which have rule-offs drawn
+/// **above** them, stored as a bitmask.  Index `0` is the lead head, so a rule-off there is drawn
+/// between the lead end and the next lead head.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub(crate) struct Ruleoffs {
+    /// Bit `i % 64` of word `i / 64` is set if sub-lead index `i` has a rule-off above it
+    mask: Vec<u64>,
+}
+
+impl Ruleoffs {
+    /// Creates a set of `Ruleoffs` containing the given sub-lead indices
+    pub fn from_indices(indices: impl IntoIterator<Item = usize>) -> Self {
+        let mut ruleoffs = Self::default();
+        for idx in indices {
+            ruleoffs.insert(idx);
+        }
+        ruleoffs
+    }
+
+    /// Creates the set of `Ruleoffs` generated by a [`RuleoffPreset`] for a lead of a given length
+    pub fn from_preset(preset: RuleoffPreset, lead_len: usize) -> Self {
+        Self::from_indices(preset.indices(lead_len))
+    }
+
+    fn insert(&mut self, idx: usize) {
+        let word_idx = idx / BITS_PER_WORD;
+        if word_idx >= self.mask.len() {
+            self.mask.resize(word_idx + 1, 0);
+        }
+        self.mask[word_idx] |= 1 << (idx % BITS_PER_WORD);
+    }
+
+    /// Returns `true` if there is a rule-off above the row at `sub_lead_idx`
+    pub fn is_ruleoff_above(&self, sub_lead_idx: usize) -> bool {
+        let word = self.mask.get(sub_lead_idx / BITS_PER_WORD).copied();
+        word.unwrap_or(0) & (1 << (sub_lead_idx % BITS_PER_WORD)) != 0
+    }
+
+    /// An [`Iterator`] over the sub-lead indices with rule-offs above them, in increasing order
+    pub fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        let max_idx = self.mask.len() * BITS_PER_WORD;
+        (0..max_idx).filter(move |idx| self.is_ruleoff_above(*idx))
+    }
+
+    /// Returns the [`RuleoffPreset`] which generates these `Ruleoffs` for a lead of length
+    /// `lead_len`, or `None` if they don't correspond to any preset.
+    pub fn preset(&self, lead_len: usize) -> Option<RuleoffPreset> {
+        let mut indices = self.indices();
+        let candidates = match (indices.next(), indices.next()) {
+            (None, _) => vec![RuleoffPreset::None],
+            // A single rule-off could be the lead end, or a single rule-off somewhere else in the
+            // lead
+            (Some(offset), None) => vec![
+                RuleoffPreset::LeadEnd,
+                RuleoffPreset::EveryNRows {
+                    n: lead_len,
+                    offset,
+                },
+            ],
+            // Otherwise, the gap between the first two rule-offs determines `n`
+            (Some(first), Some(second)) => vec![
+                RuleoffPreset::HalfLead,
+                RuleoffPreset::EveryNRows {
+                    n: second - first,
+                    offset: first,
+                },
+            ],
+        };
+        candidates
+            .into_iter()
+            .find(|preset| Self::from_preset(*preset, lead_len) == *self)
+    }
+}
+
+/// The common ways of placing rule-offs in a [`Method`](super::Method)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RuleoffPreset {
+    /// No rule-offs at all
+    None,
+    /// A rule-off at every lead end (the default for most methods)
+    #[default]
+    LeadEnd,
+    /// Rule-offs at every lead end and half-lead
+    HalfLead,
+    /// A rule-off every `n` rows, with the first one above the row at sub-lead index `offset`.
+    /// For example, Stedman Triples (`3.1.7.3.1.3,1`) has six ends every 6 rows with an offset
+    /// of 3.
+    EveryNRows { n: usize, offset: usize },
+}
+
+impl RuleoffPreset {
+    /// The sub-lead indices which have rule-offs above them when this preset is applied to a lead
+    /// of length `lead_len`
+    pub fn indices(self, lead_len: usize) -> Vec<usize> {
+        match self {
+            RuleoffPreset::None => vec![],
+            RuleoffPreset::LeadEnd => vec![0],
+            RuleoffPreset::HalfLead => vec![0, lead_len / 2],
+            RuleoffPreset::EveryNRows { n, offset } => {
+                // `n = 0` would place infinitely many rule-offs, so is treated as `n = 1`
+                let step = n.max(1);
+                (offset % step..lead_len).step_by(step).collect()
+            }
+        }
+    }
+}
+
+impl Display for RuleoffPreset {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            RuleoffPreset::None => write!(f, "None"),
+            RuleoffPreset::LeadEnd => write!(f, "Lead end"),
+            RuleoffPreset::HalfLead => write!(f, "Half lead"),
+            RuleoffPreset::EveryNRows { n, offset } => {
+                write!(f, "Every {} rows (offset {})", n, offset)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use jigsaw_utils::indexed_vec::MethodIdx;
+
+    use crate::spec::CompSpec;
+
+    use super::{RuleoffPreset, Ruleoffs};
+
+    #[test]
+    fn preset_indices() {
+        let cases = [
+            (RuleoffPreset::None, vec![]),
+            (RuleoffPreset::LeadEnd, vec![0]),
+            (RuleoffPreset::HalfLead, vec![0, 16]),
+            (
+                RuleoffPreset::EveryNRows { n: 6, offset: 3 },
+                vec![3, 9, 15, 21, 27],
+            ),
+            // An offset of 0 puts a rule-off above the lead head
+            (
+                RuleoffPreset::EveryNRows { n: 8, offset: 0 },
+                vec![0, 8, 16, 24],
+            ),
+            // Offsets larger than `n` wrap round
+            (
+                RuleoffPreset::EveryNRows { n: 6, offset: 9 },
+                vec![3, 9, 15, 21, 27],
+            ),
+            // `n = 0` is treated as `n = 1`
+            (
+                RuleoffPreset::EveryNRows { n: 0, offset: 0 },
+                (0..32).collect(),
+            ),
+        ];
+        for (preset, indices) in cases {
+            assert_eq!(preset.indices(32), indices, "{}", preset);
+        }
+        // Half leads of odd lengths are rounded down
+        assert_eq!(RuleoffPreset::HalfLead.indices(7), [0, 3]);
+    }
+
+    #[test]
+    fn preset_round_trip() {
+        for lead_len in [12, 32] {
+            let presets = [
+                RuleoffPreset::None,
+                RuleoffPreset::LeadEnd,
+                RuleoffPreset::HalfLead,
+                RuleoffPreset::EveryNRows { n: 3, offset: 1 },
+                RuleoffPreset::EveryNRows { n: 5, offset: 2 },
+                RuleoffPreset::EveryNRows {
+                    n: lead_len,
+                    offset: 4,
+                },
+            ];
+            for preset in presets {
+                let ruleoffs = Ruleoffs::from_preset(preset, lead_len);
+                assert_eq!(ruleoffs.preset(lead_len), Some(preset), "{}", preset);
+            }
+        }
+    }
+
+    /// If several presets generate the same rule-offs, then the simplest one is recovered
+    #[test]
+    fn ambiguous_presets() {
+        let recovered = |preset: RuleoffPreset, lead_len: usize| {
+            Ruleoffs::from_preset(preset, lead_len).preset(lead_len)
+        };
+        // Rule-offs every half lead or every lead, starting at the lead head
+        assert_eq!(
+            recovered(RuleoffPreset::EveryNRows { n: 16, offset: 0 }, 32),
+            Some(RuleoffPreset::HalfLead)
+        );
+        assert_eq!(
+            recovered(RuleoffPreset::EveryNRows { n: 32, offset: 0 }, 32),
+            Some(RuleoffPreset::LeadEnd)
+        );
+        // Offsets are reduced to below `n`, and `n` is reduced to the lead length if only one
+        // rule-off fits in the lead
+        assert_eq!(
+            recovered(RuleoffPreset::EveryNRows { n: 6, offset: 9 }, 32),
+            Some(RuleoffPreset::EveryNRows { n: 6, offset: 3 })
+        );
+        assert_eq!(
+            recovered(RuleoffPreset::EveryNRows { n: 20, offset: 5 }, 12),
+            Some(RuleoffPreset::EveryNRows { n: 12, offset: 5 })
+        );
+        // Rule-offs which aren't evenly spaced don't match any preset
+        assert_eq!(Ruleoffs::from_indices([0, 5]).preset(32), None);
+    }
+
+    #[test]
+    fn edit_method_ruleoffs() {
+        let mut spec = CompSpec::example();
+        let deva = MethodIdx::new(0); // Has a 32-row lead
+        let deva_pn = spec.methods[deva].place_notation().to_owned();
+        let yorkshire_pn = "-38-14-58-16-12-38-14-78,12"; // Also has a 32-row lead
+        let ruleoffs = |spec: &CompSpec| {
+            spec.methods[deva]
+                .ruleoffs_above
+                .indices()
+                .collect::<Vec<_>>()
+        };
+
+        // A rule-off above the lead head is also below the lead end
+        let every_8_rows = RuleoffPreset::EveryNRows { n: 8, offset: 0 };
+        (spec.edit_method(deva, &deva_pn, Some(every_8_rows))).unwrap();
+        assert_eq!(ruleoffs(&spec), [0, 8, 16, 24]);
+        assert!(spec.methods[deva].is_ruleoff_below(31));
+
+        // Rule-offs which don't match a preset are kept if the lead length doesn't change ...
+        Rc::make_mut(&mut spec.methods[deva]).ruleoffs_above = Ruleoffs::from_indices([3, 5]);
+        spec.edit_method(deva, yorkshire_pn, None).unwrap();
+        assert_eq!(ruleoffs(&spec), [3, 5]);
+        assert_eq!(spec.methods[deva].ruleoff_preset(), None);
+        // ... but are reset to lead ends if it does
+        spec.edit_method(deva, "x18x18x18x18,12", None).unwrap();
+        assert_eq!(ruleoffs(&spec), [0]);
+
+        // Presets are applied to the new lead length
+        let half_lead = Some(RuleoffPreset::HalfLead);
+        (spec.edit_method(deva, &deva_pn, half_lead)).unwrap();
+        assert_eq!(ruleoffs(&spec), [0, 16]);
+        (spec.edit_method(deva, "x18x18x18x18,12", half_lead)).unwrap();
+        assert_eq!(ruleoffs(&spec), [0, 8]);
+    }
+}
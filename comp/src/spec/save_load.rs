@@ -0,0 +1,777 @@
+//! Code to save and load [`CompSpec`]s as JSON files.
+//!
+//! The on-disk format is deliberately separate from [`CompSpec`]'s in-memory representation.
+//! [`Method`]s and [`Call`]s are stored once at the top level and referred to by index (rather
+//! than by [`Rc`]), and every file contains a `version` number so that files written by older
+//! versions of Jigsaw can be migrated to the current format before being loaded.
+//!
+//! A composition's [`Music`] can either be embedded in the file, or loaded from a separate
+//! [music definition file](crate::music_file) which is referenced by path.
+
+// This lint gives false positives for raw pointers (which are hashed by the memory address they
+// point to).  See https://github.com/rust-lang/rust-clippy/issues/6745
+#![allow(clippy::mutable_key_type)]
+
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use bellframe::{place_not::PnBlockParseError, InvalidRowError, RowBuf, Stage};
+use emath::Pos2;
+use jigsaw_utils::{
+    indexed_vec::{CallIdx, ChunkIdx, FragIdx, MethodIdx},
+    types::Stroke,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{music::Music, music_file};
+
+use super::{
+    part_heads::{self, PartHeads},
+    ruleoffs::Ruleoffs,
+    Call, Chunk, CompSpec, FragId, Fragment, Method,
+};
+
+/// The version of the file format written by this version of Jigsaw.  This must be incremented
+/// whenever the format changes, and a corresponding migration added to [`MIGRATIONS`].
+pub const CURRENT_VERSION: u64 = 1;
+
+/// A function which upgrades the JSON of a file from one version to the next
+type Migration = fn(&mut Value) -> Result<(), LoadError>;
+
+/// Functions to upgrade the JSON of older file formats.  `MIGRATIONS[i]` converts a file of
+/// version `i + 1` into a file of version `i + 2`, so `MIGRATIONS.len()` must always equal
+/// `CURRENT_VERSION - 1`.
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize - 1] = [];
+
+impl CompSpec {
+    /// Serialises `self` into a (pretty-printed) JSON string, using the current version of the
+    /// file format.
+    pub fn to_json(&self) -> String {
+        let ser_spec = SerSpec::from_spec(self);
+        serde_json::to_string_pretty(&ser_spec).expect("Serialising a `CompSpec` can't fail")
+    }
+
+    /// Parses a [`CompSpec`] from a JSON string, migrating it from older versions of the file
+    /// format if needed.  If the composition references a music definition file by a relative
+    /// path, then that path is relative to `dir` (usually the directory containing the
+    /// composition file).
+    pub fn from_json(json: &str, dir: &Path) -> Result<Self, LoadError> {
+        let mut value: Value = serde_json::from_str(json).map_err(LoadError::Json)?;
+        migrate(&mut value)?;
+        let ser_spec: SerSpec = serde_json::from_value(value).map_err(LoadError::Json)?;
+        ser_spec.into_spec(dir)
+    }
+}
+
+/// Upgrades the JSON of any supported file version to [`CURRENT_VERSION`]
+fn migrate(value: &mut Value) -> Result<(), LoadError> {
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or(LoadError::NoVersion)?;
+    if version == 0 || version > CURRENT_VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    // Apply the migrations in turn, updating the version number as we go
+    for (from_version, migration) in (version..).zip(&MIGRATIONS[version as usize - 1..]) {
+        migration(value)?;
+        value["version"] = Value::from(from_version + 1);
+    }
+    Ok(())
+}
+
+/////////////////
+// FILE FORMAT //
+/////////////////
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SerSpec {
+    version: u64,
+    stage: Stage,
+    /// The part head specification string, exactly as the user typed it
+    part_heads: String,
+    methods: Vec<SerMethod>,
+    calls: Vec<SerCall>,
+    /// A music definition file to load the music from.  If this is given, `music` is ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    music_file: Option<PathBuf>,
+    /// The composition's music, if it's embedded in the composition file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    music: Vec<SerMusic>,
+    fragments: Vec<SerFragment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SerMethod {
+    name: String,
+    shorthand: String,
+    place_notation: String,
+    /// Sorted list of sub-lead indices which have rule-offs **above** them
+    ruleoffs_above: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SerCall {
+    symbol: char,
+    place_notation: String,
+    sub_lead_idx_after: usize,
+    calling_positions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SerMusic {
+    Regex {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        regex: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        weight: Option<f32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stroke: Option<Stroke>,
+    },
+    Group {
+        name: String,
+        music: Vec<SerMusic>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        weight: Option<f32>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        unique_rows: bool,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SerFragment {
+    position: (f32, f32),
+    start_row: String,
+    is_proved: bool,
+    chunks: Vec<SerChunk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SerChunk {
+    Method {
+        method: usize,
+        start_sub_lead_index: usize,
+        length: usize,
+    },
+    Call {
+        call: usize,
+        method: usize,
+    },
+}
+
+////////////
+// SAVING //
+////////////
+
+impl SerSpec {
+    fn from_spec(spec: &CompSpec) -> Self {
+        // Maps the addresses of methods/calls to their indices, so that chunks can refer to them
+        let method_indices = spec
+            .methods
+            .iter()
+            .enumerate()
+            .map(|(idx, m)| (m.as_ref() as *const Method, idx))
+            .collect::<HashMap<_, _>>();
+        let call_indices = spec
+            .calls
+            .iter()
+            .enumerate()
+            .map(|(idx, c)| (c.as_ref() as *const Call, idx))
+            .collect::<HashMap<_, _>>();
+        let method_idx = |m: &Rc<Method>| method_indices[&(m.as_ref() as *const Method)];
+        let call_idx = |c: &Rc<Call>| call_indices[&(c.as_ref() as *const Call)];
+
+        Self {
+            version: CURRENT_VERSION,
+            stage: spec.stage,
+            part_heads: spec.part_heads.spec_string(),
+            methods: spec
+                .methods
+                .iter()
+                .map(|m| SerMethod::from(m.as_ref()))
+                .collect(),
+            calls: spec
+                .calls
+                .iter()
+                .map(|c| SerCall::from(c.as_ref()))
+                .collect(),
+            music_file: spec.music_file.clone(),
+            // Music loaded from a file is written as a reference to that file, not embedded
+            music: match spec.music_file {
+                Some(_) => Vec::new(),
+                None => spec.music.iter().map(SerMusic::from).collect(),
+            },
+            fragments: spec
+                .fragments
+                .iter()
+                .map(|f| SerFragment {
+                    position: (f.position.x, f.position.y),
+                    start_row: f.start_row.to_string(),
+                    is_proved: f.is_proved,
+                    chunks: f
+                        .chunks
+                        .iter()
+                        .map(|c| match c.as_ref() {
+                            Chunk::Method {
+                                method,
+                                start_sub_lead_index,
+                                length,
+                                transposition: _,
+                            } => SerChunk::Method {
+                                method: method_idx(method),
+                                start_sub_lead_index: *start_sub_lead_index,
+                                length: *length,
+                            },
+                            Chunk::Call {
+                                call,
+                                method,
+                                start_sub_lead_index: _,
+                            } => SerChunk::Call {
+                                call: call_idx(call),
+                                method: method_idx(method),
+                            },
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&Method> for SerMethod {
+    fn from(m: &Method) -> Self {
+        Self {
+            name: m.name().clone(),
+            shorthand: m.shorthand().clone(),
+            place_notation: m.place_notation().to_owned(),
+            ruleoffs_above: m.ruleoffs_above.indices().collect(),
+        }
+    }
+}
+
+impl From<&Call> for SerCall {
+    fn from(c: &Call) -> Self {
+        Self {
+            symbol: c.symbol,
+            place_notation: c.place_notation.clone(),
+            sub_lead_idx_after: c.sub_lead_idx_after,
+            calling_positions: c.calling_positions.clone(),
+        }
+    }
+}
+
+impl From<&Music> for SerMusic {
+    fn from(m: &Music) -> Self {
+        match m {
+            Music::Regex {
+                name,
+                regex,
+                weight,
+                stroke,
+            } => SerMusic::Regex {
+                name: name.clone(),
+                regex: regex.to_string(),
+                weight: *weight,
+                stroke: *stroke,
+            },
+            Music::Group {
+                name,
+                sub_groups,
+                weight,
+                unique_rows,
+            } => SerMusic::Group {
+                name: name.clone(),
+                music: sub_groups.iter().map(SerMusic::from).collect(),
+                weight: *weight,
+                unique_rows: *unique_rows,
+            },
+        }
+    }
+}
+
+/////////////
+// LOADING //
+/////////////
+
+impl SerSpec {
+    fn into_spec(self, dir: &Path) -> Result<CompSpec, LoadError> {
+        let stage = self.stage;
+        let part_heads = PartHeads::parse(&self.part_heads, stage).map_err(LoadError::PartHeads)?;
+
+        let methods = self
+            .methods
+            .into_iter()
+            .enumerate()
+            .map(|(idx, m)| {
+                let method_idx = MethodIdx::new(idx);
+                let mut method = Method::new(
+                    m.name,
+                    m.shorthand,
+                    &m.place_notation,
+                    stage,
+                    Ruleoffs::default(),
+                )
+                .map_err(|error| LoadError::MethodPn { method_idx, error })?;
+                // Rule-offs can only be checked once the lead length is known
+                if let Some(&sub_lead_idx) = m
+                    .ruleoffs_above
+                    .iter()
+                    .find(|idx| **idx >= method.lead_len())
+                {
+                    return Err(LoadError::RuleoffOutOfRange {
+                        method_idx,
+                        sub_lead_idx,
+                    });
+                }
+                method.ruleoffs_above = Ruleoffs::from_indices(m.ruleoffs_above);
+                Ok(Rc::new(method))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let calls = self
+            .calls
+            .into_iter()
+            .enumerate()
+            .map(|(idx, c)| {
+                let call = Call::new(
+                    c.symbol,
+                    &c.place_notation,
+                    c.sub_lead_idx_after,
+                    c.calling_positions,
+                    stage,
+                );
+                call.map(Rc::new).map_err(|error| LoadError::CallPn {
+                    call_idx: CallIdx::new(idx),
+                    error,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let fragments = self
+            .fragments
+            .into_iter()
+            .enumerate()
+            .map(|(idx, f)| f.into_fragment(FragIdx::new(idx), &methods, &calls, stage))
+            .collect::<Result<_, _>>()?;
+
+        let music = match &self.music_file {
+            Some(path) => {
+                music_file::load(&dir.join(path), stage).map_err(|error| LoadError::MusicFile {
+                    path: path.clone(),
+                    error,
+                })?
+            }
+            None => (self.music.into_iter().enumerate())
+                .map(|(idx, m)| m.into_music(stage, &mut vec![idx]))
+                .collect::<Result<_, _>>()?,
+        };
+
+        Ok(CompSpec {
+            fragments,
+            part_heads: Rc::new(part_heads),
+            methods: methods.into_iter().collect(),
+            calls: calls.into_iter().collect(),
+            music: Rc::new(music),
+            music_file: self.music_file,
+            stage,
+        })
+    }
+}
+
+impl SerFragment {
+    fn into_fragment(
+        self,
+        frag_idx: FragIdx,
+        methods: &[Rc<Method>],
+        calls: &[Rc<Call>],
+        stage: Stage,
+    ) -> Result<Rc<Fragment>, LoadError> {
+        let start_row = RowBuf::parse_with_stage(&self.start_row, stage)
+            .map_err(|error| LoadError::StartRow { frag_idx, error })?;
+        if self.chunks.is_empty() {
+            return Err(LoadError::EmptyFragment(frag_idx));
+        }
+
+        let chunks = self
+            .chunks
+            .into_iter()
+            .enumerate()
+            .map(|(idx, chunk)| {
+                let chunk_idx = ChunkIdx::new(idx);
+                let invalid_chunk = |reason| LoadError::InvalidChunk {
+                    frag_idx,
+                    chunk_idx,
+                    reason,
+                };
+                let get_method = |method_idx: usize| {
+                    methods.get(method_idx).cloned().ok_or(invalid_chunk(
+                        InvalidChunkReason::MethodOutOfRange(method_idx),
+                    ))
+                };
+                let chunk = match chunk {
+                    SerChunk::Method {
+                        method,
+                        start_sub_lead_index,
+                        length,
+                    } => {
+                        let method = get_method(method)?;
+                        if length == 0 {
+                            return Err(invalid_chunk(InvalidChunkReason::Empty));
+                        }
+                        if start_sub_lead_index >= method.lead_len() {
+                            return Err(invalid_chunk(InvalidChunkReason::SubLeadIdxOutOfRange(
+                                start_sub_lead_index,
+                            )));
+                        }
+                        Chunk::method(method, start_sub_lead_index, length)
+                    }
+                    SerChunk::Call { call, method } => {
+                        let method = get_method(method)?;
+                        let call = calls
+                            .get(call)
+                            .cloned()
+                            .ok_or(invalid_chunk(InvalidChunkReason::CallOutOfRange(call)))?;
+                        if call.len() > method.lead_len() {
+                            return Err(invalid_chunk(InvalidChunkReason::CallDoesntFit));
+                        }
+                        Chunk::Call {
+                            start_sub_lead_index: call.start_sub_lead_index(&method),
+                            call,
+                            method,
+                        }
+                    }
+                };
+                Ok(Rc::new(chunk))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Rc::new(Fragment {
+            id: FragId::new(),
+            position: Pos2::new(self.position.0, self.position.1),
+            start_row: Rc::new(start_row),
+            chunks,
+            is_proved: self.is_proved,
+        }))
+    }
+}
+
+impl SerMusic {
+    /// Converts `self` into [`Music`], checking that its regexes are valid on the given [`Stage`].
+    /// `path` is the index of `self` within each enclosing group, and is used for error messages.
+    fn into_music(self, stage: Stage, path: &mut Vec<usize>) -> Result<Music, LoadError> {
+        Ok(match self {
+            SerMusic::Regex {
+                name,
+                regex,
+                weight,
+                stroke,
+            } => Music::Regex {
+                name,
+                regex: music_file::parse_regex(&regex, stage).map_err(|error| {
+                    LoadError::InvalidMusic {
+                        path: path.clone(),
+                        error,
+                    }
+                })?,
+                weight,
+                stroke,
+            },
+            SerMusic::Group {
+                name,
+                music,
+                weight,
+                unique_rows,
+            } => {
+                let mut sub_groups = Vec::with_capacity(music.len());
+                for (idx, m) in music.into_iter().enumerate() {
+                    path.push(idx);
+                    sub_groups.push(m.into_music(stage, path)?);
+                    path.pop();
+                }
+                Music::Group {
+                    name,
+                    sub_groups,
+                    weight,
+                    unique_rows,
+                }
+            }
+        })
+    }
+}
+
+/////////////////
+// ERROR TYPES //
+/////////////////
+
+/// The possible ways that loading a [`CompSpec`] from JSON can fail
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file wasn't valid JSON, or didn't match the expected structure
+    Json(serde_json::Error),
+    /// The file doesn't have a `version` field
+    NoVersion,
+    /// The file's version isn't supported by this version of Jigsaw (usually because it was
+    /// written by a newer version)
+    UnsupportedVersion(u64),
+    PartHeads(part_heads::ParseError),
+    /// The music definition file referenced by the composition couldn't be loaded
+    MusicFile {
+        path: PathBuf,
+        error: music_file::LoadError,
+    },
+    /// Some music embedded in the composition file is invalid (e.g. a regex contains bells which
+    /// aren't in the composition's [`Stage`]).  `path` is the index of the music within each
+    /// enclosing group, starting from the top level.
+    InvalidMusic {
+        path: Vec<usize>,
+        error: music_file::ParseErrorKind,
+    },
+    MethodPn {
+        method_idx: MethodIdx,
+        error: PnBlockParseError,
+    },
+    /// A method has a rule-off at a sub-lead index which is outside its lead
+    RuleoffOutOfRange {
+        method_idx: MethodIdx,
+        sub_lead_idx: usize,
+    },
+    CallPn {
+        call_idx: CallIdx,
+        error: PnBlockParseError,
+    },
+    StartRow {
+        frag_idx: FragIdx,
+        error: InvalidRowError,
+    },
+    /// A fragment contains no chunks (i.e. no rows other than the leftover row)
+    EmptyFragment(FragIdx),
+    InvalidChunk {
+        frag_idx: FragIdx,
+        chunk_idx: ChunkIdx,
+        reason: InvalidChunkReason,
+    },
+}
+
+/// The possible reasons that a single chunk of a fragment can be invalid
+#[derive(Debug, Clone)]
+pub enum InvalidChunkReason {
+    MethodOutOfRange(usize),
+    CallOutOfRange(usize),
+    SubLeadIdxOutOfRange(usize),
+    /// A method chunk has a length of 0
+    Empty,
+    /// A call is longer than a lead of its method
+    CallDoesntFit,
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Json(e) => write!(f, "Invalid composition file: {}", e),
+            LoadError::NoVersion => write!(f, "Composition file has no version number"),
+            LoadError::UnsupportedVersion(v) => write!(
+                f,
+                "Composition file has version {}, but only versions 1 to {} are supported",
+                v, CURRENT_VERSION
+            ),
+            LoadError::PartHeads(e) => write!(f, "Invalid part heads: {}", e),
+            LoadError::MusicFile { path, error } => {
+                write!(f, "Error in music file '{}': {}", path.display(), error)
+            }
+            LoadError::InvalidMusic { path, error } => {
+                let path = path.iter().map(usize::to_string).collect::<Vec<_>>();
+                write!(f, "Invalid music #{}: {}", path.join("."), error)
+            }
+            LoadError::MethodPn { method_idx, error } => {
+                write!(
+                    f,
+                    "Invalid place notation for method #{}: {}",
+                    method_idx.index(),
+                    error
+                )
+            }
+            LoadError::RuleoffOutOfRange {
+                method_idx,
+                sub_lead_idx,
+            } => write!(
+                f,
+                "Method #{} has a rule-off at index {}, which is outside the lead",
+                method_idx.index(),
+                sub_lead_idx
+            ),
+            LoadError::CallPn { call_idx, error } => {
+                write!(
+                    f,
+                    "Invalid place notation for call #{}: {}",
+                    call_idx.index(),
+                    error
+                )
+            }
+            LoadError::StartRow { frag_idx, error } => {
+                write!(
+                    f,
+                    "Invalid start row for fragment #{}: {}",
+                    frag_idx.index(),
+                    error
+                )
+            }
+            LoadError::EmptyFragment(frag_idx) => {
+                write!(f, "Fragment #{} has no chunks", frag_idx.index())
+            }
+            LoadError::InvalidChunk {
+                frag_idx,
+                chunk_idx,
+                reason,
+            } => {
+                write!(
+                    f,
+                    "Chunk #{} of fragment #{} ",
+                    chunk_idx.index(),
+                    frag_idx.index()
+                )?;
+                match reason {
+                    InvalidChunkReason::MethodOutOfRange(idx) => {
+                        write!(f, "refers to method #{}, which doesn't exist", idx)
+                    }
+                    InvalidChunkReason::CallOutOfRange(idx) => {
+                        write!(f, "refers to call #{}, which doesn't exist", idx)
+                    }
+                    InvalidChunkReason::SubLeadIdxOutOfRange(idx) => {
+                        write!(f, "starts at index {}, which is outside the lead", idx)
+                    }
+                    InvalidChunkReason::Empty => write!(f, "has no rows"),
+                    InvalidChunkReason::CallDoesntFit => {
+                        write!(f, "contains a call which is longer than a lead")
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use serde_json::Value;
+
+    use crate::{music_file, spec::CompSpec};
+
+    use super::{LoadError, CURRENT_VERSION};
+
+    fn load(value: &Value) -> Result<CompSpec, LoadError> {
+        CompSpec::from_json(&value.to_string(), Path::new(""))
+    }
+
+    fn example_json() -> Value {
+        serde_json::from_str(&CompSpec::example().to_json()).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let json = CompSpec::example().to_json();
+        let reloaded = CompSpec::from_json(&json, Path::new("")).unwrap();
+        assert_eq!(reloaded.to_json(), json);
+    }
+
+    #[test]
+    fn versions() {
+        let mut value = example_json();
+        assert_eq!(value["version"], CURRENT_VERSION);
+
+        value["version"] = Value::from(CURRENT_VERSION + 1);
+        assert!(matches!(
+            load(&value),
+            Err(LoadError::UnsupportedVersion(v)) if v == CURRENT_VERSION + 1
+        ));
+        value["version"] = Value::from(0);
+        assert!(matches!(
+            load(&value),
+            Err(LoadError::UnsupportedVersion(0))
+        ));
+        value.as_object_mut().unwrap().remove("version");
+        assert!(matches!(load(&value), Err(LoadError::NoVersion)));
+    }
+
+    #[test]
+    fn ruleoff_out_of_range() {
+        let mut value = example_json();
+        // Deva has a 32-row lead
+        value["methods"][0]["ruleoffs_above"] = serde_json::json!([0, 32]);
+        assert!(matches!(
+            load(&value),
+            Err(LoadError::RuleoffOutOfRange {
+                sub_lead_idx: 32,
+                ..
+            })
+        ));
+        // Huge indices are rejected without trying to allocate a huge bitmask
+        value["methods"][0]["ruleoffs_above"] = serde_json::json!([1_000_000_000_000_000_000u64]);
+        assert!(matches!(
+            load(&value),
+            Err(LoadError::RuleoffOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_music() {
+        // `9` isn't a bell on Major, `5` is repeated, and there are more than 8 bells
+        for regex in ["*5679", "*5566", "135724687"] {
+            let mut value = example_json();
+            value["music"][0]["music"][1]["regex"] = Value::from(regex);
+            // The error gives the index of the regex within each of its groups
+            assert!(matches!(
+                load(&value),
+                Err(LoadError::InvalidMusic { path, .. }) if path == [0, 1]
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_chunks() {
+        let mut value = example_json();
+        value["fragments"][0]["chunks"][0]["method"] = Value::from(100);
+        assert!(matches!(load(&value), Err(LoadError::InvalidChunk { .. })));
+
+        let mut value = example_json();
+        value["fragments"][0]["chunks"] = serde_json::json!([]);
+        assert!(matches!(load(&value), Err(LoadError::EmptyFragment(_))));
+    }
+
+    #[test]
+    fn music_file_reference() {
+        let dir = std::env::temp_dir().join(format!("jigsaw-music-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("music.txt"), "regex *5678\nregex *6578\n").unwrap();
+
+        // Music loaded from a file is saved as a reference to that file
+        let mut spec = CompSpec::example();
+        let music = music_file::load(&dir.join("music.txt"), spec.stage).unwrap();
+        spec.set_music(music, Some(PathBuf::from("music.txt")));
+        let value: Value = serde_json::from_str(&spec.to_json()).unwrap();
+        assert_eq!(value["music_file"], "music.txt");
+        assert!(value["music"].as_array().is_none_or(Vec::is_empty));
+        // ... and is reloaded from the file, relative to the composition's directory
+        let reloaded = CompSpec::from_json(&value.to_string(), &dir).unwrap();
+        assert_eq!(reloaded.music_file(), Some(Path::new("music.txt")));
+        assert_eq!(reloaded.music.len(), 2);
+        assert!(matches!(load(&value), Err(LoadError::MusicFile { .. })));
+
+        // Embedding the music keeps it, but stops referencing the file
+        spec.embed_music();
+        let value: Value = serde_json::from_str(&spec.to_json()).unwrap();
+        assert!(value["music_file"].is_null());
+        assert_eq!(value["music"].as_array().unwrap().len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
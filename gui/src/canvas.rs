@@ -7,27 +7,31 @@ use std::{
 
 use bellframe::Bell;
 use eframe::egui::{
-    self, epaint::Galley, Color32, Pos2, Rect, Response, Rgba, Sense, Shape, Stroke, TextStyle, Ui,
-    Vec2, Widget,
+    self, epaint::Galley, Align2, Color32, Pos2, Rect, Response, Rgba, Sense, Shape, Stroke,
+    TextStyle, Ui, Vec2, Widget,
 };
 use itertools::Itertools;
 use jigsaw_comp::full::{Fragment, FullState, RowDataForOnePart};
 use jigsaw_utils::{
     indexed_vec::{FragIdx, PartIdx},
-    types::RowSource,
+    types::RowLocation,
 };
 
 use crate::config::Config;
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn draw(
     ctx: &egui::CtxRef,
     full_state: &FullState,
     config: &Config,
     camera_pos: Pos2,
-    rows_to_highlight: HashSet<RowSource>,
+    rows_to_highlight: HashSet<RowLocation>,
     part_being_viewed: PartIdx,
+    show_all_parts: bool,
+    dragged_frag: Option<(FragIdx, Pos2)>,
 ) -> CanvasResponse {
     let mut frag_hover = None;
+    let mut cursor_pos = None;
     let inner_response = egui::CentralPanel::default()
         .show(ctx, |ui| {
             ui.add(CanvasWidget {
@@ -36,13 +40,18 @@ pub(crate) fn draw(
                 camera_pos,
                 rows_to_highlight,
                 part_being_viewed,
-                frag_hover: &mut frag_hover, // Used to pass values out of `ui.add`
+                show_all_parts,
+                dragged_frag,
+                // Used to pass values out of `ui.add`
+                frag_hover: &mut frag_hover,
+                cursor_pos: &mut cursor_pos,
             })
         })
         .inner;
 
     CanvasResponse {
         frag_hover,
+        cursor_pos,
         inner: inner_response,
     }
 }
@@ -50,6 +59,9 @@ pub(crate) fn draw(
 #[derive(Debug, Clone)]
 pub(crate) struct CanvasResponse {
     pub frag_hover: Option<FragHover>,
+    /// The position of the cursor in the same coordinates as [`Fragment::position`], or `None`
+    /// if the cursor isn't over the canvas
+    pub cursor_pos: Option<Pos2>,
     pub inner: Response,
 }
 
@@ -62,9 +74,16 @@ struct CanvasWidget<'a> {
     config: &'a Config,
     /// Position of the camera
     camera_pos: Pos2,
-    rows_to_highlight: HashSet<RowSource>,
+    rows_to_highlight: HashSet<RowLocation>,
     part_being_viewed: PartIdx,
+    /// If `true`, every fragment is drawn once for each part, in adjacent columns.  Otherwise,
+    /// only `part_being_viewed` is drawn.
+    show_all_parts: bool,
+    /// The fragment being dragged by the user (if any), along with the position it should be
+    /// drawn at
+    dragged_frag: Option<(FragIdx, Pos2)>,
     frag_hover: &'a mut Option<FragHover>,
+    cursor_pos: &'a mut Option<Pos2>,
 }
 
 impl<'a> Widget for CanvasWidget<'a> {
@@ -73,6 +92,10 @@ impl<'a> Widget for CanvasWidget<'a> {
         let (rect, response) = ui.allocate_exact_size(size, Sense::click_and_drag());
 
         let origin = rect.min - self.camera_pos.to_vec2();
+        let mouse_pos = ui.ctx().input().pointer.hover_pos();
+        *self.cursor_pos = mouse_pos
+            .filter(|pos| rect.contains(*pos))
+            .map(|pos| (pos - origin).to_pos2());
 
         // Generate 'Galley's for every bell before rendering starts, placing them in a lookup
         // table when rendering.  This way, the text layout only gets calculated once which
@@ -84,44 +107,93 @@ impl<'a> Widget for CanvasWidget<'a> {
             .map(|bell| ui.fonts().layout_single_line(TextStyle::Body, bell.name()))
             .collect_vec();
 
-        for (frag_idx, frag) in self.full_state.fragments.iter_enumerated() {
-            /* Compute bboxes */
-
-            // The unpadded rectangle containing all the rows
-            let row_bbox = Rect::from_min_size(
-                origin + frag.position.to_vec2(),
-                Vec2::new(
-                    self.config.col_width * self.full_state.stage.num_bells() as f32,
-                    // TODO: This doesn't take row folding into account - once row folding is
-                    // implemented, this will become incorrect
-                    self.config.row_height * frag.num_rows() as f32,
-                ),
-            );
-            // The bounding box of the fragment **after** padding has been added.  This is used for
-            // detecting mouse input and is used to draw the backing rectangle
-            let padded_bbox = row_bbox.expand2(self.config.frag_padding_vec());
-
-            /* Draw fragment */
+        // Decide which parts are drawn, and how far apart their columns are
+        let parts_to_draw = if self.show_all_parts {
+            (0..self.full_state.part_heads.len())
+                .map(PartIdx::new)
+                .collect_vec()
+        } else {
+            vec![self.part_being_viewed]
+        };
+        let column_width = self.config.col_width
+            * (self.full_state.stage.num_bells() as f32
+                + self.config.frag_padding_x * 2.0
+                + self.config.part_column_gap);
+
+        // Draw the fragments in order, except that the fragment being dragged is drawn on top
+        let dragged_frag_idx = self.dragged_frag.map(|(idx, _pos)| idx);
+        let frag_draw_order = self
+            .full_state
+            .fragments
+            .indices()
+            .filter(|idx| Some(*idx) != dragged_frag_idx)
+            .chain(dragged_frag_idx);
+        for frag_idx in frag_draw_order {
+            let frag = &self.full_state.fragments[frag_idx];
+            let position = match self.dragged_frag {
+                Some((idx, drag_pos)) if idx == frag_idx => drag_pos,
+                _ => frag.position,
+            };
 
-            self.draw_frag(
-                ui,
-                frag_idx,
-                frag,
-                row_bbox,
-                padded_bbox,
-                &bell_name_galleys,
-            );
+            // Draw one copy of the fragment for each part, all of which correspond to the same
+            // fragment in the `CompSpec`
+            for (column_idx, &part_idx) in parts_to_draw.iter().enumerate() {
+                /* Compute bboxes */
+
+                // The unpadded rectangle containing all the rows
+                let row_bbox = Rect::from_min_size(
+                    origin + position.to_vec2() + Vec2::RIGHT * column_width * column_idx as f32,
+                    Vec2::new(
+                        self.config.col_width * self.full_state.stage.num_bells() as f32,
+                        self.config.row_height * frag.num_display_rows() as f32,
+                    ),
+                );
+                // The bounding box of the fragment **after** padding has been added.  This is used
+                // for detecting mouse input and is used to draw the backing rectangle
+                let padded_bbox = row_bbox.expand2(self.config.frag_padding_vec());
+
+                /* Draw fragment */
+
+                self.draw_frag(
+                    ui,
+                    frag_idx,
+                    frag,
+                    part_idx,
+                    row_bbox,
+                    padded_bbox,
+                    &bell_name_galleys,
+                    // Method names are the same in every part, so are only drawn once to the
+                    // right of the last column
+                    column_idx == parts_to_draw.len() - 1,
+                );
+                // Label each column with its part head
+                if self.show_all_parts {
+                    ui.painter().text(
+                        Pos2::new(row_bbox.min.x, padded_bbox.min.y),
+                        Align2::LEFT_BOTTOM,
+                        &self.full_state.part_heads.rows()[part_idx.index()],
+                        TextStyle::Body,
+                        Color32::WHITE,
+                    );
+                }
 
-            // If the cursor is hovering this fragment, then save its position.  When the user
-            // presses a key, this position is used by the input handling code to determine which
-            // fragment/row should receive the input.
-            if let Some(mouse_pos) = ui.ctx().input().pointer.hover_pos() {
-                if padded_bbox.contains(mouse_pos) {
-                    let mouse_indices_float =
-                        (mouse_pos - row_bbox.min) / self.config.bell_box_size();
-                    // Overwrite the `frag_hover` with this fragment.  This way, the top-most
-                    // fragment will take any user input
-                    *self.frag_hover = Some(FragHover::new(frag_idx, mouse_indices_float));
+                // If the cursor is hovering this fragment, then save its position.  When the user
+                // presses a key, this position is used by the input handling code to determine
+                // which fragment/row should receive the input.  The fragment being dragged is
+                // never hovered, so that it can be dropped onto other fragments.
+                if let (Some(mouse_pos), false) = (mouse_pos, Some(frag_idx) == dragged_frag_idx) {
+                    if padded_bbox.contains(mouse_pos) {
+                        let mouse_indices_float =
+                            (mouse_pos - row_bbox.min) / self.config.bell_box_size();
+                        // Overwrite the `frag_hover` with this fragment.  This way, the top-most
+                        // fragment will take any user input
+                        *self.frag_hover = Some(FragHover::new(
+                            frag_idx,
+                            part_idx,
+                            frag,
+                            mouse_indices_float,
+                        ));
+                    }
                 }
             }
         }
@@ -131,16 +203,18 @@ impl<'a> Widget for CanvasWidget<'a> {
 }
 
 impl<'a> CanvasWidget<'a> {
-    /// Draw a [`Fragment`] to the display, returning the bounding [`Rect`] of this [`Fragment`]
-    /// **in screen space**.
+    /// Draw one part of a [`Fragment`] to the display
+    #[allow(clippy::too_many_arguments)]
     fn draw_frag(
         &self,
         ui: &mut Ui,
         frag_index: FragIdx,
         frag: &Fragment,
+        part_idx: PartIdx,
         rows_bbox: Rect,   // The bbox containing the rows of this fragment
         padded_bbox: Rect, // The bbox which adds padding round the rows
         bell_name_galleys: &[Arc<Galley>],
+        draw_method_names: bool,
     ) {
         // Create empty line paths for each bell which should be drawn as lines.  These will be
         // extended during row drawing, and then all rendered at the end.
@@ -159,20 +233,78 @@ impl<'a> CanvasWidget<'a> {
             stroke: Stroke::none(),
         });
 
-        // Draw the rows
-        for (row_index, data) in frag.rows_in_part(self.part_being_viewed) {
-            let row_source = RowSource {
+        // Draw link markers in the top and bottom padding of linked fragments
+        let link_colors = &self.config.frag_link_colors;
+        let link_markers = [
+            (frag.link_group_top, padded_bbox.min.y, rows_bbox.min.y),
+            (frag.link_group_bottom, rows_bbox.max.y, padded_bbox.max.y),
+        ];
+        for (link_group, min_y, max_y) in link_markers {
+            if let Some(group) = link_group {
+                ui.painter().add(Shape::Rect {
+                    rect: Rect::from_x_y_ranges(rows_bbox.x_range(), min_y..=max_y),
+                    corner_radius: 0.0,
+                    fill: link_colors[group % link_colors.len()],
+                    stroke: Stroke::none(),
+                });
+            }
+        }
+
+        // Draw the false row ranges in the left-hand padding, coloured by their group
+        for range in frag.false_row_ranges_in_part(part_idx) {
+            let colors = &self.config.false_row_group_colors;
+            let top_display_idx = frag.display_idx(range.top);
+            let bottom_display_idx = frag.display_idx(range.bottom);
+            let rect = Rect::from_min_max(
+                Pos2::new(
+                    padded_bbox.min.x,
+                    rows_bbox.min.y + top_display_idx as f32 * self.config.row_height,
+                ),
+                Pos2::new(
+                    rows_bbox.min.x,
+                    rows_bbox.min.y + (bottom_display_idx + 1) as f32 * self.config.row_height,
+                ),
+            );
+            ui.painter().add(Shape::Rect {
+                rect,
+                corner_radius: 0.0,
+                fill: colors[range.group % colors.len()],
+                stroke: Stroke::none(),
+            });
+        }
+
+        // Draw the rows, skipping any which are hidden in folded leads
+        for (row_index, data) in frag.rows_in_part(part_idx) {
+            if frag.is_hidden(row_index) {
+                continue;
+            }
+            let row_location = RowLocation {
                 frag_index,
                 row_index,
+                part_index: part_idx,
             };
             self.draw_row(
                 ui,
                 rows_bbox,
-                row_source,
+                row_location,
+                frag.display_idx(row_index),
                 data,
                 bell_name_galleys,
                 &mut lines,
+                draw_method_names,
             );
+            // Mark folded leads with a line along the bottom of their lead head
+            if frag.folded_lead_len(row_index).is_some() {
+                let y = rows_bbox.min.y
+                    + (frag.display_idx(row_index) + 1) as f32 * self.config.row_height;
+                ui.painter().add(Shape::LineSegment {
+                    points: [Pos2::new(rows_bbox.min.x, y), Pos2::new(rows_bbox.max.x, y)],
+                    stroke: Stroke {
+                        width: self.config.fold_line_width,
+                        color: self.config.fold_line_color,
+                    },
+                });
+            }
         }
 
         // Render lines, always in increasing order of bell (otherwise HashMap's non-determinism
@@ -197,12 +329,14 @@ impl<'a> CanvasWidget<'a> {
         &self,
         ui: &mut Ui,
         rows_bbox: Rect,
-        source: RowSource,
+        location: RowLocation,
+        display_idx: usize,
         data: RowDataForOnePart,
         bell_name_galleys: &[Arc<Galley>],
         lines: &mut HashMap<Bell, (f32, Color32, Vec<Pos2>)>,
+        draw_method_name: bool,
     ) {
-        let y_coord = rows_bbox.min.y + source.row_index.index() as f32 * self.config.row_height;
+        let y_coord = rows_bbox.min.y + display_idx as f32 * self.config.row_height;
         let text_y_coord = y_coord + self.config.row_height * self.config.text_pos_y;
 
         /* COMPUTE OPACITY */
@@ -211,7 +345,7 @@ impl<'a> CanvasWidget<'a> {
         let mut opacity = 1.0;
         // If no rows are highlighted, then all rows are highlighted
         let is_highlighted =
-            self.rows_to_highlight.is_empty() || self.rows_to_highlight.contains(&source);
+            self.rows_to_highlight.is_empty() || self.rows_to_highlight.contains(&location);
         if !is_highlighted {
             opacity *= 0.5; // Fade out non-highlighted rows
         }
@@ -228,7 +362,7 @@ impl<'a> CanvasWidget<'a> {
                 rows_bbox.min
                     + Vec2::new(
                         col_idx as f32 * self.config.col_width,
-                        source.row_index.index() as f32 * self.config.row_height,
+                        display_idx as f32 * self.config.row_height,
                     ),
                 self.config.bell_box_size(),
             );
@@ -261,7 +395,7 @@ impl<'a> CanvasWidget<'a> {
 
         /* DRAW METHOD NAME */
 
-        if let Some(method_name) = &data.method_annotation {
+        if let (Some(method_name), true) = (&data.method_annotation, draw_method_name) {
             ui.painter().add(Shape::Text {
                 pos: Pos2::new(rows_bbox.max.x + self.config.col_width, text_y_coord),
                 galley: ui
@@ -272,6 +406,24 @@ impl<'a> CanvasWidget<'a> {
             });
         }
 
+        /* DRAW CALL LABEL */
+
+        if let Some(call) = &data.call_annotation {
+            // Label the call with its symbol and calling position (e.g. `-H` or `sW`)
+            let label = format!("{}{}", call.symbol(), data.calling_position.unwrap_or(""));
+            let galley = ui.fonts().layout_single_line(TextStyle::Body, label);
+            ui.painter().add(Shape::Text {
+                // Right-align the label one column to the left of the rows
+                pos: Pos2::new(
+                    rows_bbox.min.x - self.config.col_width - galley.size.x,
+                    text_y_coord,
+                ),
+                galley,
+                color: foreground_color,
+                fake_italics: false,
+            });
+        }
+
         /* DRAW RULE-OFF */
 
         if data.ruleoff_above {
@@ -293,10 +445,17 @@ impl<'a> CanvasWidget<'a> {
 #[derive(Debug, Clone)]
 pub(crate) struct FragHover {
     pub frag_idx: FragIdx,
+    /// The part of the [`Fragment`] being hovered.  When all parts are displayed side by side,
+    /// this is the part of the column under the cursor.
+    pub part_idx: PartIdx,
     /// The fractional index of the cursor's location within the rows (i.e. if the cursor is half
     /// way through a row, then this will be `x + 0.5` where x is that row's index).  In addition
-    /// to being fractional, this can be negative or point to non-existent rows.
+    /// to being fractional, this can be negative or point to non-existent rows.  This refers to
+    /// the [`Fragment`]'s real rows, not the rows on screen (which may be folded).
     pub row_idx_float: f32,
+    /// Like `row_idx_float`, but refers to the rows on screen (where every folded lead only takes
+    /// up one row)
+    pub display_idx_float: f32,
     /// The fractional index of the cursor's location within the places (i.e. if the cursor is half
     /// way through a column, then this will be `x + 0.5` where x is that columns's index).  As
     /// with `row_idx_float`, this can also be negative or otherwise out-of-bounds.
@@ -304,16 +463,22 @@ pub(crate) struct FragHover {
 }
 
 impl FragHover {
-    fn new(frag_idx: FragIdx, mouse_indices_float: Vec2) -> Self {
+    fn new(
+        frag_idx: FragIdx,
+        part_idx: PartIdx,
+        frag: &Fragment,
+        mouse_indices_float: Vec2,
+    ) -> Self {
         Self {
             frag_idx,
-            row_idx_float: mouse_indices_float.y,
+            part_idx,
+            row_idx_float: frag.display_pos_to_row_pos(mouse_indices_float.y),
+            display_idx_float: mouse_indices_float.y,
             place_idx_float: mouse_indices_float.x,
         }
     }
 
     /// The integer index of the row that's being hovered (which may be negative)
-    #[allow(dead_code)]
     pub fn hovered_row_idx(&self) -> isize {
         self.row_idx_float.floor() as isize
     }
@@ -11,22 +11,44 @@ pub struct Config {
     pub(crate) row_height: f32, // points
 
     pub(crate) ruleoff_line_width: f32, // points
+    /// The line drawn under a folded lead, to show that there are rows hidden there
+    pub(crate) fold_line_width: f32, // points
+    pub(crate) fold_line_color: Color32,
 
     pub(crate) text_pos_x: f32, // multiple of `col_width`
     pub(crate) text_pos_y: f32, // multiple of `row_height`
 
     pub(crate) frag_padding_x: f32, // multiple of `col_width`
     pub(crate) frag_padding_y: f32, // multiple of `row_height`
+    /// When all parts are displayed side by side, the gap between the columns (which leaves room
+    /// for call labels)
+    pub(crate) part_column_gap: f32, // multiple of `col_width`
 
     /// Widths are multiples of `self.col_width`
     pub(crate) bell_lines: HashMap<Bell, (f32, Color32)>,
 
+    /// The colours used to highlight groups of false rows.  If there are more groups than
+    /// colours, then the colours are reused.
+    pub(crate) false_row_group_colors: Vec<Color32>,
+    /// The colours used to mark the tops and bottoms of linked fragments.  As with
+    /// `false_row_group_colors`, these are reused if there are more link groups than colours.
+    pub(crate) frag_link_colors: Vec<Color32>,
+
     /* User interaction */
     /// When splitting a fragment at a rule-off, the cursor must be less than this many rows away
     /// from the nearest rule-off.
     pub(crate) ruleoff_snap_distance: f32, // rows
     /// When a fragment is split, how far away is the 2nd fragment?
     pub(crate) split_height: f32, // multiples of `row_height`
+    /// When a fragment is duplicated, how far to the right is the copy?
+    pub(crate) duplicate_offset_x: f32, // multiples of the fragment's width
+    /// When a fragment is dragged to within this distance of a fragment it can be linked to, it
+    /// snaps into line with that fragment
+    pub(crate) frag_snap_distance: f32, // points
+
+    /* Method library */
+    /// The maximum number of methods shown when searching the method library
+    pub(crate) max_method_lib_results: usize,
 }
 
 impl Config {
@@ -51,15 +73,22 @@ impl Default for Config {
             row_height: 16.0,
 
             ruleoff_line_width: 1.0,
+            fold_line_width: 2.0,
+            fold_line_color: Color32::from_rgb(230, 120, 30),
 
             text_pos_x: 0.125,
             text_pos_y: 0.05,
 
             frag_padding_x: 0.5,
             frag_padding_y: 0.3,
+            part_column_gap: 4.0,
 
             ruleoff_snap_distance: 3.0, // rows
             split_height: 2.0,
+            duplicate_offset_x: 2.0,
+            frag_snap_distance: 20.0,
+
+            max_method_lib_results: 20,
 
             bell_lines: {
                 let mut map = HashMap::new();
@@ -67,6 +96,25 @@ impl Default for Config {
                 map.insert(Bell::tenor(Stage::MAJOR), (0.2, Color32::LIGHT_BLUE));
                 map
             },
+
+            false_row_group_colors: vec![
+                Color32::from_rgb(255, 0, 0),
+                Color32::from_rgb(0, 255, 0),
+                Color32::from_rgb(0, 0, 255),
+                Color32::from_rgb(255, 255, 0),
+                Color32::from_rgb(0, 255, 255),
+                Color32::from_rgb(255, 0, 255),
+                Color32::from_rgb(255, 128, 0),
+                Color32::from_rgb(128, 0, 255),
+            ],
+            frag_link_colors: vec![
+                Color32::from_rgb(0, 200, 100),
+                Color32::from_rgb(230, 120, 30),
+                Color32::from_rgb(70, 130, 255),
+                Color32::from_rgb(220, 60, 160),
+                Color32::from_rgb(240, 220, 60),
+                Color32::from_rgb(120, 220, 240),
+            ],
         }
     }
 }
@@ -1,5 +1,11 @@
 //! Top-level code for Jigsaw's GUI
 
+use std::{
+    fmt::Formatter,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
 use canvas::{CanvasResponse, FragHover};
 use eframe::{
     egui::{self, PointerButton, Pos2, Vec2},
@@ -7,11 +13,13 @@ use eframe::{
 };
 
 use jigsaw_comp::{
-    full::FullState,
-    spec::{self, part_heads::PartHeads, CompSpec},
-    History,
+    full::{Folds, FullState},
+    method_lib::{LibMethod, MethodLib, Query},
+    music_file,
+    spec::{self, part_heads::PartHeads, CompSpec, FragLength, RuleoffPreset},
+    History, Music, MusicPresets,
 };
-use jigsaw_utils::indexed_vec::{FragIdx, PartIdx};
+use jigsaw_utils::indexed_vec::{CallIdx, FragIdx, MethodIdx, PartIdx, RowIdx};
 
 use self::config::Config;
 
@@ -19,6 +27,8 @@ mod canvas;
 mod config;
 mod side_panel;
 
+use bellframe::{PnBlock, RowBuf};
+
 // Imports only used for doc comments
 #[allow(unused_imports)]
 use bellframe::Row;
@@ -34,33 +44,154 @@ pub struct JigsawApp {
     history: History,
     /// The fully specified state, cached between frames and used to draw the GUI
     full_state: FullState,
+    /// Which leads are folded on the canvas.  These aren't part of the composition, so are kept
+    /// out of `history`, but must be updated whenever fragments are split, joined or duplicated.
+    folds: Folds,
 
     /* GUI state */
     /// The text currently in the part head UI box.  Whilst the user is typing, this can become
     /// invalid, and therefore must be able to diverge from `self.history`
     part_head_str: String,
     camera_pos: Pos2,
+    /// The part which is being displayed on the canvas
+    part_being_viewed: PartIdx,
+    /// If `true`, every part is displayed side by side on the canvas (rather than just
+    /// `part_being_viewed`)
+    show_all_parts: bool,
+    /// If set, hovering over music in the side panel only highlights the rows of this part
+    music_highlight_part: Option<PartIdx>,
+    /// The [`Method`](spec::Method) which is used when adding new fragments
+    selected_method: MethodIdx,
+    /// The fragment which is currently being dragged with the left mouse button (if any)
+    frag_drag: Option<FragDrag>,
+    /// If the user is transposing a fragment, then this contains the state of that transposition.
+    /// Whilst transposing, `full_state` shows a preview of the transposed composition which isn't
+    /// part of the undo history.
+    transposition: Option<Transposition>,
+    /// If the user is adding or editing a method, then this contains the contents of the method
+    /// edit dialog
+    method_edit: Option<MethodEdit>,
+    /// The library of methods which the user can search and add to the composition.  This is
+    /// `None` if no library has been loaded.
+    method_lib: Option<MethodLib>,
+    /// The reason that the method library couldn't be loaded, which is shown in the method
+    /// library panel
+    method_lib_error: Option<String>,
+    /// The user's current search of `method_lib`
+    method_lib_search: MethodLibSearch,
+    /// The contents of the music file box in the music panel
+    music_file_box: MusicFileBox,
+    /// The music presets which will be used if the user creates a new composition
+    new_comp_presets: MusicPresets,
+    /// The file which the composition will be written to when the user saves.  If this is `None`,
+    /// then the composition can't be saved.
+    save_path: Option<PathBuf>,
+    /// The reason that the last save failed, which is shown until the user dismisses it
+    save_error: Option<String>,
 }
 
 impl JigsawApp {
     /// Load an example composition
     pub fn example() -> Self {
-        let spec = CompSpec::example();
+        Self::from_spec(CompSpec::example(), None)
+    }
+
+    /// Load a composition from a JSON file, which is also where the composition will be saved.  If
+    /// the file doesn't exist yet, then the example composition is loaded instead (and will be
+    /// saved to `path`).
+    pub fn open(path: PathBuf) -> Result<Self, FileError> {
+        let spec = if path.exists() {
+            let json = std::fs::read_to_string(&path).map_err(FileError::Io)?;
+            let dir = path.parent().unwrap_or_else(|| Path::new(""));
+            CompSpec::from_json(&json, dir).map_err(FileError::Load)?
+        } else {
+            CompSpec::example()
+        };
+        Ok(Self::from_spec(spec, Some(path)))
+    }
+
+    fn from_spec(spec: CompSpec, save_path: Option<PathBuf>) -> Self {
         let full_state = FullState::new(&spec);
         let part_head_str = full_state.part_heads.spec_string();
-
+        let music_file_box = MusicFileBox::new(&spec);
         Self {
             config: Config::default(),
 
             history: History::new(spec),
             full_state,
+            folds: Folds::default(),
 
             part_head_str,
             camera_pos: Pos2::ZERO,
+            part_being_viewed: PartIdx::new(0),
+            show_all_parts: false,
+            music_highlight_part: None,
+            selected_method: MethodIdx::new(0),
+            frag_drag: None,
+            transposition: None,
+            method_edit: None,
+            method_lib: None,
+            method_lib_error: None,
+            method_lib_search: MethodLibSearch::default(),
+            music_file_box,
+            new_comp_presets: MusicPresets::default(),
+            save_path,
+            save_error: None,
+        }
+    }
+
+    /// Load the library of methods which can be searched and added to the composition (usually
+    /// the CCCBR's `CCCBR_methods.xml`), replacing any existing library.  If loading fails, the
+    /// error is shown in the method library panel.
+    pub fn load_method_lib(&mut self, path: &Path) {
+        match MethodLib::from_xml_file(path) {
+            Ok(lib) => {
+                self.method_lib = Some(lib);
+                self.method_lib_error = None;
+            }
+            Err(e) => {
+                self.method_lib = None;
+                self.method_lib_error = Some(format!("{} ({})", e, path.display()));
+            }
+        }
+        // Re-run the search over the new library
+        let search = std::mem::take(&mut self.method_lib_search);
+        self.search_method_lib(search.text, search.class);
+    }
+
+    /// Write the current composition to `self.save_path`
+    fn save(&self) -> Result<(), FileError> {
+        let path = self.save_path.as_ref().ok_or(FileError::NoSavePath)?;
+        std::fs::write(path, self.history.comp_spec().to_json()).map_err(FileError::Io)
+    }
+}
+
+/// The ways that loading or saving a composition file can fail
+#[derive(Debug)]
+pub enum FileError {
+    /// The composition has no file to be saved to
+    NoSavePath,
+    /// The file couldn't be read or written
+    Io(std::io::Error),
+    /// The file was read, but didn't contain a valid composition
+    Load(spec::LoadError),
+}
+
+impl std::fmt::Display for FileError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            FileError::NoSavePath => write!(
+                f,
+                "No file to save the composition to.  Open Jigsaw with a file to save to it."
+            ),
+            FileError::Io(e) => write!(f, "{}", e),
+            FileError::Load(e) => write!(f, "{}", e),
         }
     }
 }
 
+impl std::error::Error for FileError {}
+
 impl epi::App for JigsawApp {
     fn name(&self) -> &str {
         "Jigsaw"
@@ -96,10 +227,36 @@ impl JigsawApp {
     // DRAW GUI //
     //////////////
 
-    fn draw_gui(&self, ctx: &egui::CtxRef, push_action: impl FnMut(Action)) -> CanvasResponse {
+    fn draw_gui(&self, ctx: &egui::CtxRef, mut push_action: impl FnMut(Action)) -> CanvasResponse {
         // Draw right-hand panel, and decide which rows should be highlighted
-        let rows_to_highlight =
-            side_panel::draw(ctx, &self.full_state, &self.part_head_str, push_action);
+        let rows_to_highlight = side_panel::draw(
+            ctx,
+            &self.full_state,
+            &self.part_head_str,
+            self.part_being_viewed,
+            self.show_all_parts,
+            self.music_highlight_part,
+            self.selected_method,
+            self.method_lib.as_ref(),
+            self.method_lib_error.as_deref(),
+            &self.method_lib_search,
+            &self.music_file_box,
+            self.history.comp_spec().music_file(),
+            self.new_comp_presets,
+            &mut push_action,
+        );
+        // Draw the transposition box, if the user is transposing a fragment
+        if let Some(transposition) = &self.transposition {
+            self.draw_transposition_window(ctx, transposition, &mut push_action);
+        }
+        // Draw the method editing dialog, if the user is editing a method
+        if let Some(method_edit) = &self.method_edit {
+            self.draw_method_edit_window(ctx, method_edit, &mut push_action);
+        }
+        // Tell the user if their composition couldn't be saved
+        if let Some(save_error) = &self.save_error {
+            Self::draw_save_error_window(ctx, save_error, &mut push_action);
+        }
         // Draw the main canvas
         canvas::draw(
             ctx,
@@ -107,10 +264,180 @@ impl JigsawApp {
             &self.config,
             self.camera_pos,
             rows_to_highlight,
-            PartIdx::new(0), // Always display the first part until we can change this
+            self.part_being_viewed,
+            self.show_all_parts,
+            self.dragged_frag_position(),
         )
     }
 
+    /// The position that the fragment being dragged should be displayed at (if there is one).
+    /// This snaps the fragment into line with any fragment it could be joined to.
+    fn dragged_frag_position(&self) -> Option<(FragIdx, Pos2)> {
+        let drag = self.frag_drag.as_ref()?;
+        let fragments = &self.full_state.fragments;
+        let dragged_frag = &fragments[drag.frag_idx];
+        let unsnapped_pos = dragged_frag.position + drag.offset;
+
+        // Find the positions where the dragged fragment would line up with the end of a fragment
+        // that it can link to
+        let snap_positions = self.full_state.frag_links.iter().filter_map(|link| {
+            if link.from == link.to {
+                None // Fragments can't be snapped to themselves
+            } else if link.to == drag.frag_idx {
+                // The dragged fragment's first row would overlap the leftover row of `link.from`
+                let top_frag = &fragments[link.from];
+                let height = self.config.row_height * (top_frag.num_display_rows() - 1) as f32;
+                Some(top_frag.position + Vec2::DOWN * height)
+            } else if link.from == drag.frag_idx {
+                // The dragged fragment's leftover row would overlap the first row of `link.to`
+                let height = self.config.row_height * (dragged_frag.num_display_rows() - 1) as f32;
+                Some(fragments[link.to].position - Vec2::DOWN * height)
+            } else {
+                None
+            }
+        });
+        // Snap to the nearest of these positions, if it's close enough
+        let snapped_pos = snap_positions
+            .map(|pos| (pos, pos.distance(unsnapped_pos)))
+            .filter(|(_pos, dist)| *dist < self.config.frag_snap_distance)
+            .min_by(|(_, d1), (_, d2)| d1.partial_cmp(d2).unwrap())
+            .map(|(pos, _dist)| pos);
+        Some((drag.frag_idx, snapped_pos.unwrap_or(unsnapped_pos)))
+    }
+
+    fn draw_method_edit_window(
+        &self,
+        ctx: &egui::CtxRef,
+        method_edit: &MethodEdit,
+        mut push_action: impl FnMut(Action),
+    ) {
+        let title = match method_edit.method_idx {
+            Some(_) => "Edit Method",
+            None => "New Method",
+        };
+        egui::Window::new(title)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let mut new_edit = method_edit.clone();
+                egui::Grid::new("method_edit_grid").show(ui, |grid_ui| {
+                    grid_ui.label("Name:");
+                    grid_ui.text_edit_singleline(&mut new_edit.name);
+                    grid_ui.end_row();
+                    grid_ui.label("Shorthand:");
+                    grid_ui.text_edit_singleline(&mut new_edit.shorthand);
+                    grid_ui.end_row();
+                    grid_ui.label("Place notation:");
+                    grid_ui.text_edit_singleline(&mut new_edit.place_notation);
+                    grid_ui.end_row();
+                    grid_ui.label("Rule-offs:");
+                    Self::draw_ruleoff_selector(grid_ui, &mut new_edit.ruleoffs);
+                    grid_ui.end_row();
+                });
+                // Parse the place notation as the user types, so they get immediate feedback
+                let parse_result = PnBlock::parse(&new_edit.place_notation, self.full_state.stage);
+                if let Err(e) = &parse_result {
+                    ui.label(egui::Label::new(e.to_string()).text_color(egui::Color32::RED));
+                }
+                // Shorthands are typed to extend fragments, so they have to be unique letters
+                let shorthand_error =
+                    self.shorthand_error(&new_edit.shorthand, method_edit.method_idx);
+                if let Some(e) = &shorthand_error {
+                    ui.label(egui::Label::new(e).text_color(egui::Color32::RED));
+                }
+                if let Some(e) = &method_edit.error {
+                    ui.label(egui::Label::new(e).text_color(egui::Color32::RED));
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Cancel").clicked() {
+                        push_action(Action::CancelEditingMethod);
+                    }
+                    let is_valid = parse_result.is_ok() && shorthand_error.is_none();
+                    if is_valid && ui.button("Save").clicked() {
+                        push_action(Action::FinishEditingMethod);
+                    }
+                });
+
+                if new_edit != *method_edit {
+                    // The error (if any) was caused by the old contents of the dialog
+                    new_edit.error = None;
+                    push_action(Action::UpdateMethodEdit(new_edit));
+                }
+            });
+    }
+
+    /// Draw the widgets for choosing a method's [`RuleoffPreset`].  `None` means that the method's
+    /// rule-offs don't match any preset, and will be left unchanged.
+    fn draw_ruleoff_selector(ui: &mut egui::Ui, ruleoffs: &mut Option<RuleoffPreset>) {
+        let every_n_rows = match *ruleoffs {
+            Some(preset @ RuleoffPreset::EveryNRows { .. }) => preset,
+            _ => RuleoffPreset::EveryNRows { n: 6, offset: 0 },
+        };
+        ui.horizontal(|ui| {
+            let selected_text = match ruleoffs {
+                Some(RuleoffPreset::EveryNRows { .. }) => "Every N rows".to_owned(),
+                Some(preset) => preset.to_string(),
+                None => "Custom".to_owned(),
+            };
+            egui::ComboBox::from_id_source("ruleoff_preset")
+                .selected_text(selected_text)
+                .show_ui(ui, |combo_ui| {
+                    for (preset, label) in [
+                        (RuleoffPreset::None, "None"),
+                        (RuleoffPreset::LeadEnd, "Lead end"),
+                        (RuleoffPreset::HalfLead, "Half lead"),
+                        (every_n_rows, "Every N rows"),
+                    ] {
+                        combo_ui.selectable_value(ruleoffs, Some(preset), label);
+                    }
+                });
+            // Let the user choose the spacing of the rule-offs
+            if let Some(RuleoffPreset::EveryNRows { n, offset }) = ruleoffs {
+                ui.add(egui::DragValue::new(n).prefix("n: ").clamp_range(1..=1000));
+                ui.add(egui::DragValue::new(offset).prefix("offset: "));
+            }
+        });
+    }
+
+    fn draw_transposition_window(
+        &self,
+        ctx: &egui::CtxRef,
+        transposition: &Transposition,
+        mut push_action: impl FnMut(Action),
+    ) {
+        egui::Window::new("Transpose")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let mut row_str = transposition.row_str.clone();
+                // Always keep focus on the text box, so the user can type straight away
+                ui.text_edit_singleline(&mut row_str).request_focus();
+                if row_str != transposition.row_str {
+                    push_action(Action::SetTranspositionString(row_str));
+                }
+                if let Err(e) = transposition.parse_row(self.full_state.stage) {
+                    ui.label(egui::Label::new(e.to_string()).text_color(egui::Color32::RED));
+                }
+                ui.label("Enter to transpose, Esc to cancel");
+            });
+    }
+
+    fn draw_save_error_window(
+        ctx: &egui::CtxRef,
+        save_error: &str,
+        mut push_action: impl FnMut(Action),
+    ) {
+        egui::Window::new("Save Failed")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(egui::Label::new(save_error).text_color(egui::Color32::RED));
+                if ui.button("OK").clicked() {
+                    push_action(Action::DismissSaveError);
+                }
+            });
+    }
+
     ////////////////////
     // INPUT HANDLING //
     ////////////////////
@@ -130,10 +457,72 @@ impl JigsawApp {
                 modifiers,
             } = *evt
             {
+                // Ctrl+S (or Cmd+S on Mac) saves the composition, even if the user is typing
+                if pressed && key == egui::Key::S && modifiers.command {
+                    push_action(Action::Save);
+                    continue;
+                }
+                // Whilst transposing, Enter and Escape commit/cancel the transposition and all other
+                // keys go to the transposition box
+                if self.transposition.is_some() {
+                    match (key, pressed) {
+                        (egui::Key::Enter, true) => push_action(Action::FinishTransposing),
+                        (egui::Key::Escape, true) => push_action(Action::CancelTransposing),
+                        _ => {}
+                    }
+                    continue;
+                }
+                // Page Up/Page Down to view the previous/next part
+                if !ctx.wants_keyboard_input() && pressed {
+                    let num_parts = self.full_state.part_heads.len();
+                    let part = self.part_being_viewed.index();
+                    let new_part = match key {
+                        egui::Key::PageUp => Some((part + num_parts - 1) % num_parts),
+                        egui::Key::PageDown => Some((part + 1) % num_parts),
+                        _ => None,
+                    };
+                    if let Some(new_part) = new_part {
+                        push_action(Action::ViewPart(PartIdx::new(new_part)));
+                        continue;
+                    }
+                }
+                // f to fold or unfold the lead under the cursor
+                if !ctx.wants_keyboard_input() && pressed && key == egui::Key::F {
+                    if let Some(frag_hover) = &canvas_response.frag_hover {
+                        // The leftover row can never be folded, so there `f` is left to extend
+                        // the fragment
+                        let fragment = &self.full_state.fragments[frag_hover.frag_idx];
+                        if frag_hover.hovered_row_idx() < fragment.num_rows() as isize - 1 {
+                            push_action(Action::ToggleLeadFold {
+                                frag_idx: frag_hover.frag_idx,
+                                row_idx: frag_hover.hovered_row_idx(),
+                            });
+                            continue;
+                        }
+                    }
+                }
+                // t to start transposing the fragment under the cursor
+                if !ctx.wants_keyboard_input() && pressed && key == egui::Key::T {
+                    if let Some(frag_hover) = &canvas_response.frag_hover {
+                        let fragment = &self.full_state.fragments[frag_hover.frag_idx];
+                        let row_idx = frag_hover
+                            .hovered_row_idx()
+                            .clamp(0, fragment.num_rows() as isize - 1);
+                        push_action(Action::StartTransposing {
+                            frag_idx: frag_hover.frag_idx,
+                            part_idx: frag_hover.part_idx,
+                            row_idx: RowIdx::new(row_idx as usize),
+                        });
+                        continue;
+                    }
+                }
                 if !ctx.wants_keyboard_input() && pressed {
-                    if let Some(comp_action) =
-                        self.handle_key_press(key, modifiers, canvas_response.frag_hover.as_ref())
-                    {
+                    if let Some(comp_action) = self.handle_key_press(
+                        key,
+                        modifiers,
+                        canvas_response.frag_hover.as_ref(),
+                        canvas_response.cursor_pos,
+                    ) {
                         push_action(Action::Comp(comp_action));
                     }
                 }
@@ -144,6 +533,67 @@ impl JigsawApp {
         if canvas_response.inner.dragged_by(PointerButton::Middle) {
             push_action(Action::PanView(-canvas_response.inner.drag_delta()));
         }
+
+        // Drag fragments with the left mouse button
+        if canvas_response.inner.drag_started()
+            && canvas_response.inner.dragged_by(PointerButton::Primary)
+        {
+            if let Some(frag_hover) = &canvas_response.frag_hover {
+                push_action(Action::StartDraggingFragment(frag_hover.frag_idx));
+            }
+        } else if self.frag_drag.is_some()
+            && canvas_response.inner.dragged_by(PointerButton::Primary)
+        {
+            push_action(Action::DragFragment(canvas_response.inner.drag_delta()));
+        }
+        if canvas_response.inner.drag_released() {
+            if let Some((dragged_frag_idx, new_position)) = self.dragged_frag_position() {
+                push_action(Action::StopDraggingFragment);
+                // Dropping a fragment onto a fragment which it links to joins them together.
+                // Otherwise, the fragment is moved to wherever it was dropped.
+                let old_position = self.full_state.fragments[dragged_frag_idx].position;
+                let join = canvas_response
+                    .frag_hover
+                    .as_ref()
+                    .and_then(|frag_hover| self.join_fragments(dragged_frag_idx, frag_hover));
+                // Don't create an undo step if the fragment didn't actually move
+                let comp_action = join.or_else(|| {
+                    (new_position != old_position).then(|| CompAction::MoveFragment {
+                        frag_idx: dragged_frag_idx,
+                        new_position,
+                    })
+                });
+                if let Some(comp_action) = comp_action {
+                    push_action(Action::Comp(comp_action));
+                }
+            }
+        }
+    }
+
+    /// Joins the fragment being dragged to the fragment under the cursor.  If the cursor is in the
+    /// bottom half of the hovered fragment, then the dragged fragment is joined to its end,
+    /// otherwise the hovered fragment is joined to the end of the dragged fragment.  Returns
+    /// `None` if the fragments can't be joined that way round.
+    fn join_fragments(
+        &self,
+        dragged_frag_idx: FragIdx,
+        frag_hover: &FragHover,
+    ) -> Option<CompAction> {
+        let hovered_frag = &self.full_state.fragments[frag_hover.frag_idx];
+        // Compare on-screen positions, since folded leads make the fragment shorter on screen
+        let is_bottom_half =
+            frag_hover.display_idx_float > hovered_frag.num_display_rows() as f32 / 2.0;
+        let (top, bottom) = if is_bottom_half {
+            (frag_hover.frag_idx, dragged_frag_idx)
+        } else {
+            (dragged_frag_idx, frag_hover.frag_idx)
+        };
+        let is_linked = self
+            .full_state
+            .frag_links
+            .iter()
+            .any(|link| link.from == top && link.to == bottom);
+        (top != bottom && is_linked).then(|| CompAction::JoinFragments { top, bottom })
     }
 
     /// Handle a keyboard key being pressed down
@@ -153,23 +603,36 @@ impl JigsawApp {
         key: egui::Key,
         modifiers: egui::Modifiers,
         frag_hover: Option<&FragHover>,
+        cursor_pos: Option<Pos2>,
     ) -> Option<CompAction> {
         use egui::Key::*;
 
-        // z with any set of modifiers is undo
-        if key == Z && !modifiers.shift {
+        // z with any set of modifiers (except alt, which is used for method shorthands) is undo
+        if key == Z && !modifiers.shift && !modifiers.alt {
             return Some(CompAction::UndoRedo(HistoryDirection::Undo));
         }
-        // Z, y or Y with any set of modifiers is redo
-        if (key == Z && modifiers.shift) || key == Y {
+        // Z, y or Y with any set of modifiers (except alt) is redo
+        if ((key == Z && modifiers.shift) || key == Y) && !modifiers.alt {
             return Some(CompAction::UndoRedo(HistoryDirection::Redo));
         }
 
+        // When hovering the leftover row of a fragment, alt + the shorthand of a method extends
+        // that fragment with a lead (or a plain course, with shift) of that method
+        if let Some(frag_hover) = frag_hover {
+            if modifiers.alt && !modifiers.command {
+                if let Some(action) = self.extend_fragment(key, modifiers, frag_hover) {
+                    return Some(action);
+                }
+            }
+        }
+
         // Actions which apply to a fragment under the cursor
         if let Some(frag_hover) = frag_hover {
             let action = match (key, modifiers.shift) {
-                // d or D to delete the fragment under the cursor
-                (D, _) => Some(CompAction::DeleteFragment(frag_hover.frag_idx)),
+                // d to delete the fragment under the cursor
+                (D, false) => Some(CompAction::DeleteFragment(frag_hover.frag_idx)),
+                // D to duplicate the fragment under the cursor
+                (D, true) => Some(self.duplicate_fragment(frag_hover)),
                 // x to split the fragment at the nearest rule-off
                 (X, false) => self.split_fragment(frag_hover, FragSplitLocation::NearestRuleoff),
                 // X to split the hovered fragment at the cursor
@@ -178,6 +641,8 @@ impl JigsawApp {
                 (S, false) => Some(CompAction::MuteFragment(frag_hover.frag_idx)),
                 // S to solo the fragment under the cursor
                 (S, true) => Some(CompAction::SoloFragment(frag_hover.frag_idx)),
+                // c to cycle the call at the nearest lead end (plain -> bob -> single -> plain)
+                (C, false) => self.cycle_call(frag_hover),
 
                 // All other key presses are ignored
                 _ => None,
@@ -189,9 +654,68 @@ impl JigsawApp {
             }
         }
 
+        // Actions which apply to empty space under the cursor
+        if let (None, Some(position)) = (frag_hover, cursor_pos) {
+            let length = match (key, modifiers.shift) {
+                // a to add a lead of the selected method at the cursor
+                (A, false) => Some(FragLength::Leads(1)),
+                // A to add a plain course of the selected method at the cursor
+                (A, true) => Some(FragLength::PlainCourse),
+                // All other key presses are ignored
+                _ => None,
+            };
+            if let Some(length) = length {
+                return Some(CompAction::AddFragment {
+                    position,
+                    method_idx: self.selected_method,
+                    length,
+                });
+            }
+        }
+
         None
     }
 
+    /// If the cursor is over the leftover row of a fragment and `key` types a method's shorthand,
+    /// then extend the fragment with that method
+    fn extend_fragment(
+        &self,
+        key: egui::Key,
+        modifiers: egui::Modifiers,
+        frag_hover: &FragHover,
+    ) -> Option<CompAction> {
+        let fragment = &self.full_state.fragments[frag_hover.frag_idx];
+        if frag_hover.hovered_row_idx() != fragment.num_rows() as isize - 1 {
+            return None; // Not hovering over the leftover row
+        }
+        let letter = key_to_letter(key)?.to_string();
+        // Shorthands are unique, so at most one method can match
+        let method_idx = (self.full_state.methods.iter())
+            .position(|m| m.shorthand().eq_ignore_ascii_case(&letter))?;
+        Some(CompAction::ExtendFragment {
+            frag_idx: frag_hover.frag_idx,
+            method_idx: MethodIdx::new(method_idx),
+            length: if modifiers.shift {
+                FragLength::PlainCourse
+            } else {
+                FragLength::Leads(1)
+            },
+        })
+    }
+
+    fn duplicate_fragment(&self, frag_hover: &FragHover) -> CompAction {
+        let fragment = &self.full_state.fragments[frag_hover.frag_idx];
+        // Place the copy to the right of the original
+        let frag_width = self.config.col_width * self.full_state.stage.num_bells() as f32
+            + self.config.frag_padding_vec().x * 2.0;
+        let new_position =
+            fragment.position + Vec2::RIGHT * frag_width * self.config.duplicate_offset_x;
+        CompAction::DuplicateFragment {
+            frag_idx: frag_hover.frag_idx,
+            new_position,
+        }
+    }
+
     fn split_fragment(
         &self,
         frag_hover: &FragHover,
@@ -211,14 +735,51 @@ impl JigsawApp {
             FragSplitLocation::NearestRow => frag_hover.nearest_row_boundary(),
         };
         // Compute the position of the new fragment
+        let split_display_pos = fragment.row_pos_to_display_pos(split_index as f32);
         let pos_of_new_frag = fragment.position
-            + Vec2::DOWN * self.config.row_height * (split_index as f32 + self.config.split_height);
+            + Vec2::DOWN * self.config.row_height * (split_display_pos + self.config.split_height);
         Some(CompAction::SplitFragment {
             frag_idx: frag_hover.frag_idx,
             split_index,
             pos_of_new_frag,
         })
     }
+
+    /// Cycles the [`Call`](spec::Call) at the lead end closest to the cursor through every
+    /// call in the composition, in order, before returning to a plain lead.
+    fn cycle_call(&self, frag_hover: &FragHover) -> Option<CompAction> {
+        let fragment = &self.full_state.fragments[frag_hover.frag_idx];
+
+        // Find the nearest rule-off to the cursor, and use the lead end above it
+        let (ruleoff_idx, _dist) = fragment
+            .nearest_ruleoff_to(frag_hover.row_idx_float)
+            .filter(|(_idx, dist)| *dist < self.config.ruleoff_snap_distance)?;
+        // A rule-off above the first row (e.g. from `RuleoffPreset::EveryNRows` with an offset of
+        // 0) has no lead end above it
+        let lead_end_idx = RowIdx::new(ruleoff_idx.index().checked_sub(1)?);
+
+        // Find the index of the call which is currently at this lead end (if any)
+        let calls = &self.full_state.calls;
+        let current_call_idx = match &fragment.row_data(lead_end_idx).call_annotation {
+            Some(call) => Some(calls.iter().position(|c| Rc::ptr_eq(c, call))?),
+            None => None,
+        };
+        // Pick the next call in the list, or go back to a plain lead after the last call
+        let next_call_idx = match current_call_idx {
+            None => 0,
+            Some(idx) => idx + 1,
+        };
+        let new_call = (next_call_idx < calls.len()).then(|| CallIdx::new(next_call_idx));
+        if current_call_idx.is_none() && new_call.is_none() {
+            return None; // There are no calls to cycle through
+        }
+
+        Some(CompAction::SetCall {
+            frag_idx: frag_hover.frag_idx,
+            row_idx: lead_end_idx.index() as isize,
+            call_idx: new_call,
+        })
+    }
 }
 
 /////////////
@@ -230,6 +791,58 @@ impl JigsawApp {
         match action {
             Action::PanView(delta) => self.camera_pos += delta,
             Action::SetPartHeadString(new_part_head_str) => self.part_head_str = new_part_head_str,
+            Action::SelectMethod(method_idx) => self.selected_method = method_idx,
+            Action::ViewPart(part_idx) => self.part_being_viewed = part_idx,
+            Action::SetShowAllParts(show_all_parts) => self.show_all_parts = show_all_parts,
+            Action::SetMusicHighlightPart(part_idx) => self.music_highlight_part = part_idx,
+            Action::StartDraggingFragment(frag_idx) => {
+                self.frag_drag = Some(FragDrag {
+                    frag_idx,
+                    offset: Vec2::ZERO,
+                })
+            }
+            Action::DragFragment(delta) => {
+                if let Some(drag) = &mut self.frag_drag {
+                    drag.offset += delta;
+                }
+            }
+            Action::StopDraggingFragment => self.frag_drag = None,
+            Action::StartTransposing {
+                frag_idx,
+                part_idx,
+                row_idx,
+            } => self.start_transposing(frag_idx, part_idx, row_idx),
+            Action::SetTranspositionString(row_str) => {
+                if let Some(transposition) = &mut self.transposition {
+                    transposition.row_str = row_str;
+                    self.preview_transposition();
+                }
+            }
+            Action::FinishTransposing => self.finish_transposing(),
+            Action::StartEditingMethod(method_idx) => self.start_editing_method(method_idx),
+            Action::UpdateMethodEdit(method_edit) => self.method_edit = Some(method_edit),
+            Action::FinishEditingMethod => self.finish_editing_method(),
+            Action::CancelEditingMethod => self.method_edit = None,
+            Action::SearchMethodLib { text, class } => self.search_method_lib(text, class),
+            Action::SetMusicFileString(path) => self.music_file_box.path = path,
+            Action::LoadMusicFile => self.load_music_file(),
+            Action::SetNewCompPresets(presets) => self.new_comp_presets = presets,
+            Action::CancelTransposing => {
+                self.transposition = None;
+                // Remove the preview of the transposition
+                self.full_state
+                    .update(self.history.comp_spec(), &self.folds);
+            }
+            Action::ToggleLeadFold { frag_idx, row_idx } => {
+                // Folds aren't part of the undo history, so are changed without an undo step
+                let spec = self.history.comp_spec();
+                match self.folds.toggle(spec, frag_idx, row_idx) {
+                    Ok(()) => self.full_state.update(spec, &self.folds),
+                    Err(e) => println!("FOLD ERROR: {:?}", e),
+                }
+            }
+            Action::Save => self.save_error = self.save().err().map(|e| e.to_string()),
+            Action::DismissSaveError => self.save_error = None,
             Action::Comp(comp_action) => {
                 if let Err(e) = self.apply_comp_action(comp_action) {
                     println!("EDIT ERROR: {:?}", e);
@@ -238,6 +851,210 @@ impl JigsawApp {
         }
     }
 
+    fn start_editing_method(&mut self, method_idx: Option<MethodIdx>) {
+        self.method_edit = Some(match method_idx {
+            Some(idx) => {
+                let method = &self.full_state.methods[idx.index()];
+                MethodEdit {
+                    method_idx,
+                    name: method.name(),
+                    shorthand: method.shorthand(),
+                    place_notation: method.place_notation(),
+                    ruleoffs: method.ruleoff_preset(),
+                    error: None,
+                }
+            }
+            None => MethodEdit {
+                method_idx,
+                name: String::new(),
+                shorthand: String::new(),
+                place_notation: String::new(),
+                ruleoffs: Some(RuleoffPreset::LeadEnd),
+                error: None,
+            },
+        });
+    }
+
+    /// Apply the changes in the method edit dialog, closing the dialog if successful
+    fn finish_editing_method(&mut self) {
+        let mut method_edit = match self.method_edit.take() {
+            Some(m) => m,
+            None => return,
+        };
+        if let Err(e) = self.apply_method_edit(method_edit.clone()) {
+            // Leave the dialog open so the user can fix the problem
+            method_edit.error = Some(e);
+            self.method_edit = Some(method_edit);
+        }
+    }
+
+    /// Add or change a method according to the contents of the method edit dialog.  If this fails,
+    /// the composition is unchanged and the reason is returned.
+    fn apply_method_edit(&mut self, method_edit: MethodEdit) -> Result<(), String> {
+        let MethodEdit {
+            method_idx,
+            name,
+            shorthand,
+            place_notation,
+            ruleoffs,
+            error: _,
+        } = method_edit;
+        // Check the new method before changing anything
+        PnBlock::parse(&place_notation, self.full_state.stage).map_err(|e| e.to_string())?;
+        if let Some(e) = self.shorthand_error(&shorthand, method_idx) {
+            return Err(e);
+        }
+
+        let result = match method_idx {
+            Some(idx) => {
+                let old_spec = self.history.comp_spec().clone();
+                let old_method = &self.full_state.methods[idx.index()];
+                let is_changed = old_method.place_notation() != place_notation
+                    || old_method.ruleoff_preset() != ruleoffs;
+                let result = if is_changed {
+                    self.apply_comp_action(CompAction::EditMethod {
+                        method_idx: idx,
+                        place_notation,
+                        ruleoffs,
+                    })
+                } else {
+                    Ok(())
+                };
+                // Names aren't part of the undo history, so they are set directly (on the
+                // method from before and after the edit) once the rest of the edit has succeeded
+                result.and_then(|()| {
+                    old_spec.set_method_names(idx, name.clone(), shorthand.clone())?;
+                    let spec = self.history.comp_spec();
+                    Ok(spec.set_method_names(idx, name, shorthand)?)
+                })
+            }
+            None => self.apply_comp_action(CompAction::AddMethod {
+                name,
+                shorthand,
+                place_notation,
+                ruleoffs: ruleoffs.unwrap_or_default(),
+            }),
+        };
+        // The inputs have been checked, so any other error is a bug
+        result.map_err(|e| format!("{:?}", e))
+    }
+
+    /// The reason that `shorthand` can't be given to the method at `method_idx` (or a new method
+    /// if `method_idx` is `None`), if it can't
+    fn shorthand_error(&self, shorthand: &str, method_idx: Option<MethodIdx>) -> Option<String> {
+        match self
+            .history
+            .comp_spec()
+            .check_shorthand(shorthand, method_idx)
+        {
+            Ok(()) => None,
+            Err(spec::EditError::ShorthandUsed { method_idx, .. }) => Some(format!(
+                "Shorthand is already used by {}",
+                self.full_state.methods[method_idx.index()].name()
+            )),
+            Err(_) => Some("Shorthand must be a single letter".to_owned()),
+        }
+    }
+
+    /// Update the method library search box, and re-run the search
+    fn search_method_lib(&mut self, text: String, class: String) {
+        let results = match &self.method_lib {
+            Some(lib) if !text.trim().is_empty() => {
+                let query = Query {
+                    text: &text,
+                    stage: Some(self.full_state.stage),
+                    class: Some(class.as_str()).filter(|c| !c.trim().is_empty()),
+                };
+                (lib.search(&query, self.config.max_method_lib_results))
+                    .into_iter()
+                    .cloned()
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        self.method_lib_search = MethodLibSearch {
+            text,
+            class,
+            results,
+        };
+    }
+
+    /// Load the music file named in the music file box, replacing the composition's music.  The
+    /// path is relative to the directory containing the composition file.
+    fn load_music_file(&mut self) {
+        let path = PathBuf::from(self.music_file_box.path.trim());
+        let dir = self
+            .save_path
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or_else(|| Path::new(""));
+        match music_file::load(&dir.join(&path), self.full_state.stage) {
+            Ok(music) => {
+                self.music_file_box.error = None;
+                let action = CompAction::SetMusic {
+                    music,
+                    music_file: Some(path),
+                };
+                if let Err(e) = self.apply_comp_action(action) {
+                    println!("EDIT ERROR: {:?}", e);
+                }
+            }
+            Err(e) => self.music_file_box.error = Some(e.to_string()),
+        }
+    }
+
+    fn start_transposing(&mut self, frag_idx: FragIdx, part_idx: PartIdx, row_idx: RowIdx) {
+        // The user edits the row that they see, which may be in a different part to the one
+        // stored in the `CompSpec`
+        let part_head = &self.full_state.part_heads.rows()[part_idx.index()];
+        let visible_row = self.full_state.fragments[frag_idx].row_in_part(part_idx, row_idx);
+        self.transposition = Some(Transposition {
+            frag_idx,
+            row_idx,
+            inv_part_head: part_head.inv(),
+            row_str: visible_row.to_string(),
+        });
+    }
+
+    /// Replace `self.full_state` with a preview of the composition after the current
+    /// transposition, without creating an undo step
+    fn preview_transposition(&mut self) {
+        let transposition = match &self.transposition {
+            Some(t) => t,
+            None => return,
+        };
+        let new_row = match transposition.parse_row(self.full_state.stage) {
+            Ok(r) => r,
+            Err(_) => return, // Keep the last valid preview whilst the user is typing
+        };
+        let mut preview_spec = self.history.comp_spec().clone();
+        let result = preview_spec.transpose_fragment(
+            transposition.frag_idx,
+            transposition.row_idx.index() as isize,
+            &new_row,
+        );
+        if result.is_ok() {
+            self.full_state.update(&preview_spec, &self.folds);
+        }
+    }
+
+    /// Commit the current transposition as a new undo step, if the user's row is valid
+    fn finish_transposing(&mut self) {
+        let transposition = match &self.transposition {
+            Some(t) => t,
+            None => return,
+        };
+        if let Ok(new_row) = transposition.parse_row(self.full_state.stage) {
+            let action = CompAction::TransposeFragment {
+                frag_idx: transposition.frag_idx,
+                row_idx: transposition.row_idx.index() as isize,
+                new_row,
+            };
+            self.transposition = None;
+            self.apply_action(Action::Comp(action));
+        }
+    }
+
     fn apply_comp_action(&mut self, action: CompAction) -> Result<(), ActionError> {
         match action {
             CompAction::UndoRedo(direction) => {
@@ -256,17 +1073,114 @@ impl JigsawApp {
                 //
                 // TODO: Don't update the box if the user is part-way through editing it?
                 self.part_head_str = self.full_state.part_heads.spec_string();
+                // Similarly, the music file may have changed
+                self.music_file_box = MusicFileBox::new(self.history.comp_spec());
             }
             CompAction::SetPartHeads(new_part_heads) => {
                 self.history
                     .apply_infallible_edit(|spec| spec.set_part_heads(new_part_heads));
             }
+            CompAction::SetMusic { music, music_file } => self
+                .history
+                .apply_infallible_edit(|spec| spec.set_music(music, music_file)),
+            CompAction::NewComposition(music_presets) => {
+                // Replacing the composition is an undo step, so nothing is lost if the user
+                // clicks 'new' by accident
+                let stage = self.full_state.stage;
+                self.history
+                    .apply_infallible_edit(|spec| *spec = CompSpec::empty(stage, music_presets));
+                self.folds = Folds::default();
+                // New compositions are one-part, which is specified by the empty string
+                self.part_head_str = String::new();
+                self.music_file_box = MusicFileBox::default();
+            }
+            CompAction::EmbedMusic => {
+                self.history.apply_infallible_edit(CompSpec::embed_music);
+                self.music_file_box.path.clear();
+            }
             CompAction::SoloFragment(frag_idx) => {
                 self.history.apply_edit(|spec| spec.solo_frag(frag_idx))?
             }
             CompAction::MuteFragment(frag_idx) => self
                 .history
                 .apply_frag_edit(frag_idx, |frag| frag.toggle_mute())?,
+            CompAction::AddFragment {
+                position,
+                method_idx,
+                length,
+            } => {
+                let start_row = RowBuf::rounds(self.full_state.stage);
+                self.history.apply_edit(|spec| {
+                    spec.add_fragment(position, start_row, method_idx, length)
+                })?;
+            }
+            CompAction::ExtendFragment {
+                frag_idx,
+                method_idx,
+                length,
+            } => self
+                .history
+                .apply_edit(|spec| spec.extend_fragment(frag_idx, method_idx, length))?,
+            CompAction::AddMethod {
+                name,
+                shorthand,
+                place_notation,
+                ruleoffs,
+            } => {
+                self.history.apply_edit(|spec| {
+                    spec.add_method(name, shorthand, &place_notation, ruleoffs)
+                })?;
+            }
+            CompAction::AddLibMethod(lib_method) => {
+                // Select the new method, since the user is probably about to use it
+                self.selected_method = self
+                    .history
+                    .apply_edit(|spec| spec.add_lib_method(&lib_method))?;
+            }
+            CompAction::EditMethod {
+                method_idx,
+                place_notation,
+                ruleoffs,
+            } => self
+                .history
+                .apply_edit(|spec| spec.edit_method(method_idx, &place_notation, ruleoffs))?,
+            CompAction::RemoveMethod(method_idx) => {
+                self.history
+                    .apply_edit(|spec| spec.remove_method(method_idx))?;
+                // Keep the same method selected, if it still exists
+                if self.selected_method > method_idx {
+                    self.selected_method -= 1;
+                }
+            }
+            CompAction::DuplicateFragment {
+                frag_idx,
+                new_position,
+            } => {
+                let new_frag_idx = self
+                    .history
+                    .apply_edit(|spec| spec.duplicate_fragment(frag_idx, new_position))?;
+                (self.folds).duplicate_fragment(self.history.comp_spec(), frag_idx, new_frag_idx);
+            }
+            CompAction::MoveFragment {
+                frag_idx,
+                new_position,
+            } => self
+                .history
+                .apply_edit(|spec| spec.move_fragment(frag_idx, new_position))?,
+            CompAction::JoinFragments { top, bottom } => {
+                // Keep the spec from before the join, so that the folds can be copied from it
+                let old_spec = self.history.comp_spec().clone();
+                self.history
+                    .apply_edit(|spec| spec.join_fragments(top, bottom))?;
+                (self.folds).join_fragments(&old_spec, self.history.comp_spec(), top, bottom);
+            }
+            CompAction::TransposeFragment {
+                frag_idx,
+                row_idx,
+                new_row,
+            } => self
+                .history
+                .apply_edit(|spec| spec.transpose_fragment(frag_idx, row_idx, &new_row))?,
             CompAction::DeleteFragment(frag_idx) => self
                 .history
                 .apply_edit(|spec| spec.delete_fragment(frag_idx))?,
@@ -274,12 +1188,42 @@ impl JigsawApp {
                 frag_idx,
                 split_index,
                 pos_of_new_frag,
+            } => {
+                let old_spec = self.history.comp_spec().clone();
+                self.history.apply_edit(|spec| {
+                    spec.split_fragment(frag_idx, split_index, pos_of_new_frag)
+                })?;
+                let new_spec = self.history.comp_spec();
+                (self.folds).split_fragment(&old_spec, new_spec, frag_idx, split_index);
+            }
+            CompAction::SetCall {
+                frag_idx,
+                row_idx,
+                call_idx,
             } => self
                 .history
-                .apply_edit(|spec| spec.split_fragment(frag_idx, split_index, pos_of_new_frag))?,
+                .apply_edit(|spec| spec.set_call(frag_idx, row_idx, call_idx))?,
         }
         // If the edit succeeded, rebuild `self.full_state` so that the new changes are rendered
-        self.full_state.update(self.history.comp_spec());
+        self.full_state
+            .update(self.history.comp_spec(), &self.folds);
+        // If the number of parts has decreased, then the part being viewed may no longer exist
+        let num_parts = self.full_state.part_heads.len();
+        if self.part_being_viewed.index() >= num_parts {
+            self.part_being_viewed = PartIdx::new(num_parts - 1);
+        }
+        if self
+            .music_highlight_part
+            .is_some_and(|p| p.index() >= num_parts)
+        {
+            self.music_highlight_part = None;
+        }
+        // Similarly, removing the last method (or undoing adding one) can leave the selected
+        // method out of range
+        let num_methods = self.full_state.methods.len();
+        if self.selected_method.index() >= num_methods {
+            self.selected_method = MethodIdx::new(num_methods.saturating_sub(1));
+        }
         Ok(())
     }
 }
@@ -294,6 +1238,57 @@ pub(crate) enum Action {
     PanView(Vec2),
     /// Update the 'Part Heads' box to some new value
     SetPartHeadString(String),
+    /// Choose which method will be used for new fragments
+    SelectMethod(MethodIdx),
+    /// Choose which part is displayed on the canvas
+    ViewPart(PartIdx),
+    /// Choose whether or not every part is displayed side by side on the canvas
+    SetShowAllParts(bool),
+    /// Choose which part's rows are highlighted when hovering over music (`None` for all parts)
+    SetMusicHighlightPart(Option<PartIdx>),
+    /// Start dragging a fragment with the mouse
+    StartDraggingFragment(FragIdx),
+    /// Move the fragment being dragged by some amount.  This only moves the preview of the
+    /// fragment; the composition isn't changed until the fragment is dropped.
+    DragFragment(Vec2),
+    /// Let go of the fragment being dragged
+    StopDraggingFragment,
+    /// Start transposing a fragment by editing the row at `row_idx`, as it appears in the part
+    /// `part_idx`
+    StartTransposing {
+        frag_idx: FragIdx,
+        part_idx: PartIdx,
+        row_idx: RowIdx,
+    },
+    /// Update the contents of the transposition box, previewing the result if it's a valid row
+    SetTranspositionString(String),
+    /// Commit the current transposition as a new undo step
+    FinishTransposing,
+    /// Open the method edit dialog for an existing method, or for a new method if `None`
+    StartEditingMethod(Option<MethodIdx>),
+    /// Update the contents of the method edit dialog
+    UpdateMethodEdit(MethodEdit),
+    /// Apply the changes in the method edit dialog
+    FinishEditingMethod,
+    /// Close the method edit dialog without changing the composition
+    CancelEditingMethod,
+    /// Update the method library search boxes, re-running the search
+    SearchMethodLib { text: String, class: String },
+    /// Update the contents of the music file box
+    SetMusicFileString(String),
+    /// Load the music from the file named in the music file box
+    LoadMusicFile,
+    /// Choose the music presets which will be used by new compositions
+    SetNewCompPresets(MusicPresets),
+    /// Exit transposition mode without changing the composition
+    CancelTransposing,
+    /// Fold or unfold the lead containing a given row.  This isn't a [`CompAction`] because
+    /// folding isn't part of the undo history.
+    ToggleLeadFold { frag_idx: FragIdx, row_idx: isize },
+    /// Save the composition to the file it was loaded from
+    Save,
+    /// Close the window explaining why the last save failed
+    DismissSaveError,
     /// Make an edit to the composition
     Comp(CompAction),
 }
@@ -303,10 +1298,73 @@ pub(crate) enum Action {
 pub(crate) enum CompAction {
     /// Updates the [`PartHeads`] of the current [`CompSpec`]
     SetPartHeads(PartHeads),
+    /// Replace the composition's music with music loaded from a music definition file (or
+    /// embedded in the composition file, if `music_file` is `None`)
+    SetMusic {
+        music: Vec<Music>,
+        music_file: Option<PathBuf>,
+    },
+    /// Stop referencing a music definition file, embedding the music in the composition file
+    EmbedMusic,
+    /// Replace the composition with an empty one (of the same stage), whose music is generated
+    /// from a set of presets
+    NewComposition(MusicPresets),
     /// Undo or redo (which are similar enough to be handled as one case)
     UndoRedo(HistoryDirection),
     MuteFragment(FragIdx),
     SoloFragment(FragIdx),
+    /// Add a new fragment of plain leads, starting from rounds
+    AddFragment {
+        position: Pos2,
+        method_idx: MethodIdx,
+        length: FragLength,
+    },
+    /// Add rows of a method to the end of a fragment
+    ExtendFragment {
+        frag_idx: FragIdx,
+        method_idx: MethodIdx,
+        length: FragLength,
+    },
+    /// Add a new method to the composition
+    AddMethod {
+        name: String,
+        shorthand: String,
+        place_notation: String,
+        ruleoffs: RuleoffPreset,
+    },
+    /// Add a method from the method library to the composition
+    AddLibMethod(LibMethod),
+    /// Change the place notation and rule-offs of a method.  If `ruleoffs` is `None`, the
+    /// method's existing rule-offs are kept.
+    EditMethod {
+        method_idx: MethodIdx,
+        place_notation: String,
+        ruleoffs: Option<RuleoffPreset>,
+    },
+    /// Remove a method which isn't used in the composition
+    RemoveMethod(MethodIdx),
+    /// Create a copy of a fragment at a new position
+    DuplicateFragment {
+        frag_idx: FragIdx,
+        new_position: Pos2,
+    },
+    /// Move a fragment to a new position on the canvas
+    MoveFragment {
+        frag_idx: FragIdx,
+        new_position: Pos2,
+    },
+    /// Join the fragment `bottom` onto the end of the fragment `top`
+    JoinFragments {
+        top: FragIdx,
+        bottom: FragIdx,
+    },
+    /// Transpose a fragment so that the row at `row_idx` becomes `new_row`.  `new_row` is the row
+    /// in the first part, not necessarily the one displayed on screen.
+    TransposeFragment {
+        frag_idx: FragIdx,
+        row_idx: isize,
+        new_row: RowBuf,
+    },
     /// Delete a fragment
     DeleteFragment(FragIdx),
     /// Split a fragment at a given row
@@ -315,6 +1373,12 @@ pub(crate) enum CompAction {
         split_index: isize,
         pos_of_new_frag: Pos2,
     },
+    /// Set, change or remove the call starting at a given row (`None` removes the call)
+    SetCall {
+        frag_idx: FragIdx,
+        row_idx: isize,
+        call_idx: Option<CallIdx>,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -342,8 +1406,98 @@ pub(crate) enum HistoryDirection {
 // MISC //
 //////////
 
+/// The contents of the method edit dialog.  This can represent invalid methods (e.g. with
+/// unparseable place notation), so is kept separate from the `CompSpec` until the user saves it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MethodEdit {
+    /// The method being edited, or `None` if this is a new method
+    method_idx: Option<MethodIdx>,
+    name: String,
+    shorthand: String,
+    place_notation: String,
+    /// The rule-offs of the method, or `None` if they don't match any [`RuleoffPreset`] (in which
+    /// case they aren't changed)
+    ruleoffs: Option<RuleoffPreset>,
+    /// The reason that the last attempt to save this method failed, if it failed
+    error: Option<String>,
+}
+
+/// The contents of the method library search boxes, along with the methods they match
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MethodLibSearch {
+    /// Text to search for in the methods' titles
+    text: String,
+    /// The classification of the methods (e.g. `Surprise`).  If empty, any class is allowed.
+    class: String,
+    /// The methods matching this search, cached so that the library isn't searched every frame
+    results: Vec<LibMethod>,
+}
+
+/// The contents of the music file box, which loads the composition's music from a music definition
+/// file
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MusicFileBox {
+    /// The path of the music file, relative to the composition file
+    path: String,
+    /// The reason that the last music file couldn't be loaded, if it failed
+    error: Option<String>,
+}
+
+impl MusicFileBox {
+    /// Creates a [`MusicFileBox`] containing the music file referenced by a [`CompSpec`]
+    fn new(spec: &CompSpec) -> Self {
+        Self {
+            path: spec
+                .music_file()
+                .map_or_else(String::new, |p| p.display().to_string()),
+            error: None,
+        }
+    }
+}
+
+/// The state of a fragment which is being dragged by the user
+#[derive(Debug, Clone)]
+struct FragDrag {
+    frag_idx: FragIdx,
+    /// How far the fragment has been dragged from its original position
+    offset: Vec2,
+}
+
+/// The state of a fragment which is being transposed by the user
+#[derive(Debug, Clone)]
+struct Transposition {
+    frag_idx: FragIdx,
+    row_idx: RowIdx,
+    /// The inverse of the part head of the part which was being viewed when the transposition
+    /// started.  This converts the row that the user sees into the corresponding row in the first
+    /// part (which is what's stored in the `CompSpec`).
+    inv_part_head: RowBuf,
+    /// The contents of the transposition box.  Whilst the user is typing, this may not be a valid
+    /// row.
+    row_str: String,
+}
+
+impl Transposition {
+    /// Parse `self.row_str`, converting it into the corresponding row in the first part
+    fn parse_row(&self, stage: bellframe::Stage) -> Result<RowBuf, bellframe::InvalidRowError> {
+        let visible_row = RowBuf::parse_with_stage(&self.row_str, stage)?;
+        Ok(self.inv_part_head.as_row() * visible_row.as_row())
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 enum FragSplitLocation {
     NearestRow,
     NearestRuleoff,
 }
+
+/// Converts a letter [`Key`](egui::Key) into the (lower-case) letter it types
+fn key_to_letter(key: egui::Key) -> Option<char> {
+    use egui::Key::*;
+
+    const LETTER_KEYS: [egui::Key; 26] = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+    let idx = LETTER_KEYS.iter().position(|k| *k == key)?;
+    Some((b'a' + idx as u8) as char)
+}
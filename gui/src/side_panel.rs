@@ -1,41 +1,104 @@
 //! Drawing code for the GUI's side panel
 
-use std::{collections::HashSet, rc::Rc};
+use std::{collections::HashSet, path::Path, rc::Rc};
 
 use eframe::egui::{self, Color32, Ui};
+use itertools::Itertools;
 use jigsaw_comp::{
     full::{self, FullState, MusicGroupInner},
+    method_lib::MethodLib,
     spec::part_heads,
+    MusicPresets,
+};
+use jigsaw_utils::{
+    indexed_vec::{FragIdx, MethodIdx, PartIdx},
+    types::RowLocation,
 };
-use jigsaw_utils::types::RowSource;
 
-use crate::{Action, CompAction};
+use crate::{Action, CompAction, MethodLibSearch, MusicFileBox};
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn draw(
     ctx: &egui::CtxRef,
     state: &FullState,
     part_head_str: &str,
+    part_being_viewed: PartIdx,
+    show_all_parts: bool,
+    music_highlight_part: Option<PartIdx>,
+    selected_method: MethodIdx,
+    method_lib: Option<&MethodLib>,
+    method_lib_error: Option<&str>,
+    method_lib_search: &MethodLibSearch,
+    music_file_box: &MusicFileBox,
+    music_file: Option<&Path>,
+    new_comp_presets: MusicPresets,
     push_action: impl FnMut(Action),
-) -> HashSet<RowSource> {
+) -> HashSet<RowLocation> {
     egui::SidePanel::right("side_panel")
         .show(ctx, |ui| {
-            draw_panel_contents(ui, state, part_head_str, push_action)
+            draw_panel_contents(
+                ui,
+                state,
+                part_head_str,
+                part_being_viewed,
+                show_all_parts,
+                music_highlight_part,
+                selected_method,
+                method_lib,
+                method_lib_error,
+                method_lib_search,
+                music_file_box,
+                music_file,
+                new_comp_presets,
+                push_action,
+            )
         })
         .inner
 }
 
+#[allow(clippy::too_many_arguments)]
 fn draw_panel_contents(
     ui: &mut Ui,
     full_state: &FullState,
     part_head_str: &str,
-    push_action: impl FnMut(Action),
-) -> HashSet<RowSource> {
+    part_being_viewed: PartIdx,
+    show_all_parts: bool,
+    music_highlight_part: Option<PartIdx>,
+    selected_method: MethodIdx,
+    method_lib: Option<&MethodLib>,
+    method_lib_error: Option<&str>,
+    method_lib_search: &MethodLibSearch,
+    music_file_box: &MusicFileBox,
+    music_file: Option<&Path>,
+    new_comp_presets: MusicPresets,
+    mut push_action: impl FnMut(Action),
+) -> HashSet<RowLocation> {
     const PANEL_SPACE: f32 = 5.0; // points
 
-    let mut rows_to_highlight = HashSet::<RowSource>::new();
+    let mut rows_to_highlight = HashSet::<RowLocation>::new();
 
     ui.heading("Jigsaw");
 
+    // New composition, with a choice of which music presets to start with
+    ui.horizontal(|ui| {
+        if ui.button("New composition").clicked() {
+            push_action(Action::Comp(CompAction::NewComposition(new_comp_presets)));
+        }
+        ui.label("Music:");
+        egui::ComboBox::from_id_source("new_comp_presets")
+            .selected_text(new_comp_presets.to_string())
+            .show_ui(ui, |ui| {
+                for presets in MusicPresets::ALL {
+                    let label =
+                        ui.selectable_label(presets == new_comp_presets, presets.to_string());
+                    if label.clicked() && presets != new_comp_presets {
+                        push_action(Action::SetNewCompPresets(presets));
+                    }
+                }
+            });
+    });
+    ui.add_space(PANEL_SPACE);
+
     // General info
     let part_len = full_state.stats.part_len;
     let num_parts = full_state.part_heads.len();
@@ -45,6 +108,22 @@ fn draw_panel_contents(
         num_parts,
         part_len * num_parts
     ));
+    // Falseness
+    let num_false_rows = full_state.stats.num_false_rows;
+    if num_false_rows == 0 {
+        ui.label("true");
+    } else {
+        let label =
+            egui::Label::new(format!("{} false rows", num_false_rows)).text_color(Color32::RED);
+        ui.label(label);
+    }
+    // Round block status
+    if full_state.stats.is_round_block {
+        ui.label("Comes round");
+    } else {
+        let label = egui::Label::new("Doesn't come round").text_color(Color32::YELLOW);
+        ui.label(label);
+    }
 
     ui.add_space(PANEL_SPACE);
 
@@ -55,7 +134,14 @@ fn draw_panel_contents(
         let r = egui::CollapsingHeader::new(part_panel_title)
             .id_source("Parts")
             .show(panels_ui, |ui| {
-                draw_parts_panel(ui, full_state, part_head_str, push_action)
+                draw_parts_panel(
+                    ui,
+                    full_state,
+                    part_head_str,
+                    part_being_viewed,
+                    show_all_parts,
+                    &mut push_action,
+                )
             });
         // Add space only when the panel is open
         if r.body_response.is_some() {
@@ -66,17 +152,36 @@ fn draw_panel_contents(
         let method_panel_title = format!("Methods ({})", full_state.methods.len());
         let r = egui::CollapsingHeader::new(method_panel_title)
             .id_source("Methods")
-            .show(panels_ui, |ui| draw_method_panel(ui, full_state));
+            .show(panels_ui, |ui| {
+                draw_method_panel(ui, full_state, selected_method, &mut push_action)
+            });
+        // Add space only when the panel is open
+        if r.body_response.is_some() {
+            panels_ui.add_space(PANEL_SPACE);
+        }
+
+        // Method library panel
+        let r = egui::CollapsingHeader::new("Method library")
+            .id_source("Method library")
+            .show(panels_ui, |ui| {
+                draw_method_lib_panel(
+                    ui,
+                    method_lib,
+                    method_lib_error,
+                    method_lib_search,
+                    &mut push_action,
+                )
+            });
         // Add space only when the panel is open
         if r.body_response.is_some() {
             panels_ui.add_space(PANEL_SPACE);
         }
 
         // Calls panel
-        let r = panels_ui.collapsing("Calls", |ui| {
-            ui.label("14 LE -");
-            ui.label("1234 LE s");
-        });
+        let call_panel_title = format!("Calls ({})", full_state.calls.len());
+        let r = egui::CollapsingHeader::new(call_panel_title)
+            .id_source("Calls")
+            .show(panels_ui, |ui| draw_call_panel(ui, full_state));
         // Add space only when the panel is open
         if r.body_response.is_some() {
             panels_ui.add_space(PANEL_SPACE);
@@ -84,11 +189,24 @@ fn draw_panel_contents(
 
         // Music panel
         let music = &full_state.music;
-        let label = format!("Music ({}/{})", music.total_count(), music.max_count());
+        let label = format!(
+            "Music ({}/{}, score {})",
+            music.total_count(),
+            music.max_count(),
+            music.total_score()
+        );
         egui::CollapsingHeader::new(label)
             .id_source("Music")
             .show(panels_ui, |ui| {
-                draw_music_ui(ui, music.groups(), &mut rows_to_highlight);
+                draw_music_panel(
+                    ui,
+                    full_state,
+                    music_file_box,
+                    music_file,
+                    music_highlight_part,
+                    &mut rows_to_highlight,
+                    &mut push_action,
+                )
             });
     });
 
@@ -99,6 +217,8 @@ fn draw_parts_panel(
     ui: &mut Ui,
     full_state: &FullState,
     part_head_str: &str,
+    part_being_viewed: PartIdx,
+    show_all_parts: bool,
     mut push_action: impl FnMut(Action),
 ) {
     let mut part_head_str_mut = part_head_str.to_owned();
@@ -131,43 +251,69 @@ fn draw_parts_panel(
         ui.label("Parts don't form a group!");
     }
 
-    // Part list
+    // Part selector
     ui.separator();
-    for r in full_state.part_heads.rows() {
-        ui.label(r.to_string());
+    let num_parts = full_state.part_heads.len();
+    let part = part_being_viewed.index();
+    ui.horizontal(|ui| {
+        // The part selector wraps round at either end, like Page Up/Page Down
+        if ui.button("<").clicked() {
+            push_action(Action::ViewPart(PartIdx::new(
+                (part + num_parts - 1) % num_parts,
+            )));
+        }
+        ui.label(format!("Viewing part {}/{}", part + 1, num_parts));
+        if ui.button(">").clicked() {
+            push_action(Action::ViewPart(PartIdx::new((part + 1) % num_parts)));
+        }
+    });
+    // Toggle for drawing every part side by side
+    let mut show_all_parts_mut = show_all_parts;
+    ui.checkbox(&mut show_all_parts_mut, "Show all parts");
+    if show_all_parts_mut != show_all_parts {
+        push_action(Action::SetShowAllParts(show_all_parts_mut));
+    }
+
+    // Part list.  Clicking a part head displays that part on the canvas.
+    for (i, r) in full_state.part_heads.rows().iter().enumerate() {
+        if ui.selectable_label(i == part, r.to_string()).clicked() {
+            push_action(Action::ViewPart(PartIdx::new(i)));
+        }
     }
 }
 
-fn draw_method_panel(ui: &mut Ui, full_state: &FullState) {
+fn draw_method_panel(
+    ui: &mut Ui,
+    full_state: &FullState,
+    selected_method: MethodIdx,
+    mut push_action: impl FnMut(Action),
+) {
     for (i, method) in full_state.methods.iter().enumerate() {
-        left_then_right(
+        let method_idx = MethodIdx::new(i);
+        let (was_selected, button_action) = left_then_right(
             ui,
-            // The main label sticks to the left
+            // The main label sticks to the left.  Clicking it selects the method which will be
+            // used for new fragments.
             |left_ui| {
-                left_ui.label(format!(
-                    "(#{}, {}): {}",
-                    i,
-                    method.shorthand(),
-                    method.name()
-                ))
+                let label = format!("(#{}, {}): {}", i, method.shorthand(), method.name());
+                left_ui
+                    .selectable_label(method_idx == selected_method, label)
+                    .clicked()
             },
             |right_ui| {
+                let mut action = None;
+                // Because we're in a right-to-left block, the buttons are added from right to
+                // left (which feels like the reverse order)
                 if method.num_rows == 0 {
-                    // Because we're in a right-to-left block, the buttons are added from right
-                    // to left (which feels like the reverse order)
+                    // Methods can only be deleted if they aren't used
                     if right_ui.button("del").clicked() {
-                        println!(
-                            "Can't delete methods.  Even {}, good though it is!",
-                            method.name()
-                        );
-                    }
-                    if right_ui.button("edit").clicked() {
-                        println!(
-                            "Can't edit methods.  Even {}, good though it is!",
-                            method.name()
-                        );
+                        action = Some(Action::Comp(CompAction::RemoveMethod(method_idx)));
                     }
-                } else {
+                }
+                if right_ui.button("edit").clicked() {
+                    action = Some(Action::StartEditingMethod(Some(method_idx)));
+                }
+                if method.num_rows > 0 {
                     // If the method is used, then display either 'x rows' or 'x/y rows',
                     // depending on whether or not all the method's rows are muted
                     let label_text = if method.num_proved_rows == method.num_rows {
@@ -177,32 +323,302 @@ fn draw_method_panel(ui: &mut Ui, full_state: &FullState) {
                     };
                     right_ui.label(label_text);
                 }
+                action
+            },
+        )
+        .inner;
+
+        if was_selected {
+            push_action(Action::SelectMethod(method_idx));
+        }
+        if let Some(action) = button_action {
+            push_action(action);
+        }
+    }
+
+    if ui.button("Add method").clicked() {
+        push_action(Action::StartEditingMethod(None));
+    }
+}
+
+fn draw_method_lib_panel(
+    ui: &mut Ui,
+    method_lib: Option<&MethodLib>,
+    method_lib_error: Option<&str>,
+    search: &MethodLibSearch,
+    mut push_action: impl FnMut(Action),
+) {
+    let method_lib = match (method_lib, method_lib_error) {
+        (Some(lib), _) => lib,
+        (None, Some(error)) => {
+            ui.label(egui::Label::new(error).text_color(Color32::RED));
+            return;
+        }
+        (None, None) => {
+            ui.label("No method library loaded");
+            return;
+        }
+    };
+
+    // Search boxes
+    let mut text = search.text.clone();
+    let mut class = search.class.clone();
+    egui::Grid::new("method_lib_search_grid").show(ui, |grid_ui| {
+        grid_ui.label("Title");
+        grid_ui.text_edit_singleline(&mut text);
+        grid_ui.end_row();
+        grid_ui.label("Class");
+        grid_ui.text_edit_singleline(&mut class);
+        grid_ui.end_row();
+    });
+    // Re-run the search if the user changed either box
+    if text != search.text || class != search.class {
+        push_action(Action::SearchMethodLib { text, class });
+    }
+
+    // Search results
+    ui.separator();
+    if search.results.is_empty() {
+        ui.label(format!("Searching {} methods", method_lib.len()));
+    }
+    for lib_method in &search.results {
+        let add_clicked = left_then_right(
+            ui,
+            // The method's title sticks to the left
+            |left_ui| {
+                left_ui.label(&lib_method.title);
+            },
+            |right_ui| right_ui.button("add").clicked(),
+        )
+        .inner
+        .1;
+        if add_clicked {
+            let action = CompAction::AddLibMethod(lib_method.clone());
+            push_action(Action::Comp(action));
+        }
+    }
+}
+
+fn draw_call_panel(ui: &mut Ui, full_state: &FullState) {
+    for call in &full_state.calls {
+        left_then_right(
+            ui,
+            // The main label sticks to the left
+            |left_ui| {
+                left_ui.label(format!(
+                    "{} {} {}",
+                    call.place_notation(),
+                    call.location(),
+                    call.symbol()
+                ))
+            },
+            |right_ui| {
+                // Display either 'x uses' or 'x/y uses', depending on whether or not all the
+                // call's uses are proved
+                let label_text = if call.num_proved_uses == call.num_uses {
+                    format!("{} uses", call.num_uses)
+                } else {
+                    format!("{}/{} uses", call.num_proved_uses, call.num_uses)
+                };
+                right_ui.label(label_text);
             },
         );
     }
 }
 
+fn draw_music_panel(
+    ui: &mut Ui,
+    full_state: &FullState,
+    music_file_box: &MusicFileBox,
+    music_file: Option<&Path>,
+    music_highlight_part: Option<PartIdx>,
+    rows_to_highlight: &mut HashSet<RowLocation>,
+    mut push_action: impl FnMut(Action),
+) {
+    let music = &full_state.music;
+    let part_heads = full_state.part_heads.rows();
+
+    // Music file box, to share music definitions between compositions
+    ui.horizontal(|ui| {
+        ui.label("File:");
+        let mut path = music_file_box.path.clone();
+        ui.text_edit_singleline(&mut path);
+        if path != music_file_box.path {
+            push_action(Action::SetMusicFileString(path));
+        }
+        if ui.button("load").clicked() {
+            push_action(Action::LoadMusicFile);
+        }
+        // Music can only be embedded if it's currently loaded from a file
+        if music_file.is_some() && ui.button("embed").clicked() {
+            push_action(Action::Comp(CompAction::EmbedMusic));
+        }
+    });
+    if let Some(error) = &music_file_box.error {
+        ui.label(egui::Label::new(error).text_color(Color32::RED));
+    }
+
+    // Part selector, to restrict highlighting to the rows of one part
+    let part_label = |part: Option<PartIdx>| match part {
+        Some(p) => format!("Part {} ({})", p.index() + 1, part_heads[p.index()]),
+        None => "All parts".to_owned(),
+    };
+    ui.horizontal(|ui| {
+        ui.label("Highlight:");
+        egui::ComboBox::from_id_source("music_highlight_part")
+            .selected_text(part_label(music_highlight_part))
+            .show_ui(ui, |ui| {
+                let options = std::iter::once(None)
+                    .chain((0..part_heads.len()).map(|i| Some(PartIdx::new(i))));
+                for part in options {
+                    let label = ui.selectable_label(music_highlight_part == part, part_label(part));
+                    if label.clicked() && music_highlight_part != part {
+                        push_action(Action::SetMusicHighlightPart(part));
+                    }
+                }
+            });
+    });
+
+    // Music groups
+    let filter = |loc: &RowLocation| music_highlight_part.is_none_or(|part| loc.part_index == part);
+    draw_music_ui(ui, music.groups(), &filter, rows_to_highlight);
+
+    // Breakdown tables.  Hovering over a count highlights the rows which it counts
+    let stroke_of = |loc: &RowLocation| {
+        full_state.fragments[loc.frag_index].stroke(loc.row_index, loc.part_index)
+    };
+    egui::CollapsingHeader::new("Per stroke")
+        .id_source("Music per stroke")
+        .show(ui, |ui| {
+            draw_music_table(
+                ui,
+                "Music per stroke table",
+                music,
+                &["Handstroke".to_owned(), "Backstroke".to_owned()],
+                |g| &g.counts_per_stroke,
+                music.counts_per_stroke(),
+                |loc, column| stroke_of(loc) as usize == column && filter(loc),
+                rows_to_highlight,
+            )
+        });
+    let part_names = (1..=part_heads.len())
+        .map(|i| format!("Part {}", i))
+        .collect_vec();
+    egui::CollapsingHeader::new("Per part")
+        .id_source("Music per part")
+        .show(ui, |ui| {
+            draw_music_table(
+                ui,
+                "Music per part table",
+                music,
+                &part_names,
+                |g| g.counts_per_part.as_raw_slice(),
+                music.counts_per_part().as_raw_slice(),
+                |loc, column| loc.part_index.index() == column,
+                rows_to_highlight,
+            )
+        });
+    let frag_names = (0..full_state.fragments.len())
+        .map(|i| format!("Frag {}", i))
+        .collect_vec();
+    egui::CollapsingHeader::new("Per fragment")
+        .id_source("Music per fragment")
+        .show(ui, |ui| {
+            draw_music_table(
+                ui,
+                "Music per fragment table",
+                music,
+                &frag_names,
+                |g| g.counts_per_frag.as_raw_slice(),
+                music.counts_per_frag().as_raw_slice(),
+                |loc, column| loc.frag_index == FragIdx::new(column) && filter(loc),
+                rows_to_highlight,
+            )
+        });
+}
+
+/// Draws a table of music counts, with one row per music group (plus a row for the totals) and
+/// one column per entry in `column_names`.  `counts` returns the counts of a given group, and
+/// `in_column` determines which matched rows are counted in each column.
+#[allow(clippy::too_many_arguments)]
+fn draw_music_table(
+    ui: &mut Ui,
+    id: &str,
+    music: &full::Music,
+    column_names: &[String],
+    counts: fn(&full::MusicGroup) -> &[usize],
+    totals: &[usize],
+    in_column: impl Fn(&RowLocation, usize) -> bool,
+    rows_to_highlight: &mut HashSet<RowLocation>,
+) {
+    // Flatten the group tree, so that each group gets one row of the table
+    fn flatten<'m>(
+        groups: &'m [Rc<full::MusicGroup>],
+        depth: usize,
+        out: &mut Vec<(usize, &'m full::MusicGroup)>,
+    ) {
+        for g in groups {
+            out.push((depth, g));
+            if let MusicGroupInner::Group { sub_groups, .. } = &g.inner {
+                flatten(sub_groups, depth + 1, out);
+            }
+        }
+    }
+    let mut groups = Vec::new();
+    flatten(music.groups(), 0, &mut groups);
+
+    egui::Grid::new(id).striped(true).show(ui, |ui| {
+        // Header
+        ui.label("");
+        for name in column_names {
+            ui.label(name);
+        }
+        ui.end_row();
+        // One row per group
+        for (depth, group) in groups {
+            ui.label(format!("{}{}", "  ".repeat(depth), group.name));
+            for (column, count) in counts(group).iter().enumerate() {
+                if ui.label(count.to_string()).hovered() {
+                    group.add_row_locations(&|loc| in_column(loc, column), rows_to_highlight);
+                }
+            }
+            ui.end_row();
+        }
+        // Totals
+        ui.label("Total");
+        for count in totals {
+            ui.label(count.to_string());
+        }
+        ui.end_row();
+    });
+}
+
 /// Recursively creates the GUI for a set of `MusicGroup`s
 fn draw_music_ui(
     ui: &mut Ui,
     musics: &[Rc<full::MusicGroup>],
-    rows_to_highlight: &mut HashSet<RowSource>,
+    filter: &dyn Fn(&RowLocation) -> bool,
+    rows_to_highlight: &mut HashSet<RowLocation>,
 ) {
     for m in musics {
-        draw_music_group_ui(m, ui, rows_to_highlight);
+        draw_music_group_ui(m, ui, filter, rows_to_highlight);
     }
 }
 
-/// Recursively creates the GUI for a single `MusicGroup`
+/// Recursively creates the GUI for a single `MusicGroup`.  Hovering over it highlights every
+/// row which it matches, provided that the row's location satisfies `filter`.
 fn draw_music_group_ui(
     group: &full::MusicGroup,
     ui: &mut Ui,
-    rows_to_highlight: &mut HashSet<RowSource>,
+    filter: &dyn Fn(&RowLocation) -> bool,
+    rows_to_highlight: &mut HashSet<RowLocation>,
 ) {
     let full::MusicGroup {
         name,
         max_count,
+        score,
         inner,
+        ..
     } = group;
 
     let response = match inner {
@@ -210,16 +626,19 @@ fn draw_music_group_ui(
             left_then_right(
                 ui,
                 |left_ui| left_ui.label(name),
-                |right_ui| right_ui.label(format!("{}/{}", rows_matched.len(), max_count)),
+                |right_ui| {
+                    let label = format!("{}/{}, score {}", rows_matched.len(), max_count, score);
+                    right_ui.label(label)
+                },
             )
             .response // Get the response from the entire horizontal layout
         }
         MusicGroupInner::Group { sub_groups, count } => {
-            let label = format!("{} ({}/{})", name, count, max_count);
+            let label = format!("{} ({}/{}, score {})", name, count, max_count, score);
             egui::CollapsingHeader::new(label)
                 .id_source(name)
                 .show(ui, |sub_ui| {
-                    draw_music_ui(sub_ui, sub_groups, rows_to_highlight)
+                    draw_music_ui(sub_ui, sub_groups, filter, rows_to_highlight)
                 })
                 .header_response
         }
@@ -227,7 +646,7 @@ fn draw_music_group_ui(
 
     // If this is being hovered, then highlight every row matched by any of its descendants
     if response.hovered() {
-        group.add_row_sources(rows_to_highlight);
+        group.add_row_locations(filter, rows_to_highlight);
     }
 }
 
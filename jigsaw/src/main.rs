@@ -1,6 +1,47 @@
+use std::path::PathBuf;
+
+/// The method library which is loaded if `--method-lib` isn't given
+const DEFAULT_METHOD_LIB_PATH: &str = "CCCBR_methods.xml";
+
+const USAGE: &str = "Usage: jigsaw [--method-lib <path>] [<composition file>]";
+
 // When compiling natively:
 fn main() {
-    let app = jigsaw::JigsawApp::example();
+    // Parse the command line arguments
+    let mut comp_path: Option<PathBuf> = None;
+    let mut method_lib_path = PathBuf::from(DEFAULT_METHOD_LIB_PATH);
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--method-lib" {
+            match args.next() {
+                Some(path) => method_lib_path = path.into(),
+                None => exit_with_usage(),
+            }
+        } else if arg == "--help" || arg.to_string_lossy().starts_with("--") || comp_path.is_some()
+        {
+            exit_with_usage();
+        } else {
+            comp_path = Some(arg.into());
+        }
+    }
+
+    // If a path is given on the command line, then load (and save) the composition from that file
+    let mut app = match comp_path {
+        Some(path) => match jigsaw::JigsawApp::open(path) {
+            Ok(app) => app,
+            Err(e) => {
+                eprintln!("Error loading composition: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => jigsaw::JigsawApp::example(),
+    };
+    app.load_method_lib(&method_lib_path);
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(Box::new(app), native_options);
 }
+
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(1);
+}
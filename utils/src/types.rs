@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 use crate::indexed_vec::{FragIdx, PartIdx, RowIdx};
 
 // Imports used for doc comments
@@ -15,7 +17,7 @@ pub struct RowSource {
 
 /// The position of a [`Row`] within the expanded/`full` composition - i.e. the same as
 /// [`RowSource`], but also specifying the part.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct RowLocation {
     pub frag_index: FragIdx,
     pub row_index: RowIdx,
@@ -30,3 +32,32 @@ impl RowLocation {
         }
     }
 }
+
+/// The stroke at which a [`Row`] is rung.  Rows alternate between handstroke and backstroke.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stroke {
+    /// Fragments which aren't linked onto anything start at handstroke
+    #[default]
+    Hand,
+    Back,
+}
+
+impl Stroke {
+    /// The other stroke to `self`
+    pub fn opposite(self) -> Self {
+        match self {
+            Stroke::Hand => Stroke::Back,
+            Stroke::Back => Stroke::Hand,
+        }
+    }
+
+    /// The stroke of the [`Row`] `offset` rows after a [`Row`] rung at `self`
+    pub fn offset(self, offset: usize) -> Self {
+        if offset.is_multiple_of(2) {
+            self
+        } else {
+            self.opposite()
+        }
+    }
+}